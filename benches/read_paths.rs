@@ -0,0 +1,44 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use tempfile::TempDir;
+
+use kvs::storage::bitcask::bitcask_engine::Bitcask;
+
+const KEY_COUNT: usize = 1000;
+const VALUE_SIZE: usize = 256;
+
+fn fill(store_engine: &Bitcask) {
+    for i in 0..KEY_COUNT {
+        let key = format!("key{}", i).into_bytes();
+        store_engine.set(key, vec![b'v'; VALUE_SIZE]).unwrap();
+    }
+}
+
+fn get_all(store_engine: &Bitcask) {
+    for i in 0..KEY_COUNT {
+        let key = format!("key{}", i).into_bytes();
+        store_engine.get(key).unwrap();
+    }
+}
+
+fn read_paths(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+
+    let temp_dir = TempDir::new().unwrap();
+    let active_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+    fill(&active_engine);
+    group.bench_function("active_file", |b| b.iter(|| get_all(&active_engine)));
+
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+        fill(&store_engine);
+    }
+    // Reopening seals every generation written above.
+    let sealed_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+    group.bench_function("sealed_mmap", |b| b.iter(|| get_all(&sealed_engine)));
+
+    group.finish();
+}
+
+criterion_group!(benches, read_paths);
+criterion_main!(benches);
@@ -1,9 +1,28 @@
+#![allow(clippy::needless_return)]
+
 use kvs::constants as Constants;
 use kvs::error::KVResult;
+use kvs::metrics_server::serve_metrics;
+use kvs::storage::bitcask::backup::{restore, restore_chain};
 use kvs::storage::bitcask::bitcask_engine::Bitcask;
+use kvs::storage::bitcask::changes::{KeyFilter, LogTailer};
+use kvs::storage::bitcask::dump::{dump, DumpFilter, DumpedRecord};
+use kvs::storage::bitcask::export::{
+    export, export_namespace, import, import_namespace, BinaryEncoding, ExportFormat,
+};
+use kvs::storage::bitcask::repair::{repair, RepairReport};
+use kvs::storage::bitcask::stats::BitcaskStats;
+use kvs::storage::bitcask::verify::{verify, VerifyReport};
 
-use clap::{App, Arg, SubCommand};
+use clap::{value_t, App, Arg, SubCommand};
+use std::env::var_os;
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, BufReader, Error};
+use std::net::TcpListener;
 use std::path::PathBuf;
+use std::process::exit;
+use std::thread::{park, sleep};
+use std::time::Duration;
 
 fn main() -> KVResult<()> {
     let arg_matches = App::new(env!("CARGO_PKG_NAME"))
@@ -42,6 +61,177 @@ fn main() -> KVResult<()> {
                         .required(true),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_STATS)
+                .about(Constants::SUBCOMMAND_STATS_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_JSON)
+                        .long(Constants::ARGUMENT_NAME_FOR_JSON)
+                        .help(Constants::JSON_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_SERVE)
+                .about(Constants::SUBCOMMAND_SERVE_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_METRICS_ADDR)
+                        .long(Constants::ARGUMENT_NAME_FOR_METRICS_ADDR)
+                        .takes_value(true)
+                        .help(Constants::METRICS_ADDR_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_WATCH)
+                .about(Constants::SUBCOMMAND_WATCH_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_KEY)
+                        .help(Constants::GENERAL_ARGUMENT_HELP_INFORMATION)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_PREFIX)
+                        .long(Constants::ARGUMENT_NAME_FOR_PREFIX)
+                        .help(Constants::PREFIX_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_BACKUP)
+                .about(Constants::SUBCOMMAND_BACKUP_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_DEST)
+                        .help(Constants::DEST_ARGUMENT_HELP_INFORMATION)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_ARCHIVE)
+                        .long(Constants::ARGUMENT_NAME_FOR_ARCHIVE)
+                        .conflicts_with(Constants::ARGUMENT_NAME_FOR_INCREMENTAL_FROM)
+                        .help(Constants::ARCHIVE_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_INCREMENTAL_FROM)
+                        .long(Constants::ARGUMENT_NAME_FOR_INCREMENTAL_FROM)
+                        .takes_value(true)
+                        .help(Constants::INCREMENTAL_FROM_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_RESTORE)
+                .about(Constants::SUBCOMMAND_RESTORE_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_SOURCE)
+                        .help(Constants::SOURCE_ARGUMENT_HELP_INFORMATION)
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_EXPORT)
+                .about(Constants::SUBCOMMAND_EXPORT_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_FORMAT)
+                        .long(Constants::ARGUMENT_NAME_FOR_FORMAT)
+                        .possible_values(&Constants::EXPORT_FORMATS)
+                        .default_value(Constants::DEFAULT_EXPORT_FORMAT)
+                        .help(Constants::FORMAT_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_ENCODING)
+                        .long(Constants::ARGUMENT_NAME_FOR_ENCODING)
+                        .possible_values(&Constants::BINARY_ENCODINGS)
+                        .default_value(Constants::DEFAULT_BINARY_ENCODING)
+                        .help(Constants::ENCODING_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_NAMESPACE)
+                        .long(Constants::ARGUMENT_NAME_FOR_NAMESPACE)
+                        .takes_value(true)
+                        .help(Constants::NAMESPACE_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_IMPORT)
+                .about(Constants::SUBCOMMAND_IMPORT_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_FORMAT)
+                        .long(Constants::ARGUMENT_NAME_FOR_FORMAT)
+                        .possible_values(&Constants::EXPORT_FORMATS)
+                        .default_value(Constants::DEFAULT_EXPORT_FORMAT)
+                        .help(Constants::FORMAT_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_NAMESPACE)
+                        .long(Constants::ARGUMENT_NAME_FOR_NAMESPACE)
+                        .takes_value(true)
+                        .help(Constants::NAMESPACE_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_FILE)
+                        .help(Constants::FILE_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_VERIFY)
+                .about(Constants::SUBCOMMAND_VERIFY_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_DIR)
+                        .help(Constants::DIR_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_JSON)
+                        .long(Constants::ARGUMENT_NAME_FOR_JSON)
+                        .help(Constants::JSON_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_REPAIR)
+                .about(Constants::SUBCOMMAND_REPAIR_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_DIR)
+                        .help(Constants::DIR_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_JSON)
+                        .long(Constants::ARGUMENT_NAME_FOR_JSON)
+                        .help(Constants::JSON_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name(Constants::SUBCOMMAND_DUMP)
+                .about(Constants::SUBCOMMAND_DUMP_DESCRIPTION)
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_GEN)
+                        .help(Constants::GEN_ARGUMENT_HELP_INFORMATION)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_DIR)
+                        .help(Constants::DIR_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_KEY_FILTER)
+                        .long(Constants::ARGUMENT_NAME_FOR_KEY_FILTER)
+                        .takes_value(true)
+                        .help(Constants::KEY_FILTER_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_FROM)
+                        .long(Constants::ARGUMENT_NAME_FOR_FROM)
+                        .takes_value(true)
+                        .help(Constants::FROM_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_TO)
+                        .long(Constants::ARGUMENT_NAME_FOR_TO)
+                        .takes_value(true)
+                        .help(Constants::TO_ARGUMENT_HELP_INFORMATION),
+                )
+                .arg(
+                    Arg::with_name(Constants::ARGUMENT_NAME_FOR_JSON)
+                        .long(Constants::ARGUMENT_NAME_FOR_JSON)
+                        .help(Constants::JSON_LINES_ARGUMENT_HELP_INFORMATION),
+                ),
+        )
         .get_matches();
 
     match arg_matches.subcommand() {
@@ -53,8 +243,8 @@ fn main() -> KVResult<()> {
                 .value_of(Constants::ARGUMENT_NAME_FOR_VALUE)
                 .expect(Constants::MISSING_VALUE_ARGUMENT_MESSAGE);
 
-            let path = PathBuf::from(Constants::TEMP_LOG_FILE_PATH);
-            let mut store_engine = Bitcask::open(&path)?;
+            let path = store_path();
+            let store_engine = Bitcask::open(&path)?;
 
             store_engine.set(key.as_bytes().to_vec(), value.as_bytes().to_vec())
         }
@@ -63,8 +253,8 @@ fn main() -> KVResult<()> {
                 .value_of(Constants::ARGUMENT_NAME_FOR_KEY)
                 .expect(Constants::MISSING_KEY_ARGUMENT_MESSAGE);
 
-            let path = PathBuf::from(Constants::TEMP_LOG_FILE_PATH);
-            let mut store_engine = Bitcask::open(&path)?;
+            let path = store_path();
+            let store_engine = Bitcask::open(&path)?;
 
             match store_engine.get(key.as_bytes().to_vec())? {
                 Some(res) => {
@@ -82,11 +272,386 @@ fn main() -> KVResult<()> {
                 .value_of(Constants::ARGUMENT_NAME_FOR_KEY)
                 .expect(Constants::MISSING_KEY_ARGUMENT_MESSAGE);
 
-            let path = PathBuf::from(Constants::TEMP_LOG_FILE_PATH);
-            let mut store_engine = Bitcask::open(&path)?;
+            let path = store_path();
+            let store_engine = Bitcask::open(&path)?;
 
             store_engine.remove(key.as_bytes().to_vec())
         }
+        (Constants::SUBCOMMAND_STATS, Some(arg_matches)) => {
+            let path = store_path();
+            let store_engine = Bitcask::open(&path)?;
+
+            let stats = store_engine.stats();
+            if arg_matches.is_present(Constants::ARGUMENT_NAME_FOR_JSON) {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&stats).map_err(Error::from)?
+                );
+            } else {
+                print_stats_table(&stats);
+            }
+
+            return Ok(());
+        }
+        (Constants::SUBCOMMAND_SERVE, Some(arg_matches)) => {
+            let path = store_path();
+            let store_engine = Bitcask::open(&path)?;
+
+            match arg_matches.value_of(Constants::ARGUMENT_NAME_FOR_METRICS_ADDR) {
+                Some(addr) => serve_metrics(TcpListener::bind(addr)?, store_engine),
+                None => loop {
+                    park();
+                },
+            }
+        }
+        (Constants::SUBCOMMAND_WATCH, Some(arg_matches)) => {
+            let key = arg_matches
+                .value_of(Constants::ARGUMENT_NAME_FOR_KEY)
+                .expect(Constants::MISSING_KEY_ARGUMENT_MESSAGE);
+            let filter = if arg_matches.is_present(Constants::ARGUMENT_NAME_FOR_PREFIX) {
+                KeyFilter::Prefix(key.as_bytes().to_vec())
+            } else {
+                KeyFilter::Key(key.as_bytes().to_vec())
+            };
+
+            // Writers may live in other processes, so follow the log files instead of
+            // opening the store.
+            let path = store_path();
+            let mut tailer = LogTailer::new(&path);
+            tailer.read_new(|_| {})?;
+
+            loop {
+                tailer.read_new(|event| {
+                    if !filter.matches(&event.key) {
+                        return;
+                    }
+                    match event.value {
+                        Some(value) => println!("{}", String::from_utf8_lossy(&value)),
+                        None => println!("{}", Constants::MISSING_KEY_MESSAGE),
+                    }
+                })?;
+                sleep(Duration::from_millis(Constants::WATCH_POLL_INTERVAL_MILLIS));
+            }
+        }
+        (Constants::SUBCOMMAND_BACKUP, Some(arg_matches)) => {
+            let dest = arg_matches
+                .value_of(Constants::ARGUMENT_NAME_FOR_DEST)
+                .expect(Constants::MISSING_DEST_ARGUMENT_MESSAGE);
+
+            let path = store_path();
+            let store_engine = Bitcask::open(&path)?;
+
+            let incremental_from =
+                arg_matches.value_of(Constants::ARGUMENT_NAME_FOR_INCREMENTAL_FROM);
+            if arg_matches.is_present(Constants::ARGUMENT_NAME_FOR_ARCHIVE) {
+                store_engine.backup_archive(&PathBuf::from(dest))
+            } else if let Some(base) = incremental_from {
+                store_engine.backup_incremental(&PathBuf::from(dest), &PathBuf::from(base))
+            } else {
+                store_engine.backup(&PathBuf::from(dest))
+            }
+        }
+        (Constants::SUBCOMMAND_RESTORE, Some(arg_matches)) => {
+            let sources: Vec<PathBuf> = arg_matches
+                .values_of(Constants::ARGUMENT_NAME_FOR_SOURCE)
+                .expect(Constants::MISSING_SOURCE_ARGUMENT_MESSAGE)
+                .map(PathBuf::from)
+                .collect();
+
+            let path = store_path();
+            if sources.len() == 1 {
+                restore(&sources[0], &path)
+            } else {
+                restore_chain(&sources, &path)
+            }
+        }
+        (Constants::SUBCOMMAND_EXPORT, Some(arg_matches)) => {
+            let format: ExportFormat = arg_matches
+                .value_of(Constants::ARGUMENT_NAME_FOR_FORMAT)
+                .unwrap()
+                .parse()
+                .unwrap();
+            let encoding: BinaryEncoding = arg_matches
+                .value_of(Constants::ARGUMENT_NAME_FOR_ENCODING)
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            let path = store_path();
+            let store_engine = Bitcask::open(&path)?;
+
+            match arg_matches.value_of(Constants::ARGUMENT_NAME_FOR_NAMESPACE) {
+                Some(name) => {
+                    let namespace = store_engine.namespace(name)?;
+                    export_namespace(&namespace, stdout().lock(), format, encoding)?;
+                }
+                None => {
+                    let others = &store_engine.list_namespaces()[1..];
+                    if !others.is_empty() {
+                        eprintln!(
+                            "only the default namespace is exported, not {}",
+                            others.join(", ")
+                        );
+                    }
+                    export(&store_engine, stdout().lock(), format, encoding)?;
+                }
+            }
+
+            return Ok(());
+        }
+        (Constants::SUBCOMMAND_IMPORT, Some(arg_matches)) => {
+            let format: ExportFormat = arg_matches
+                .value_of(Constants::ARGUMENT_NAME_FOR_FORMAT)
+                .unwrap()
+                .parse()
+                .unwrap();
+
+            let path = store_path();
+            let store_engine = Bitcask::open(&path)?;
+
+            let reader: Box<dyn BufRead> =
+                match arg_matches.value_of(Constants::ARGUMENT_NAME_FOR_FILE) {
+                    Some(file) => Box::new(BufReader::new(File::open(file)?)),
+                    None => Box::new(stdin().lock()),
+                };
+            let count = match arg_matches.value_of(Constants::ARGUMENT_NAME_FOR_NAMESPACE) {
+                Some(name) => import_namespace(&store_engine.namespace(name)?, reader, format)?,
+                None => import(&store_engine, reader, format)?,
+            };
+            eprintln!("imported {} pairs", count);
+
+            return Ok(());
+        }
+        (Constants::SUBCOMMAND_VERIFY, Some(arg_matches)) => {
+            let path = arg_matches
+                .value_of(Constants::ARGUMENT_NAME_FOR_DIR)
+                .map_or_else(store_path, PathBuf::from);
+
+            let report = verify(&path)?;
+            if arg_matches.is_present(Constants::ARGUMENT_NAME_FOR_JSON) {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).map_err(Error::from)?
+                );
+            } else {
+                print_verify_report(&report);
+            }
+
+            if !report.is_healthy() {
+                exit(1);
+            }
+            return Ok(());
+        }
+        (Constants::SUBCOMMAND_REPAIR, Some(arg_matches)) => {
+            let path = arg_matches
+                .value_of(Constants::ARGUMENT_NAME_FOR_DIR)
+                .map_or_else(store_path, PathBuf::from);
+
+            let report = repair(&path)?;
+            if arg_matches.is_present(Constants::ARGUMENT_NAME_FOR_JSON) {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).map_err(Error::from)?
+                );
+            } else {
+                print_repair_report(&report);
+            }
+
+            return Ok(());
+        }
+        (Constants::SUBCOMMAND_DUMP, Some(arg_matches)) => {
+            let gen = value_t!(arg_matches, Constants::ARGUMENT_NAME_FOR_GEN, u64)
+                .unwrap_or_else(|err| err.exit());
+            let path = arg_matches
+                .value_of(Constants::ARGUMENT_NAME_FOR_DIR)
+                .map_or_else(store_path, PathBuf::from);
+
+            let mut filter = DumpFilter {
+                key: arg_matches
+                    .value_of(Constants::ARGUMENT_NAME_FOR_KEY_FILTER)
+                    .map(|key| key.as_bytes().to_vec()),
+                offsets: None,
+            };
+            let has_from = arg_matches.is_present(Constants::ARGUMENT_NAME_FOR_FROM);
+            let has_to = arg_matches.is_present(Constants::ARGUMENT_NAME_FOR_TO);
+            if has_from || has_to {
+                let mut from = 0;
+                let mut to = u64::MAX;
+                if has_from {
+                    from = value_t!(arg_matches, Constants::ARGUMENT_NAME_FOR_FROM, u64)
+                        .unwrap_or_else(|err| err.exit());
+                }
+                if has_to {
+                    to = value_t!(arg_matches, Constants::ARGUMENT_NAME_FOR_TO, u64)
+                        .unwrap_or_else(|err| err.exit());
+                }
+                filter.offsets = Some(from..to);
+            }
+
+            let records = dump(&path, gen, &filter)?;
+            for record in &records {
+                if arg_matches.is_present(Constants::ARGUMENT_NAME_FOR_JSON) {
+                    println!("{}", serde_json::to_string(record).unwrap());
+                } else {
+                    print_dumped_record(record);
+                }
+            }
+
+            return Ok(());
+        }
         _ => unreachable!(),
     }
 }
+
+/// The store in use, in the directory named by the `KVS_DIR` environment variable if set.
+fn store_path() -> PathBuf {
+    return var_os(Constants::STORE_DIR_ENV_VAR).map_or_else(
+        || PathBuf::from(Constants::TEMP_LOG_FILE_PATH),
+        PathBuf::from,
+    );
+}
+
+fn print_stats_table(stats: &BitcaskStats) {
+    let rows = [
+        ("keys", stats.key_count),
+        ("generations", stats.generations.len() as u64),
+        ("uncompacted bytes", stats.uncompacted),
+        ("index memory bytes", stats.index_memory_bytes),
+        (
+            "index bytes per key",
+            stats
+                .index_memory_bytes
+                .checked_div(stats.key_count)
+                .unwrap_or(0),
+        ),
+        ("gets", stats.gets),
+        ("sets", stats.sets),
+        ("removes", stats.removes),
+        ("cache hits", stats.cache_hits),
+        ("cache misses", stats.cache_misses),
+        ("cache bytes", stats.cache_size),
+        ("write batches", stats.write_batches),
+        ("compactions", stats.compactions),
+    ];
+    for (name, value) in rows.iter() {
+        println!("{:<20}{:>16}", name, value);
+    }
+
+    if stats.namespaces.len() > 1 {
+        println!();
+        println!(
+            "{:<20}{:>12}{:>16}{:>16}",
+            "namespace", "keys", "live bytes", "index bytes"
+        );
+        for namespace in &stats.namespaces {
+            println!(
+                "{:<20}{:>12}{:>16}{:>16}",
+                namespace.name,
+                namespace.key_count,
+                namespace.live_bytes,
+                namespace.index_memory_bytes
+            );
+        }
+    }
+
+    println!();
+    println!(
+        "{:>8}{:>16}{:>16}{:>16}{:>16}{:>12}",
+        "gen", "file bytes", "live bytes", "dead bytes", "tombstone bytes", "live keys"
+    );
+    for generation in &stats.generations {
+        println!(
+            "{:>8}{:>16}{:>16}{:>16}{:>16}{:>12}",
+            generation.gen,
+            generation.file_size,
+            generation.live_bytes,
+            generation.dead_bytes,
+            generation.tombstone_bytes,
+            generation.live_keys
+        );
+    }
+}
+
+fn print_verify_report(report: &VerifyReport) {
+    for range in &report.corrupt_ranges {
+        println!(
+            "corrupt gen={} offset={} len={} defect={}",
+            range.gen, range.offset, range.len, range.defect
+        );
+    }
+
+    let rows = [
+        ("generations", report.generations as u64),
+        ("records", report.records),
+        ("live records", report.live_records),
+        ("dead records", report.dead_records),
+        ("corrupt ranges", report.corrupt_ranges.len() as u64),
+    ];
+    for (name, value) in rows.iter() {
+        println!("{:<20}{:>16}", name, value);
+    }
+}
+
+fn print_repair_report(report: &RepairReport) {
+    for range in report
+        .generations
+        .iter()
+        .flat_map(|generation| &generation.lost_ranges)
+    {
+        println!(
+            "lost gen={} offset={} len={} defect={}",
+            range.gen, range.offset, range.len, range.defect
+        );
+    }
+    for generation in &report.generations {
+        for key in &generation.lost_keys {
+            println!("lost gen={} key={}", generation.gen, key);
+        }
+    }
+
+    let salvaged_records: u64 = report
+        .generations
+        .iter()
+        .map(|generation| generation.salvaged_records)
+        .sum();
+    let rows = [
+        ("repaired generations", report.generations.len() as u64),
+        ("salvaged records", salvaged_records),
+        ("lost bytes", report.lost_bytes()),
+    ];
+    for (name, value) in rows.iter() {
+        println!("{:<20}{:>16}", name, value);
+    }
+    if let Some(quarantine_dir) = &report.quarantine_dir {
+        println!("originals kept in {}", quarantine_dir.display());
+    }
+}
+
+fn print_dumped_record(record: &DumpedRecord) {
+    let mut line = format!(
+        "offset={} len={} type={}",
+        record.offset, record.len, record.record_type
+    );
+    if let Some(seq) = record.seq {
+        line += &format!(" seq={}", seq);
+    }
+    if let Some(namespace) = record.namespace {
+        line += &format!(" namespace={}", namespace);
+    }
+    if let Some(key) = &record.key {
+        line += &format!(" key=\"{}\"", key);
+    }
+    if let (Some(value_len), Some(value_preview)) = (record.value_len, &record.value_preview) {
+        line += &format!(" value_len={} value=\"{}\"", value_len, value_preview);
+    }
+    if let (Some(value_len), Some(blob)) = (record.value_len, &record.blob) {
+        line += &format!(" value_len={} blob={}", value_len, blob);
+    }
+    if let Some(batch_len) = record.batch_len {
+        line += &format!(" records={}", batch_len);
+    }
+    if let Some(defect) = &record.defect {
+        line += &format!(" defect={}", defect);
+    }
+    println!("{}", line);
+}
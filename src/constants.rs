@@ -3,10 +3,33 @@ pub const COMMAND_VERSION_FLAGS: &str = "-V";
 pub const SUBCOMMAND_GET: &str = "get";
 pub const SUBCOMMAND_SET: &str = "set";
 pub const SUBCOMMAND_REMOVE: &str = "rm";
+pub const SUBCOMMAND_STATS: &str = "stats";
+pub const SUBCOMMAND_SERVE: &str = "serve";
+pub const SUBCOMMAND_WATCH: &str = "watch";
+pub const SUBCOMMAND_BACKUP: &str = "backup";
+pub const SUBCOMMAND_RESTORE: &str = "restore";
+pub const SUBCOMMAND_EXPORT: &str = "export";
+pub const SUBCOMMAND_IMPORT: &str = "import";
+pub const SUBCOMMAND_VERIFY: &str = "verify";
+pub const SUBCOMMAND_REPAIR: &str = "repair";
+pub const SUBCOMMAND_DUMP: &str = "dump";
 
 pub const SUBCOMMAND_SET_DESCRIPTION: &str = "Set the string key value pair.";
 pub const SUBCOMMAND_GET_DESCRIPTION: &str = "Get the string value of a given string key.";
 pub const SUBCOMMAND_REMOVE_DESCRIPTION: &str = "Remove the given string key value pair.";
+pub const SUBCOMMAND_STATS_DESCRIPTION: &str = "Print engine statistics.";
+pub const SUBCOMMAND_SERVE_DESCRIPTION: &str = "Keep the store open, optionally serving metrics.";
+pub const SUBCOMMAND_WATCH_DESCRIPTION: &str = "Print every new value of a key as it is written.";
+pub const SUBCOMMAND_BACKUP_DESCRIPTION: &str = "Copy a consistent backup of the store to DEST.";
+pub const SUBCOMMAND_EXPORT_DESCRIPTION: &str = "Write every key value pair to standard output.";
+pub const SUBCOMMAND_IMPORT_DESCRIPTION: &str = "Set every key value pair of an export.";
+pub const SUBCOMMAND_VERIFY_DESCRIPTION: &str =
+    "Check every record of a data directory without changing it, fail on corruption.";
+pub const SUBCOMMAND_REPAIR_DESCRIPTION: &str =
+    "Rewrite corrupt generation files of a closed store with every record left intact.";
+pub const SUBCOMMAND_DUMP_DESCRIPTION: &str = "Print every record of a generation file.";
+pub const SUBCOMMAND_RESTORE_DESCRIPTION: &str =
+    "Restore a backup, or a full backup and its incremental backups, into an empty store.";
 
 pub const GENERAL_ARGUMENT_HELP_INFORMATION: &str = "A string key";
 
@@ -14,6 +37,66 @@ pub const ARGUMENT_NAME_FOR_KEY: &str = "KEY";
 pub const MISSING_KEY_ARGUMENT_MESSAGE: &str = "KEY argument missing";
 pub const ARGUMENT_NAME_FOR_VALUE: &str = "VALUE";
 pub const MISSING_VALUE_ARGUMENT_MESSAGE: &str = "VALUE argument missing";
+pub const MISSING_DEST_ARGUMENT_MESSAGE: &str = "DEST argument missing";
+pub const MISSING_SOURCE_ARGUMENT_MESSAGE: &str = "SOURCE argument missing";
 pub const MISSING_KEY_MESSAGE: &str = "Key not existed";
+pub const ARGUMENT_NAME_FOR_JSON: &str = "json";
+pub const JSON_ARGUMENT_HELP_INFORMATION: &str = "Print as JSON instead of a table";
+pub const JSON_LINES_ARGUMENT_HELP_INFORMATION: &str = "Print one JSON object per line";
+pub const ARGUMENT_NAME_FOR_PREFIX: &str = "prefix";
+pub const PREFIX_ARGUMENT_HELP_INFORMATION: &str = "Watch every key starting with KEY";
+pub const ARGUMENT_NAME_FOR_DEST: &str = "DEST";
+pub const DEST_ARGUMENT_HELP_INFORMATION: &str = "Backup directory, or archive file with --archive";
+pub const ARGUMENT_NAME_FOR_SOURCE: &str = "SOURCE";
+pub const SOURCE_ARGUMENT_HELP_INFORMATION: &str =
+    "Backup directory or archive file, incremental backups follow their base";
+pub const ARGUMENT_NAME_FOR_INCREMENTAL_FROM: &str = "incremental-from";
+pub const INCREMENTAL_FROM_ARGUMENT_HELP_INFORMATION: &str =
+    "Only copy what changed since the backup directory given";
+pub const ARGUMENT_NAME_FOR_ARCHIVE: &str = "archive";
+pub const ARCHIVE_ARGUMENT_HELP_INFORMATION: &str =
+    "Write a single archive file instead of a directory";
+pub const ARGUMENT_NAME_FOR_FORMAT: &str = "format";
+pub const FORMAT_ARGUMENT_HELP_INFORMATION: &str = "Data format";
+pub const ARGUMENT_NAME_FOR_ENCODING: &str = "encoding";
+pub const ENCODING_ARGUMENT_HELP_INFORMATION: &str =
+    "Encoding of keys and values that are not UTF-8";
+pub const ARGUMENT_NAME_FOR_NAMESPACE: &str = "namespace";
+pub const NAMESPACE_ARGUMENT_HELP_INFORMATION: &str =
+    "Namespace whose keys to read or write, the default one when missing";
+pub const ARGUMENT_NAME_FOR_FILE: &str = "FILE";
+pub const FILE_ARGUMENT_HELP_INFORMATION: &str = "File to import, standard input when missing";
+pub const ARGUMENT_NAME_FOR_DIR: &str = "DIR";
+pub const DIR_ARGUMENT_HELP_INFORMATION: &str = "Data directory, the store in use when missing";
+pub const ARGUMENT_NAME_FOR_GEN: &str = "GEN";
+pub const GEN_ARGUMENT_HELP_INFORMATION: &str = "Generation number of the log file";
+pub const ARGUMENT_NAME_FOR_KEY_FILTER: &str = "key";
+pub const KEY_FILTER_ARGUMENT_HELP_INFORMATION: &str = "Only print records of this key";
+pub const ARGUMENT_NAME_FOR_FROM: &str = "from";
+pub const FROM_ARGUMENT_HELP_INFORMATION: &str =
+    "Only print records starting at or after this offset";
+pub const ARGUMENT_NAME_FOR_TO: &str = "to";
+pub const TO_ARGUMENT_HELP_INFORMATION: &str = "Only print records starting before this offset";
+pub const ARGUMENT_NAME_FOR_METRICS_ADDR: &str = "metrics-addr";
+pub const METRICS_ADDR_ARGUMENT_HELP_INFORMATION: &str =
+    "Address to serve Prometheus metrics on, e.g. 127.0.0.1:9100";
 
 pub const TEMP_LOG_FILE_PATH: &str = "/tmp";
+pub const STORE_DIR_ENV_VAR: &str = "KVS_DIR";
+
+pub const MAX_ACTIVE_LOG_FILE_SIZE: u64 = 1024 * 1024;
+pub const MAX_BLOB_FILE_SIZE: u64 = 64 * 1024 * 1024;
+/// Share of a sealed blob file still in use below which merges move its values out.
+pub const BLOB_FILE_REWRITE_RATIO: f64 = 0.5;
+pub const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+pub const SLOW_OPERATION_THRESHOLD_MILLIS: u64 = 500;
+pub const WATCH_POLL_INTERVAL_MILLIS: u64 = 100;
+pub const STREAM_CHUNK_LEN: usize = 64 * 1024;
+/// Merge operands a key piles up before a merge is started to fold them, so reads of the key
+/// fold at most about this many.
+pub const MAX_MERGE_CHAIN_LEN: usize = 256;
+
+pub const EXPORT_FORMATS: [&str; 2] = ["jsonl", "csv"];
+pub const DEFAULT_EXPORT_FORMAT: &str = "jsonl";
+pub const BINARY_ENCODINGS: [&str; 2] = ["base64", "hex"];
+pub const DEFAULT_BINARY_ENCODING: &str = "base64";
@@ -4,6 +4,7 @@ use std::io::Error;
 pub enum KVError {
     IOError(Error),
     KeyNoneExisted,
+    CompactionFailed(String),
 }
 
 impl From<Error> for KVError {
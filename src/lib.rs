@@ -1,4 +1,5 @@
 pub mod constants;
 pub mod error;
+pub mod metrics_server;
 pub mod storage;
 pub mod utils;
@@ -0,0 +1,87 @@
+#![allow(clippy::needless_return)]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::error::KVResult;
+use crate::storage::bitcask::bitcask_engine::Bitcask;
+
+const METRICS_PATH: &str = "/metrics";
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Serves `GET /metrics` in the Prometheus text exposition format, one connection at a time.
+/// Blocks for as long as the listener accepts connections, a broken connection is dropped
+/// without stopping the server.
+pub fn serve_metrics(listener: TcpListener, store_engine: Bitcask) -> KVResult<()> {
+    for stream in listener.incoming().flatten() {
+        let _ = handle_connection(stream, &store_engine);
+    }
+
+    return Ok(());
+}
+
+fn handle_connection(stream: TcpStream, store_engine: &Bitcask) -> KVResult<()> {
+    let mut reader = BufReader::new(&stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Drain the headers, the request body is never needed.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim_end() != "" {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(METRICS_PATH)) => (
+            "200 OK",
+            METRICS_CONTENT_TYPE,
+            store_engine.render_metrics(),
+        ),
+        _ => ("404 Not Found", "text/plain", String::from("not found\n")),
+    };
+
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    return Ok(());
+}
+
+#[test]
+fn metrics_server_serve_metrics() {
+    use std::io::Read;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+    store_engine
+        .set(b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_engine = store_engine.clone();
+    std::thread::spawn(move || serve_metrics(listener, server_engine));
+
+    let get = |path: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let response = get(METRICS_PATH);
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+    assert!(response.contains("kvs_operation_duration_seconds_count{op=\"set\"} 1\n"));
+
+    assert!(get("/other").starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
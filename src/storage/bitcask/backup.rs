@@ -0,0 +1,422 @@
+#![allow(clippy::needless_return)]
+
+use std::fs::{copy, create_dir_all, hard_link, read, write, File, OpenOptions};
+use std::io::{copy as copy_stream, BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::KVResult;
+use crate::storage::bitcask::bitcask_engine::{
+    get_log_file_dir, get_sorted_gen_list, record_positions,
+};
+use crate::storage::bitcask::blob::{get_blob_file_dir, get_sorted_blob_files, BLOB_DIR_NAME};
+use crate::storage::bitcask::namespace::NAMESPACES_FILE_NAME;
+use crate::utils::{u64_to_u8_array, u8_array_to_u64};
+
+/// First bytes of a backup archive, followed by `gen`, `len` and `len` bytes for every file.
+const ARCHIVE_MAGIC: &[u8; 8] = b"KVSBAK01";
+
+/// Stands in for `gen` in front of a blob file entry of an archive, which goes on with the
+/// blob file id, `len` and `len` bytes.
+const ARCHIVE_BLOB_MARKER: u64 = u64::MAX;
+
+/// Stands in for `gen` in front of the namespace list of an archive, which goes on with `len`
+/// and `len` bytes.
+const ARCHIVE_NAMESPACES_MARKER: u64 = u64::MAX - 1;
+
+const MANIFEST_FILE_NAME: &str = "MANIFEST.json";
+
+/// Describes one backup directory, written once every file of the backup is in place.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub id: u64,
+    /// `id` of the backup this one was taken on top of, `None` for a full backup.
+    pub base_id: Option<u64>,
+    /// Every generation of the store at backup time, in ascending order.
+    pub gens: Vec<u64>,
+    /// Generations whose files are in this backup directory.
+    pub copied_gens: Vec<u64>,
+    /// The last generation, only copied up to the last record written at backup time.
+    pub active_gen: u64,
+    /// Every blob file of the store at backup time, in ascending order.
+    #[serde(default)]
+    pub blob_files: Vec<u64>,
+    /// Blob files whose copies are in this backup directory.
+    #[serde(default)]
+    pub copied_blob_files: Vec<u64>,
+}
+
+impl BackupManifest {
+    /// Whether the backup chain ending here holds the whole file of generation `gen`.
+    fn has_sealed(&self, gen: u64) -> bool {
+        gen != self.active_gen && self.gens.contains(&gen)
+    }
+
+    /// Whether the backup chain ending here holds the whole blob file `file`, every blob file
+    /// but the last one is no longer written to.
+    fn has_sealed_blob_file(&self, file: u64) -> bool {
+        self.blob_files.last() != Some(&file) && self.blob_files.contains(&file)
+    }
+}
+
+/// Copies generations `gens` of the store in `path` into the directory `dest`. Every
+/// generation but the last is sealed and hard-linked when possible, the last one is still
+/// being appended to and is copied up to `active_end`.
+///
+/// On top of the backup in `base`, sealed generations and blob files already in that backup
+/// chain are skipped. The namespace list is part of every backup.
+pub(crate) fn copy_generations(
+    path: &PathBuf,
+    dest: &PathBuf,
+    gens: &[u64],
+    active_end: u64,
+    base: Option<&PathBuf>,
+) -> KVResult<()> {
+    let base = match base {
+        Some(base) => Some(read_manifest(base)?),
+        None => None,
+    };
+    create_dir_all(dest)?;
+    ensure_no_logs(dest)?;
+
+    let (active_gen, sealed_gens) = match gens.split_last() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+
+    let mut copied_gens = Vec::new();
+    for gen in sealed_gens {
+        if base.as_ref().is_some_and(|base| base.has_sealed(*gen)) {
+            continue;
+        }
+
+        let source = get_log_file_dir(*gen, path);
+        let target = get_log_file_dir(*gen, dest);
+        // Hard links can not cross file systems.
+        if hard_link(&source, &target).is_err() {
+            copy(&source, &target)?;
+        }
+        copied_gens.push(*gen);
+    }
+    write(
+        get_log_file_dir(*active_gen, dest),
+        read_active_tail(path, *active_gen, active_end)?,
+    )?;
+    copied_gens.push(*active_gen);
+
+    let blob_files = get_sorted_blob_files(path)?;
+    let copied_blob_files: Vec<u64> = blob_files
+        .iter()
+        .cloned()
+        .filter(|file| {
+            !base
+                .as_ref()
+                .is_some_and(|base| base.has_sealed_blob_file(*file))
+        })
+        .collect();
+    copy_blobs(path, dest, &copied_blob_files)?;
+    copy_namespaces(path, dest)?;
+
+    let manifest = BackupManifest {
+        id: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_nanos() as u64),
+        base_id: base.map(|base| base.id),
+        gens: gens.to_vec(),
+        copied_gens,
+        active_gen: *active_gen,
+        blob_files,
+        copied_blob_files,
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(Error::from)?;
+    write(dest.join(MANIFEST_FILE_NAME), manifest)?;
+
+    return Ok(());
+}
+
+/// Same as `copy_generations`, into the single archive file `dest`.
+pub(crate) fn write_archive(
+    path: &PathBuf,
+    dest: &PathBuf,
+    gens: &[u64],
+    active_end: u64,
+) -> KVResult<()> {
+    let file = OpenOptions::new().write(true).create_new(true).open(dest)?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(ARCHIVE_MAGIC)?;
+
+    let (active_gen, sealed_gens) = match gens.split_last() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+
+    for gen in sealed_gens {
+        let mut source = File::open(get_log_file_dir(*gen, path))?;
+        let len = source.metadata()?.len();
+        writer.write_all(&u64_to_u8_array(*gen))?;
+        writer.write_all(&u64_to_u8_array(len))?;
+        let copied = copy_stream(&mut (&mut source).take(len), &mut writer)?;
+        if copied != len {
+            return Err(
+                Error::new(ErrorKind::UnexpectedEof, "log file shrank during backup").into(),
+            );
+        }
+    }
+
+    let tail = read_active_tail(path, *active_gen, active_end)?;
+    writer.write_all(&u64_to_u8_array(*active_gen))?;
+    writer.write_all(&u64_to_u8_array(tail.len() as u64))?;
+    writer.write_all(&tail)?;
+
+    for file in get_sorted_blob_files(path)? {
+        let blob = read(get_blob_file_dir(file, path))?;
+        writer.write_all(&u64_to_u8_array(ARCHIVE_BLOB_MARKER))?;
+        writer.write_all(&u64_to_u8_array(file))?;
+        writer.write_all(&u64_to_u8_array(blob.len() as u64))?;
+        writer.write_all(&blob)?;
+    }
+
+    if let Some(namespaces) = read_namespaces(path)? {
+        writer.write_all(&u64_to_u8_array(ARCHIVE_NAMESPACES_MARKER))?;
+        writer.write_all(&u64_to_u8_array(namespaces.len() as u64))?;
+        writer.write_all(&namespaces)?;
+    }
+
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+
+    return Ok(());
+}
+
+/// Restores a backup directory or archive into `path`, which must not hold a store yet.
+pub fn restore(backup: &PathBuf, path: &PathBuf) -> KVResult<()> {
+    if backup.is_dir() {
+        return restore_chain(&[backup.to_owned()], path);
+    }
+
+    create_dir_all(path)?;
+    ensure_no_logs(path)?;
+
+    let mut reader = BufReader::new(File::open(backup)?);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != ARCHIVE_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "not a backup archive").into());
+    }
+
+    while let Some(gen) = read_u64(&mut reader)? {
+        let target = if gen == ARCHIVE_BLOB_MARKER {
+            let file = read_u64(&mut reader)?
+                .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "truncated backup archive"))?;
+            create_dir_all(path.join(BLOB_DIR_NAME))?;
+            get_blob_file_dir(file, path)
+        } else if gen == ARCHIVE_NAMESPACES_MARKER {
+            path.join(NAMESPACES_FILE_NAME)
+        } else {
+            get_log_file_dir(gen, path)
+        };
+        let len = read_u64(&mut reader)?
+            .ok_or_else(|| Error::new(ErrorKind::UnexpectedEof, "truncated backup archive"))?;
+        let mut target = File::create(target)?;
+        let copied = copy_stream(&mut (&mut reader).take(len), &mut target)?;
+        if copied != len {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated backup archive").into());
+        }
+        target.sync_all()?;
+    }
+    sync_dirs(path)?;
+
+    return Ok(());
+}
+
+/// Restores a full backup directory followed by the incremental backups taken on top of it,
+/// oldest first, into `path`. Only the generations of the last backup are restored, each one
+/// from the newest backup holding it.
+pub fn restore_chain(backups: &[PathBuf], path: &PathBuf) -> KVResult<()> {
+    let manifests = backups
+        .iter()
+        .map(read_manifest)
+        .collect::<KVResult<Vec<BackupManifest>>>()?;
+
+    let mut base_id = None;
+    for (manifest, backup) in manifests.iter().zip(backups) {
+        if manifest.base_id != base_id {
+            let message = format!("{} does not follow the previous backup", backup.display());
+            return Err(Error::new(ErrorKind::InvalidInput, message).into());
+        }
+        base_id = Some(manifest.id);
+    }
+    let last_manifest = match manifests.last() {
+        Some(manifest) => manifest,
+        None => return Ok(()),
+    };
+
+    create_dir_all(path)?;
+    ensure_no_logs(path)?;
+
+    for gen in &last_manifest.gens {
+        let backup = manifests
+            .iter()
+            .zip(backups)
+            .rev()
+            .find(|(manifest, _)| manifest.copied_gens.contains(gen))
+            .map(|(_, backup)| backup)
+            .ok_or_else(|| {
+                let message = format!("generation {} is missing from the backups", gen);
+                Error::new(ErrorKind::NotFound, message)
+            })?;
+        copy_synced(
+            &get_log_file_dir(*gen, backup),
+            &get_log_file_dir(*gen, path),
+        )?;
+    }
+    let mut blob_sources = Vec::new();
+    for file in &last_manifest.blob_files {
+        let backup = manifests
+            .iter()
+            .zip(backups)
+            .rev()
+            .find(|(manifest, _)| manifest.copied_blob_files.contains(file))
+            .map(|(_, backup)| backup)
+            .ok_or_else(|| {
+                let message = format!("blob file {} is missing from the backups", file);
+                Error::new(ErrorKind::NotFound, message)
+            })?;
+        blob_sources.push((*file, backup));
+    }
+    // Backups taken before blob files were listed in the manifest hold every blob file.
+    if last_manifest.blob_files.is_empty() {
+        let backup = backups.last().unwrap();
+        for file in get_sorted_blob_files(backup)? {
+            blob_sources.push((file, backup));
+        }
+    }
+    for (file, backup) in blob_sources {
+        create_dir_all(path.join(BLOB_DIR_NAME))?;
+        copy_synced(
+            &get_blob_file_dir(file, backup),
+            &get_blob_file_dir(file, path),
+        )?;
+    }
+    if let Some(backup) = backups.last() {
+        copy_namespaces(backup, path)?;
+    }
+    sync_dirs(path)?;
+
+    return Ok(());
+}
+
+#[allow(clippy::ptr_arg)]
+pub fn read_manifest(backup: &PathBuf) -> KVResult<BackupManifest> {
+    let manifest = read(backup.join(MANIFEST_FILE_NAME))?;
+    let manifest = serde_json::from_slice(&manifest).map_err(Error::from)?;
+
+    return Ok(manifest);
+}
+
+/// Content of generation `gen` up to `active_end`, or the end of its last whole record before.
+fn read_active_tail(path: &PathBuf, gen: u64, active_end: u64) -> KVResult<Vec<u8>> {
+    let mut buffer = Vec::new();
+    File::open(get_log_file_dir(gen, path))?
+        .take(active_end)
+        .read_to_end(&mut buffer)?;
+    let end = record_positions(&buffer)
+        .last()
+        .map_or(0, |(pos, len)| pos + len);
+    buffer.truncate(end);
+
+    return Ok(buffer);
+}
+
+/// Copies blob files `files` of `path` into `dest`. All but the last are no longer written to
+/// and hard-linked when possible.
+fn copy_blobs(path: &PathBuf, dest: &PathBuf, files: &[u64]) -> KVResult<()> {
+    let (last_file, sealed_files) = match files.split_last() {
+        Some(split) => split,
+        None => return Ok(()),
+    };
+
+    create_dir_all(dest.join(BLOB_DIR_NAME))?;
+    for file in sealed_files {
+        let source = get_blob_file_dir(*file, path);
+        let target = get_blob_file_dir(*file, dest);
+        if hard_link(&source, &target).is_err() {
+            copy(&source, &target)?;
+        }
+    }
+    copy(
+        get_blob_file_dir(*last_file, path),
+        get_blob_file_dir(*last_file, dest),
+    )?;
+
+    return Ok(());
+}
+
+/// Copies the namespace list of `path`, if any namespace was ever created, into `dest`.
+#[allow(clippy::ptr_arg)]
+fn copy_namespaces(path: &PathBuf, dest: &PathBuf) -> KVResult<()> {
+    if let Some(namespaces) = read_namespaces(path)? {
+        let mut file = File::create(dest.join(NAMESPACES_FILE_NAME))?;
+        file.write_all(&namespaces)?;
+        file.sync_all()?;
+    }
+
+    return Ok(());
+}
+
+/// Copies the file `source` to `target` and syncs the copy to disk.
+fn copy_synced(source: &PathBuf, target: &PathBuf) -> KVResult<()> {
+    copy(source, target)?;
+    File::open(target)?.sync_all()?;
+
+    return Ok(());
+}
+
+/// Syncs the directory `path` and its blob directory, so the files restored into them survive
+/// a crash.
+fn sync_dirs(path: &PathBuf) -> KVResult<()> {
+    let blob_dir = path.join(BLOB_DIR_NAME);
+    if blob_dir.is_dir() {
+        File::open(blob_dir)?.sync_all()?;
+    }
+    File::open(path)?.sync_all()?;
+
+    return Ok(());
+}
+
+#[allow(clippy::ptr_arg)]
+fn read_namespaces(path: &PathBuf) -> KVResult<Option<Vec<u8>>> {
+    match read(path.join(NAMESPACES_FILE_NAME)) {
+        Ok(namespaces) => return Ok(Some(namespaces)),
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    }
+}
+
+fn ensure_no_logs(path: &PathBuf) -> KVResult<()> {
+    if !get_sorted_gen_list(path)?.is_empty() {
+        let message = format!("{} already holds log files", path.display());
+        return Err(Error::new(ErrorKind::AlreadyExists, message).into());
+    }
+
+    return Ok(());
+}
+
+/// Reads a `u64`, or `None` at the very end of the input.
+fn read_u64(reader: &mut impl Read) -> KVResult<Option<u64>> {
+    let mut bytes = [0; 8];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        match reader.read(&mut bytes[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "truncated backup archive").into())
+            }
+            read => filled += read,
+        }
+    }
+
+    return Ok(Some(u8_array_to_u64(&bytes)));
+}
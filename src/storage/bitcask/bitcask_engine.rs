@@ -1,63 +1,396 @@
-use std::collections::HashMap;
-use std::fs::{create_dir_all, read_dir, File, OpenOptions, DirEntry};
-use std::io::{BufReader, BufWriter, Read, Result, Seek, SeekFrom, Write};
+#![allow(clippy::needless_return)]
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{create_dir_all, metadata, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::mem::size_of;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::constants::{
+    BLOB_FILE_REWRITE_RATIO, MAX_ACTIVE_LOG_FILE_SIZE, MAX_MERGE_CHAIN_LEN, STREAM_CHUNK_LEN,
+};
 use crate::error::{KVError, KVResult};
-use crate::storage::bitcask::command::Command;
+use crate::storage::bitcask::backup::{copy_generations, write_archive};
+use crate::storage::bitcask::blob::{get_blob_file_dir, read_blob, BlobRef, BlobStore};
+use crate::storage::bitcask::changes::{ChangeEvent, ChangeFeed, KeyFilter, LogTailer};
+use crate::storage::bitcask::command::{
+    check_record, claimed_record_len, head_key, is_removal, next_record_start, record_head_len,
+    record_namespace, record_seq, set_record_head, set_record_namespace, set_record_seq, Command,
+    RecordDefect, RECORD_FORMAT_VERSION, RECORD_HEADER_LEN, RECORD_MAGIC,
+};
+use crate::storage::bitcask::compaction::{CompactionContext, Compactor};
+use crate::storage::bitcask::events::{BitcaskEvent, EventListener};
+use crate::storage::bitcask::group_commit::GroupCommitWriter;
+use crate::storage::bitcask::keydir::{KeyDir, KeyDirKind};
 use crate::storage::bitcask::log_pointer::LogPointer;
-use crate::utils::u8_array_to_u64;
+use crate::storage::bitcask::merge_operator::{fold_operands, MergeOperator, Operand};
+use crate::storage::bitcask::merge_policy::MergePolicy;
+use crate::storage::bitcask::metrics::{Histogram, Metrics};
+use crate::storage::bitcask::mmap_reader::MmapReader;
+use crate::storage::bitcask::namespace::{
+    not_found, Namespace, NamespaceRegistry, NamespacedWrite, DEFAULT_NAMESPACE,
+};
+use crate::storage::bitcask::options::BitcaskOptions;
+use crate::storage::bitcask::stats::{BitcaskStats, GenerationStats, NamespaceStats};
+use crate::storage::bitcask::stream::{copy_value, ValueReader};
+use crate::storage::bitcask::value_cache::ValueCache;
+
+/// Extension of the file a merge writes before it is renamed to its generation.
+const MERGE_FILE_EXTENSION: &str = "merge";
+/// Extension of the file a merge keeps the old pointers of the records it copies in.
+const MERGE_POINTERS_EXTENSION: &str = "pointers";
+/// Extension of the file a streamed value is copied to before it goes to the log.
+const SPOOL_FILE_EXTENSION: &str = "spool";
 
+/// Handle to a bitcask store, clones share the same store and can be used from many threads.
+#[derive(Clone)]
 pub struct Bitcask {
-    readers: HashMap<u64, BitcaskReader>,
-    writer: BitcaskWriter,
-    index: HashMap<Vec<u8>, LogPointer>,
-    current_gen: u64,
-    uncompacted: u64,
+    path: Arc<PathBuf>,
+    state: Arc<Mutex<BitcaskState>>,
+    writer: Arc<GroupCommitWriter>,
+    compactor: Arc<Compactor>,
+    compaction_threshold: Option<u64>,
+    merge_policy: Arc<dyn MergePolicy>,
+    metrics: Arc<Metrics>,
+    events: Arc<dyn EventListener>,
+    changes: Arc<ChangeFeed>,
+    slow_operation_threshold: Option<Duration>,
+    blobs: Arc<BlobStore>,
+    blob_threshold: Option<u64>,
+    keydir: KeyDirKind,
+    /// Numbers the spool files of streamed values.
+    spools: Arc<AtomicU64>,
 }
 
-struct BitcaskWriter {
-    writer: BufWriter<File>,
+/// A value along with the sequence number of the write that stored it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueMeta {
+    pub value: Vec<u8>,
+    pub seq: u64,
 }
 
-impl BitcaskWriter {
-    fn new(path: &PathBuf) -> KVResult<BitcaskWriter> {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&path.to_owned())?;
-        let writer = BitcaskWriter {
-            writer: BufWriter::new(file),
+struct BitcaskState {
+    readers: HashMap<u64, LogReader>,
+    /// Keydir of every namespace by id.
+    namespaces: HashMap<u32, NamespaceIndex>,
+    registry: NamespaceRegistry,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    gen_stats: HashMap<u64, GenerationStats>,
+    value_cache: Option<ValueCache>,
+    merging_gens: HashSet<u64>,
+    /// Number of live snapshots referencing each generation.
+    pinned_gens: HashMap<u64, usize>,
+    /// Merged generations whose files are kept until no snapshot references them.
+    retired_gens: HashSet<u64>,
+    /// Number of live snapshots, blob files are not collected while another one is open.
+    snapshots: usize,
+    /// Id of the last snapshot taken.
+    last_snapshot_id: u64,
+    gets: u64,
+    sets: u64,
+    removes: u64,
+}
+
+/// Namespace id and key.
+type NamespacedKey = (u32, Vec<u8>);
+
+/// Keydir of one namespace and the bytes of the records it points at.
+struct NamespaceIndex {
+    keys: Box<dyn KeyDir>,
+    /// Keys whose latest records are merge operands, `keys` points at their last operand.
+    chains: HashMap<Vec<u8>, MergeChain>,
+    /// Blob of every key whose set, or the base of its chain, has its value in a blob file.
+    blobs: HashMap<Vec<u8>, BlobRef>,
+    live_bytes: u64,
+    /// What each live snapshot, by id, saw of the keys changed since it was taken.
+    views: HashMap<u64, SnapshotView>,
+}
+
+/// Pointer of a key and its chain, if any.
+type KeyEntry = (LogPointer, Option<MergeChain>);
+
+/// The entries a snapshot saw of the keys changed since it was taken, it reads every other key
+/// from the index.
+struct SnapshotView {
+    /// Keys when the snapshot was taken.
+    len: usize,
+    /// Entry of every key changed since, `None` for a key that was absent.
+    entries: HashMap<Vec<u8>, Option<KeyEntry>>,
+}
+
+/// The records a value is folded from when the latest records of its key are merge operands.
+#[derive(Debug, Clone, PartialEq)]
+struct MergeChain {
+    /// The set the operands apply to, `None` when the key was not set before them.
+    base: Option<LogPointer>,
+    /// In log order.
+    operands: Vec<LogPointer>,
+}
+
+impl MergeChain {
+    fn log_pointers(&self) -> impl Iterator<Item = &LogPointer> {
+        self.base.iter().chain(&self.operands)
+    }
+}
+
+impl NamespaceIndex {
+    fn new(keys: Box<dyn KeyDir>) -> NamespaceIndex {
+        NamespaceIndex {
+            keys,
+            chains: HashMap::new(),
+            blobs: HashMap::new(),
+            live_bytes: 0,
+            views: HashMap::new(),
+        }
+    }
+
+    /// Entry of `key` as seen by the snapshot `view`, or as of now without one.
+    fn entry(&self, view: Option<u64>, key: &[u8]) -> KVResult<Option<KeyEntry>> {
+        if let Some(entry) = view.and_then(|view| self.views[&view].entries.get(key)) {
+            return Ok(entry.clone());
+        }
+
+        return Ok(self
+            .keys
+            .get(key)?
+            .map(|log_pointer| (log_pointer, self.chains.get(key).cloned())));
+    }
+
+    /// Keeps the entry of `key` for every snapshot that has not seen it change yet, called
+    /// before `key` changes.
+    fn preserve(&mut self, key: &[u8]) -> KVResult<()> {
+        if self.views.is_empty() {
+            return Ok(());
+        }
+
+        let entry = self.entry(None, key)?;
+        for view in self.views.values_mut() {
+            if !view.entries.contains_key(key) {
+                view.entries.insert(key.to_vec(), entry.clone());
+            }
+        }
+        return Ok(());
+    }
+
+    /// Whether a tombstone of `key` still hides older records: the key holds no value, or it
+    /// holds operands written after the removal, which must not apply to an older set.
+    fn needs_tombstone(&self, key: &[u8]) -> KVResult<bool> {
+        return Ok(self.keys.get(key)?.is_none()
+            || self
+                .chains
+                .get(key)
+                .is_some_and(|chain| chain.base.is_none()));
+    }
+
+    /// Points `key` at its set record, whose value is in `blob` if any, the records it pointed
+    /// at before are dead. The keydir is updated first, so the index is unchanged when that
+    /// fails.
+    fn insert(
+        &mut self,
+        key: Vec<u8>,
+        log_pointer: LogPointer,
+        blob: Option<BlobRef>,
+        gen_stats: &mut HashMap<u64, GenerationStats>,
+    ) -> KVResult<()> {
+        self.preserve(&key)?;
+        let old_log_pointer = self.keys.insert(key.clone(), log_pointer)?;
+        self.retire_chain(&key, gen_stats);
+        self.set_blob(&key, blob);
+
+        record_live(gen_stats, &log_pointer);
+        self.live_bytes += log_pointer.len;
+        if let Some(old_log_pointer) = old_log_pointer {
+            self.live_bytes -= old_log_pointer.len;
+            retire(gen_stats, &old_log_pointer);
+        }
+        return Ok(());
+    }
+
+    fn remove(
+        &mut self,
+        key: &[u8],
+        gen_stats: &mut HashMap<u64, GenerationStats>,
+    ) -> KVResult<()> {
+        self.preserve(key)?;
+        let old_log_pointer = self.keys.remove(key)?;
+        self.retire_chain(key, gen_stats);
+        self.set_blob(key, None);
+
+        if let Some(old_log_pointer) = old_log_pointer {
+            self.live_bytes -= old_log_pointer.len;
+            retire(gen_stats, &old_log_pointer);
+        }
+        return Ok(());
+    }
+
+    /// Applies the removal numbered `seq` of `key`, whose last record is newer, going by
+    /// `record_seq`. Only the base and operands of its chain numbered before the removal are
+    /// dead.
+    fn remove_before(
+        &mut self,
+        key: &[u8],
+        seq: u64,
+        record_seq: impl Fn(&LogPointer) -> KVResult<u64>,
+        gen_stats: &mut HashMap<u64, GenerationStats>,
+    ) -> KVResult<()> {
+        let chain = match self.chains.get(key) {
+            Some(chain) => chain,
+            None => return Ok(()),
+        };
+        let base_removed = match &chain.base {
+            Some(base) => record_seq(base)? < seq,
+            None => false,
+        };
+        let mut removed_operands = Vec::with_capacity(chain.operands.len());
+        for operand in &chain.operands {
+            removed_operands.push(record_seq(operand)? < seq);
+        }
+
+        self.preserve(key)?;
+        let chain = self.chains.get_mut(key).unwrap();
+        let mut removed = Vec::new();
+        if base_removed {
+            removed.extend(chain.base.take());
+        }
+        let mut removed_operands = removed_operands.into_iter();
+        chain.operands.retain(|operand| {
+            let is_removed = removed_operands.next().unwrap();
+            if is_removed {
+                removed.push(*operand);
+            }
+            !is_removed
+        });
+        for log_pointer in &removed {
+            self.live_bytes -= log_pointer.len;
+            retire(gen_stats, log_pointer);
+        }
+        if base_removed {
+            self.set_blob(key, None);
+        }
+        return Ok(());
+    }
+
+    /// Adds the merge operand at `log_pointer` to the chain of `key`, in log order, returns how
+    /// many operands the chain holds. An operand older than the set or removal `key` last got
+    /// is dead.
+    fn push_operand(
+        &mut self,
+        key: Vec<u8>,
+        log_pointer: LogPointer,
+        gen_stats: &mut HashMap<u64, GenerationStats>,
+    ) -> KVResult<usize> {
+        self.preserve(&key)?;
+        let current = self.keys.get(&key)?;
+        let newest = match self.chains.get(&key) {
+            Some(chain)
+                if chain
+                    .base
+                    .is_some_and(|base| base.is_newer_than(&log_pointer)) =>
+            {
+                record_dead(gen_stats, &log_pointer);
+                return Ok(chain.operands.len());
+            }
+            Some(chain) => chain
+                .operands
+                .last()
+                .is_none_or(|last| log_pointer.is_newer_than(last)),
+            None if current.is_some_and(|current| current.is_newer_than(&log_pointer)) => {
+                record_dead(gen_stats, &log_pointer);
+                return Ok(0);
+            }
+            None => true,
         };
-        return Ok(writer);
+        if newest {
+            self.keys.insert(key.clone(), log_pointer)?;
+        }
+
+        let chain_len = match self.chains.get_mut(&key) {
+            Some(chain) => {
+                let at = chain
+                    .operands
+                    .iter()
+                    .position(|operand| operand.is_newer_than(&log_pointer))
+                    .unwrap_or(chain.operands.len());
+                chain.operands.insert(at, log_pointer);
+                chain.operands.len()
+            }
+            None => {
+                let chain = MergeChain {
+                    base: current,
+                    operands: vec![log_pointer],
+                };
+                self.chains.insert(key, chain);
+                1
+            }
+        };
+        record_live(gen_stats, &log_pointer);
+        self.live_bytes += log_pointer.len;
+        return Ok(chain_len);
     }
 
-    fn fully_write(&mut self, buf: &mut Vec<u8>) -> KVResult<()> {
-        let mut data_len = buf.len();
-        while data_len > 0 {
-            let written = self.writer.write(&buf)?;
-            *buf = buf[written..].to_vec();
-            data_len -= written;
+    /// Replaces `folded`, the start of the chain of `key`, with the set at `log_pointer`
+    /// holding its folded value.
+    fn fold(
+        &mut self,
+        key: &[u8],
+        folded: &MergeChain,
+        log_pointer: LogPointer,
+        gen_stats: &mut HashMap<u64, GenerationStats>,
+    ) -> KVResult<()> {
+        self.preserve(key)?;
+        if self.chains[key].operands.len() == folded.operands.len() {
+            self.keys.insert(key.to_vec(), log_pointer)?;
+        }
+
+        for old_log_pointer in folded.log_pointers() {
+            self.live_bytes -= old_log_pointer.len;
+            retire(gen_stats, old_log_pointer);
         }
+        record_live(gen_stats, &log_pointer);
+        self.live_bytes += log_pointer.len;
+        // Folded values are written to the log.
+        self.set_blob(key, None);
 
+        let chain = self.chains.get_mut(key).unwrap();
+        chain.operands.drain(..folded.operands.len());
+        if chain.operands.is_empty() {
+            self.chains.remove(key);
+        } else {
+            chain.base = Some(log_pointer);
+        }
         return Ok(());
     }
-}
 
-impl Write for BitcaskWriter {
-    fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        self.writer.write(buf)
+    fn set_blob(&mut self, key: &[u8], blob: Option<BlobRef>) {
+        match blob {
+            Some(blob) => self.blobs.insert(key.to_vec(), blob),
+            None => self.blobs.remove(key),
+        };
     }
 
-    fn flush(&mut self) -> Result<()> {
-        self.writer.flush()
+    /// Drops the chain of `key`, every record of it but the last operand is dead.
+    fn retire_chain(&mut self, key: &[u8], gen_stats: &mut HashMap<u64, GenerationStats>) {
+        if !self.chains.contains_key(key) {
+            return;
+        }
+
+        let chain = self.chains.remove(key).unwrap();
+        let operands = &chain.operands[..chain.operands.len() - 1];
+        for log_pointer in chain.base.iter().chain(operands) {
+            self.live_bytes -= log_pointer.len;
+            retire(gen_stats, log_pointer);
+        }
     }
 }
 
-impl Seek for BitcaskWriter {
-    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        self.writer.seek(pos)
-    }
+/// The active generation is read through its file descriptor, sealed ones are memory-mapped.
+enum LogReader {
+    Active(BitcaskReader),
+    Sealed(MmapReader),
 }
 
 struct BitcaskReader {
@@ -70,12 +403,22 @@ impl BitcaskReader {
             .write(true)
             .read(true)
             .create(true)
-            .open(&path.to_owned())?;
+            .truncate(false)
+            .open(path)?;
         let reader = BitcaskReader {
             reader: BufReader::new(file),
         };
         return Ok(reader);
     }
+
+    fn read_at(&mut self, pos: u64, len: u64) -> KVResult<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(pos))?;
+
+        let mut buffer = vec![0; len as usize];
+        self.reader.read_exact(&mut buffer)?;
+
+        return Ok(buffer);
+    }
 }
 
 impl Read for BitcaskReader {
@@ -92,196 +435,4319 @@ impl Seek for BitcaskReader {
 
 impl Bitcask {
     pub fn open(path: &PathBuf) -> KVResult<Bitcask> {
-        create_dir_all(&path)?;
+        return Bitcask::open_with_options(path, BitcaskOptions::default());
+    }
 
-        let sorted_gen_list = get_sorted_gen_list(&path)?;
+    pub fn open_with_options(path: &PathBuf, options: BitcaskOptions) -> KVResult<Bitcask> {
+        create_dir_all(path)?;
+        let load_start = Instant::now();
+        let events = options.event_listener.clone();
 
-        let mut index = HashMap::new();
+        remove_unfinished_files(path)?;
+
+        let sorted_gen_list = get_sorted_gen_list(path)?;
+
+        let registry = NamespaceRegistry::load(path)?;
+        let mut namespaces = HashMap::new();
+        for (_, id) in registry.namespaces() {
+            namespaces.insert(id, NamespaceIndex::new(options.keydir.new_keydir(path)?));
+        }
         let mut readers = HashMap::new();
-        let mut uncompacted = 0;
+        let mut gen_stats = HashMap::new();
+        let mut last_seq = 0;
+        let mut last_gen_clean = false;
 
         for gen in &sorted_gen_list {
-            uncompacted += load_index(*gen, &path, &mut readers, &mut index)?;
+            let (gen_last_seq, clean) = load_index(
+                *gen,
+                path,
+                &mut readers,
+                &mut namespaces,
+                &mut gen_stats,
+                &*events,
+            )?;
+            last_seq = last_seq.max(gen_last_seq);
+            last_gen_clean = clean;
         }
 
-        let current_gen = {
-            if sorted_gen_list.is_empty() {
-                0
-            } else {
-                *sorted_gen_list.last().unwrap()
+        // Writes go on in the last file if it ends with a whole record and has room left,
+        // otherwise to a fresh generation.
+        let current_gen = match sorted_gen_list.last() {
+            Some(gen)
+                if last_gen_clean
+                    && get_log_file_dir(*gen, path).metadata()?.len()
+                        < MAX_ACTIVE_LOG_FILE_SIZE =>
+            {
+                readers.remove(gen);
+                *gen
             }
+            Some(gen) => gen + 1,
+            None => 0,
         };
 
-        let writer_log_path = get_log_file_dir(current_gen, &path);
-        let writer = BitcaskWriter::new(&writer_log_path)?;
+        let metrics = Arc::new(Metrics::new());
+        let changes = Arc::new(ChangeFeed::new(path, last_seq + 1, events.clone()));
+        let blobs = Arc::new(BlobStore::new(
+            path,
+            options.max_blob_file_size,
+            options.sync_writes,
+        )?);
+        let writer = GroupCommitWriter::new(
+            current_gen,
+            path,
+            options.sync_writes,
+            MAX_ACTIVE_LOG_FILE_SIZE,
+            metrics.clone(),
+            events.clone(),
+            changes.clone(),
+        )?;
 
-        let bitcask = Bitcask {
+        events.on_event(&BitcaskEvent::Opened {
+            generations: sorted_gen_list.len(),
+            keys: namespaces
+                .values()
+                .map(|namespace| namespace.keys.len())
+                .sum(),
+            load_time: load_start.elapsed(),
+        });
+
+        let state = BitcaskState {
             readers,
+            namespaces,
+            registry,
+            merge_operator: options.merge_operator.clone(),
+            gen_stats,
+            value_cache: options.value_cache_capacity.map(ValueCache::new),
+            merging_gens: HashSet::new(),
+            pinned_gens: HashMap::new(),
+            retired_gens: HashSet::new(),
+            snapshots: 0,
+            last_snapshot_id: 0,
+            gets: 0,
+            sets: 0,
+            removes: 0,
+        };
+
+        let path = Arc::new(path.to_owned());
+        let state = Arc::new(Mutex::new(state));
+        let writer = Arc::new(writer);
+
+        let compactor = {
+            let path = path.clone();
+            let state = state.clone();
+            let writer = writer.clone();
+            let blobs = blobs.clone();
+            let compaction_threshold = options.compaction_threshold;
+            let merge_policy = options.merge_policy.clone();
+            let metrics = metrics.clone();
+            let events = events.clone();
+            Compactor::start(
+                options.max_concurrent_merges,
+                options.compaction_bytes_per_sec,
+                move |context, forced| {
+                    if !forced
+                        && !exceeds_threshold(&state, compaction_threshold)
+                        && !state.lock().unwrap().has_full_chain()
+                    {
+                        return Ok(false);
+                    }
+                    let merge_policy = if forced { None } else { Some(&*merge_policy) };
+
+                    let start = Instant::now();
+                    let result = merge_generations(
+                        &path,
+                        &state,
+                        &writer,
+                        &blobs,
+                        context,
+                        merge_policy,
+                        &*events,
+                    );
+                    match &result {
+                        Ok(true) => {
+                            metrics.compaction_duration.observe(start.elapsed());
+                            metrics.fsyncs.fetch_add(1, Ordering::Relaxed);
+                        }
+                        Ok(false) => {}
+                        Err(err) => {
+                            metrics.compaction_errors.fetch_add(1, Ordering::Relaxed);
+                            events.on_event(&BitcaskEvent::MergeFailed {
+                                error: format!("{:?}", err),
+                            });
+                        }
+                    }
+                    result
+                },
+            )
+        };
+
+        let bitcask = Bitcask {
+            path,
+            state,
             writer,
-            index,
-            current_gen,
-            uncompacted,
+            compactor: Arc::new(compactor),
+            compaction_threshold: options.compaction_threshold,
+            merge_policy: options.merge_policy.clone(),
+            metrics,
+            events,
+            changes,
+            slow_operation_threshold: options.slow_operation_threshold,
+            blobs,
+            blob_threshold: options.blob_threshold,
+            keydir: options.keydir,
+            spools: Arc::new(AtomicU64::new(0)),
         };
 
         return Ok(bitcask);
     }
 
-    pub fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> KVResult<()> {
-        let command = Command::Set {
+    pub fn set(&self, key: Vec<u8>, value: Vec<u8>) -> KVResult<()> {
+        return self.set_in(DEFAULT_NAMESPACE, key, value);
+    }
+
+    pub(crate) fn set_in(&self, namespace: u32, key: Vec<u8>, value: Vec<u8>) -> KVResult<()> {
+        let start = Instant::now();
+        let result = self.apply_set(namespace, key, value);
+        self.record_operation(
+            "set",
+            &self.metrics.set_duration,
+            &self.metrics.set_errors,
+            start,
+            &result,
+        );
+        return result;
+    }
+
+    /// Same as `set` for a value of `len` bytes read from `reader`. The value is copied to
+    /// the log, or a blob file, in chunks and never held in memory whole. Fails, leaving the
+    /// key as it was, when `reader` ends before `len` bytes.
+    pub fn set_from_reader(&self, key: Vec<u8>, reader: &mut impl Read, len: u64) -> KVResult<()> {
+        return self.set_from_reader_in(DEFAULT_NAMESPACE, key, reader, len);
+    }
+
+    pub(crate) fn set_from_reader_in(
+        &self,
+        namespace: u32,
+        key: Vec<u8>,
+        reader: &mut dyn Read,
+        len: u64,
+    ) -> KVResult<()> {
+        let start = Instant::now();
+        let result = self.apply_set_from_reader(namespace, key, reader, len);
+        self.record_operation(
+            "set",
+            &self.metrics.set_duration,
+            &self.metrics.set_errors,
+            start,
+            &result,
+        );
+        return result;
+    }
+
+    pub fn get(&self, key: Vec<u8>) -> KVResult<Option<Vec<u8>>> {
+        return self.get_in(DEFAULT_NAMESPACE, key);
+    }
+
+    pub(crate) fn get_in(&self, namespace: u32, key: Vec<u8>) -> KVResult<Option<Vec<u8>>> {
+        let start = Instant::now();
+        let result = self.read_value(namespace, key);
+        self.record_operation(
+            "get",
+            &self.metrics.get_duration,
+            &self.metrics.get_errors,
+            start,
+            &result,
+        );
+        return result;
+    }
+
+    /// Same as `get`, returning a reader over the value in its file instead of the value, for
+    /// values too large to hold in memory. The value cache is bypassed. A value folded from
+    /// merge operands not merged yet is folded in memory first.
+    pub fn get_reader(&self, key: Vec<u8>) -> KVResult<Option<ValueReader>> {
+        return self.get_reader_in(DEFAULT_NAMESPACE, key);
+    }
+
+    pub(crate) fn get_reader_in(
+        &self,
+        namespace: u32,
+        key: Vec<u8>,
+    ) -> KVResult<Option<ValueReader>> {
+        let start = Instant::now();
+        let result = self.open_value_reader(namespace, key);
+        self.record_operation(
+            "get",
+            &self.metrics.get_duration,
+            &self.metrics.get_errors,
+            start,
+            &result,
+        );
+        return result;
+    }
+
+    /// Same as `get`, also returning the sequence number of the value.
+    pub fn get_with_meta(&self, key: Vec<u8>) -> KVResult<Option<ValueMeta>> {
+        return self.get_with_meta_in(DEFAULT_NAMESPACE, key);
+    }
+
+    pub(crate) fn get_with_meta_in(
+        &self,
+        namespace: u32,
+        key: Vec<u8>,
+    ) -> KVResult<Option<ValueMeta>> {
+        let start = Instant::now();
+        let result = self.read_value_with_meta(namespace, key);
+        self.record_operation(
+            "get",
+            &self.metrics.get_duration,
+            &self.metrics.get_errors,
+            start,
+            &result,
+        );
+        return result;
+    }
+
+    pub fn remove(&self, key: Vec<u8>) -> KVResult<()> {
+        return self.remove_in(DEFAULT_NAMESPACE, key);
+    }
+
+    pub(crate) fn remove_in(&self, namespace: u32, key: Vec<u8>) -> KVResult<()> {
+        let start = Instant::now();
+        let result = self.apply_remove(namespace, key);
+        self.record_operation(
+            "remove",
+            &self.metrics.remove_duration,
+            &self.metrics.remove_errors,
+            start,
+            &result,
+        );
+        return result;
+    }
+
+    /// Adds `delta` to the value of `key`, a 64-bit integer in decimal that is 0 while the key
+    /// is not set. Only the delta is appended, it is added when the key is read or merged, so
+    /// concurrent increments never lose an update. Reading the key fails once the sum overflows,
+    /// until it is set again.
+    pub fn increment(&self, key: Vec<u8>, delta: i64) -> KVResult<()> {
+        return self.increment_in(DEFAULT_NAMESPACE, key, delta);
+    }
+
+    pub(crate) fn increment_in(&self, namespace: u32, key: Vec<u8>, delta: i64) -> KVResult<()> {
+        let start = Instant::now();
+        let command = Command::Increment {
             key: key.clone(),
-            value,
+            delta,
+        };
+        let result = self.append_operand(namespace, key, command);
+        self.record_operation(
+            "increment",
+            &self.metrics.increment_duration,
+            &self.metrics.increment_errors,
+            start,
+            &result,
+        );
+        return result;
+    }
+
+    /// Appends `operand` to the value of `key`, the merge operator of the store folds it in
+    /// when the key is read or merged. Fails when the store has no merge operator.
+    pub fn merge(&self, key: Vec<u8>, operand: Vec<u8>) -> KVResult<()> {
+        return self.merge_in(DEFAULT_NAMESPACE, key, operand);
+    }
+
+    pub(crate) fn merge_in(&self, namespace: u32, key: Vec<u8>, operand: Vec<u8>) -> KVResult<()> {
+        let start = Instant::now();
+        let result = if self.state.lock().unwrap().merge_operator.is_none() {
+            let message = "merge operands need a merge operator in the options";
+            Err(Error::new(ErrorKind::InvalidInput, message).into())
+        } else {
+            let command = Command::Merge {
+                key: key.clone(),
+                operand,
+            };
+            self.append_operand(namespace, key, command)
         };
+        self.record_operation(
+            "merge",
+            &self.metrics.merge_duration,
+            &self.metrics.merge_errors,
+            start,
+            &result,
+        );
+        return result;
+    }
 
-        let command_bytes = command.parse();
+    fn apply_set(&self, namespace: u32, key: Vec<u8>, value: Vec<u8>) -> KVResult<()> {
+        self.state.lock().unwrap().namespace(namespace)?;
 
-        let current_pos = self.writer.seek(SeekFrom::Current(0))?;
-        let command_bytes_len = command_bytes.len();
+        let (command, blob) = self.set_command(key.clone(), value)?;
+        return self.append_set(namespace, key, command, blob);
+    }
+
+    fn apply_set_from_reader(
+        &self,
+        namespace: u32,
+        key: Vec<u8>,
+        reader: &mut dyn Read,
+        len: u64,
+    ) -> KVResult<()> {
+        self.state.lock().unwrap().namespace(namespace)?;
 
-        let log_pointer = LogPointer::new(self.current_gen, current_pos, command_bytes_len as u64);
+        if self
+            .blob_threshold
+            .is_some_and(|blob_threshold| len >= blob_threshold)
+        {
+            let blob = self.blobs.write_from_reader(reader, len)?;
+            let command = Command::SetBlob {
+                key: key.clone(),
+                blob,
+            };
+            return self.append_set(namespace, key, command, Some(blob));
+        }
 
-        self.index.insert(key, log_pointer);
+        // Spooled first, so other writes do not wait on a slow `reader`.
+        let spool = self.spools.fetch_add(1, Ordering::SeqCst);
+        let spool_path = self
+            .path
+            .join(format!("{}.{}", spool, SPOOL_FILE_EXTENSION));
+        let result = spool_value(&spool_path, reader, len).and_then(|mut spooled| {
+            self.writer.append_streamed_then(
+                set_record_head(namespace, &key, len),
+                &mut spooled,
+                len,
+                |result| -> KVResult<()> {
+                    let log_pointer = result?;
+                    return self
+                        .state
+                        .lock()
+                        .unwrap()
+                        .index_set(namespace, key, log_pointer, None);
+                },
+            )
+        });
+        let _ = remove_file(&spool_path);
+        result?;
 
-        self.writer.fully_write(&mut command_bytes.to_vec())?;
-        self.writer.flush()?;
+        self.request_compaction_if_needed();
 
         return Ok(());
     }
 
-    pub fn get(&mut self, key: Vec<u8>) -> KVResult<Option<Vec<u8>>> {
-        match self.index.get(&key) {
-            None => Err(KVError::KeyNoneExisted),
-            Some(log_pointer) => {
-                match self.readers.get_mut(&log_pointer.gen) {
-                    Some(reader) => {
-                        reader.seek(SeekFrom::Start(log_pointer.pos))?;
+    /// Appends `command`, a set of `key` in `namespace`, and points the index at it. `blob`,
+    /// the blob written for the command if any, is released either way.
+    fn append_set(
+        &self,
+        namespace: u32,
+        key: Vec<u8>,
+        command: Command,
+        blob: Option<BlobRef>,
+    ) -> KVResult<()> {
+        let result = self.writer.append_then(
+            namespaced_record(namespace, &command),
+            |result| -> KVResult<()> {
+                let log_pointer = result?;
+                return self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .index_set(namespace, key, log_pointer, blob);
+            },
+        );
+        if let Some(blob) = &blob {
+            self.blobs.release(blob);
+        }
+        result?;
 
-                        let mut log_pointer_reader = reader.by_ref().take(log_pointer.len);
+        self.request_compaction_if_needed();
 
-                        let mut buffer = vec![0; log_pointer.len as usize];
-                        log_pointer_reader.read(&mut buffer)?;
+        return Ok(());
+    }
 
-                        let command = Command::from(buffer.as_slice());
+    /// The record setting `key`, a value reaching the blob threshold is written to a blob file
+    /// first. The returned blob is released once the record is applied.
+    fn set_command(&self, key: Vec<u8>, value: Vec<u8>) -> KVResult<(Command, Option<BlobRef>)> {
+        if self
+            .blob_threshold
+            .is_some_and(|blob_threshold| value.len() as u64 >= blob_threshold)
+        {
+            let blob = self.blobs.write(&value)?;
+            return Ok((Command::SetBlob { key, blob }, Some(blob)));
+        }
 
-                        match command {
-                            Command::Set { key: _, value } => {
-                                return Ok(Some(value));
-                            }
-                            Command::Remove { key: _ } => {
-                                return Ok(None);
-                            }
-                        }
-                    }
-                    None => {
-                        return Ok(None);
-                    }
+        return Ok((Command::Set { key, value }, None));
+    }
+
+    fn read_value(&self, namespace: u32, key: Vec<u8>) -> KVResult<Option<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        state.gets += 1;
+
+        let (log_pointer, chain) = state.lookup(namespace, &key)?;
+
+        if let Some(cache) = state.value_cache.as_mut() {
+            if let Some(value) = cache.get(&cache_key(namespace, &key)) {
+                return Ok(Some(value));
+            }
+        }
+
+        let active_gen = self.writer.active_gen();
+        let value = state.read_key(&self.path, active_gen, &key, &log_pointer, chain.as_ref())?;
+        self.metrics
+            .bytes_read
+            .fetch_add(log_pointer.len, Ordering::Relaxed);
+
+        match value {
+            Some(ValueMeta { value, seq: _ }) => {
+                if let Some(cache) = state.value_cache.as_mut() {
+                    cache.insert(cache_key(namespace, &key), value.clone());
                 }
+                return Ok(Some(value));
+            }
+            None => {
+                return Ok(None);
             }
         }
     }
 
-    pub fn remove(&mut self, key: Vec<u8>) -> KVResult<()> {
+    fn open_value_reader(&self, namespace: u32, key: Vec<u8>) -> KVResult<Option<ValueReader>> {
+        // The file is opened under the lock, so a merge can not remove it first.
+        let mut state = self.state.lock().unwrap();
+        state.gets += 1;
+
+        let (log_pointer, chain) = state.lookup(namespace, &key)?;
+        if let Some(chain) = chain {
+            let active_gen = self.writer.active_gen();
+            let folded = state.fold_chain(&self.path, active_gen, &key, &chain)?;
+            return Ok(Some(ValueReader::folded(folded.value)));
+        }
+
+        return ValueReader::open(&self.path, &log_pointer);
+    }
+
+    /// Always reads the record, the sequence number is not kept in the value cache.
+    fn read_value_with_meta(&self, namespace: u32, key: Vec<u8>) -> KVResult<Option<ValueMeta>> {
+        let mut state = self.state.lock().unwrap();
+        state.gets += 1;
+
+        let (log_pointer, chain) = state.lookup(namespace, &key)?;
+
+        let active_gen = self.writer.active_gen();
+        let value = state.read_key(&self.path, active_gen, &key, &log_pointer, chain.as_ref())?;
+        self.metrics
+            .bytes_read
+            .fetch_add(log_pointer.len, Ordering::Relaxed);
+
+        return Ok(value);
+    }
+
+    /// Appends `command`, a merge operand of `key` in `namespace`, and adds it to the chain of
+    /// the key.
+    fn append_operand(&self, namespace: u32, key: Vec<u8>, command: Command) -> KVResult<()> {
+        self.state.lock().unwrap().namespace(namespace)?;
+
+        let chain_len = self.writer.append_then(
+            namespaced_record(namespace, &command),
+            |result| -> KVResult<usize> {
+                let log_pointer = result?;
+                return self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .index_operand(namespace, key, log_pointer);
+            },
+        )?;
+
+        // Folding the chain takes a merge, also when no generation is due for one.
+        if chain_len >= MAX_MERGE_CHAIN_LEN {
+            self.compactor.request(false);
+        } else {
+            self.request_compaction_if_needed();
+        }
+
+        return Ok(());
+    }
+
+    fn apply_remove(&self, namespace: u32, key: Vec<u8>) -> KVResult<()> {
+        self.state.lock().unwrap().namespace(namespace)?;
+
         let command = Command::Remove { key: key.clone() };
-        let command_bytes = command.parse();
 
-        match self.index.remove(&key) {
-            Some(old_log_pointer) => {
-                self.uncompacted += old_log_pointer.len;
-                self.uncompacted += *&command_bytes.len() as u64;
+        self.writer.append_then(
+            namespaced_record(namespace, &command),
+            |result| -> KVResult<()> {
+                let log_pointer = result?;
+                return self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .index_remove(namespace, &key, log_pointer);
+            },
+        )?;
+
+        self.request_compaction_if_needed();
+
+        return Ok(());
+    }
+
+    /// Applies `writes` in order, a `None` value removes the key. The records are appended with
+    /// one write and at most one fsync, which makes bulk loads much faster than single writes.
+    pub fn write_batch(&self, writes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> KVResult<()> {
+        return self.write_batch_in(DEFAULT_NAMESPACE, writes);
+    }
+
+    pub(crate) fn write_batch_in(
+        &self,
+        namespace: u32,
+        writes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    ) -> KVResult<()> {
+        let writes = writes
+            .into_iter()
+            .map(|(key, value)| (namespace, key, value))
+            .collect();
+        return self.apply_batch(writes);
+    }
+
+    /// Same as `write_batch` for keys of any namespaces of this store.
+    pub fn write_batch_across(&self, writes: Vec<NamespacedWrite>) -> KVResult<()> {
+        let writes = writes
+            .into_iter()
+            .map(|(namespace, key, value)| (namespace.id(), key, value))
+            .collect();
+        return self.apply_batch(writes);
+    }
+
+    /// A batch of more than one record is preceded by a batch record, so it is applied whole
+    /// or not at all when the store is opened after a crash in the middle of the write.
+    fn apply_batch(&self, writes: Vec<(u32, Vec<u8>, Option<Vec<u8>>)>) -> KVResult<()> {
+        {
+            let state = self.state.lock().unwrap();
+            for (namespace, _, _) in &writes {
+                state.namespace(*namespace)?;
             }
-            None => {}
         }
 
-        self.writer.fully_write(&mut command_bytes.to_vec())?;
+        let mut records = Vec::with_capacity(writes.len() + 1);
+        if writes.len() > 1 {
+            records.push(
+                Command::Batch {
+                    count: writes.len() as u64,
+                }
+                .parse(),
+            );
+        }
+        let batch_records = records.len();
+        let mut keys = Vec::with_capacity(writes.len());
+        let mut blobs = Vec::new();
+        let mut result = Ok(());
+        for (namespace, key, value) in writes {
+            let (command, blob) = match value {
+                Some(value) => match self.set_command(key.clone(), value) {
+                    Ok((command, blob)) => {
+                        blobs.extend(blob);
+                        (command, blob)
+                    }
+                    Err(err) => {
+                        result = Err(err);
+                        break;
+                    }
+                },
+                None => (Command::Remove { key: key.clone() }, None),
+            };
+            records.push(namespaced_record(namespace, &command));
+            keys.push((
+                namespace,
+                key,
+                !matches!(command, Command::Remove { key: _ }),
+                blob,
+            ));
+        }
+
+        if result.is_ok() {
+            result = self
+                .writer
+                .append_batch_then(records, |result| -> KVResult<()> {
+                    let log_pointers = result?.into_iter().skip(batch_records);
+                    let mut state = self.state.lock().unwrap();
+                    for ((namespace, key, is_set, blob), log_pointer) in
+                        keys.into_iter().zip(log_pointers)
+                    {
+                        if is_set {
+                            state.index_set(namespace, key, log_pointer, blob)?;
+                        } else {
+                            state.index_remove(namespace, &key, log_pointer)?;
+                        }
+                    }
+                    return Ok(());
+                });
+        }
+        for blob in &blobs {
+            self.blobs.release(blob);
+        }
+        result?;
+
+        self.request_compaction_if_needed();
 
         return Ok(());
     }
-}
 
-fn get_sorted_gen_list(path: &PathBuf) -> KVResult<Vec<u64>> {
-    let x = read_dir(&path)?;
+    /// Adds an empty namespace called `name` to the store.
+    pub fn create_namespace(&self, name: &str) -> KVResult<Namespace> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.registry.create(&self.path, name)?;
+        let keys = self.keydir.new_keydir(&self.path)?;
+        state.namespaces.insert(id, NamespaceIndex::new(keys));
 
-    let res: Vec<u64> = x.map(|yo| {
-//        return Ok(yo?.path());
-        return yo?.path().to_string_lossy().parse::<i32>();
-    }).filter_map(|ff| Result::ok)
-        .map(|j| {
-        return 3;
-    }).collect();
+        return Ok(Namespace::new(self.clone(), id, name));
+    }
 
+    /// Namespace `name`, the keys written through this handle are in the default namespace.
+    pub fn namespace(&self, name: &str) -> KVResult<Namespace> {
+        let state = self.state.lock().unwrap();
+        let id = state.registry.id(name).ok_or_else(|| not_found(name))?;
 
-    let s = vec![0];
-    return Ok(s);
+        return Ok(Namespace::new(self.clone(), id, name));
+    }
 
-//    let mut entries: Vec<u64> = read_dir(&path)?
-//        .map(|maybe_dir_entry| Ok(maybe_dir_entry?))
-//        .filter(|dir_entry| {})
-//        .map(|entry| {
-//            let file_name = entry.file_name().into_string()?;
-//            let gen = file_name.parse::<u64>()?;
-//            return gen;
-//        })
-//        .collect();
-//
-//    entries.sort();
-//
-//    return Ok(entries);
-}
+    /// Names of every namespace, the default one first.
+    pub fn list_namespaces(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        return state
+            .registry
+            .namespaces()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+    }
 
-fn load_index(
-    gen: u64,
-    path: &PathBuf,
-    readers: &mut HashMap<u64, BitcaskReader>,
-    index: &mut HashMap<Vec<u8>, LogPointer>,
-) -> KVResult<u64> {
-    let mut uncompacted = 0;
-    let log_path = get_log_file_dir(gen, &path);
-    let mut reader = BitcaskReader::new(&log_path)?;
+    /// Drops namespace `name` with all its keys. Only its entry in the namespace list is
+    /// written, its records are left in the logs as dead bytes. No disk space is freed until a
+    /// merge rewrites the generations holding them, by `compact` or in the background once the
+    /// dead bytes reach the compaction threshold.
+    pub fn drop_namespace(&self, name: &str) -> KVResult<()> {
+        {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+            let id = state.registry.remove(&self.path, name)?;
 
-    let mut buffer = Vec::new();
-    reader.read_to_end(&mut buffer)?;
+            if let Some(namespace) = state.namespaces.remove(&id) {
+                namespace.keys.for_each(&mut |key, log_pointer| {
+                    retire(&mut state.gen_stats, &log_pointer);
+                    if let Some(cache) = state.value_cache.as_mut() {
+                        cache.invalidate(&cache_key(id, key));
+                    }
+                })?;
+                // The keydir only points at the last operand of a chain.
+                for chain in namespace.chains.values() {
+                    let operands = &chain.operands[..chain.operands.len() - 1];
+                    for log_pointer in chain.base.iter().chain(operands) {
+                        retire(&mut state.gen_stats, log_pointer);
+                    }
+                }
+            }
+        }
 
-    let mut current_pos = 0;
+        self.request_compaction_if_needed();
 
-    while current_pos as i64 <= buffer.len() as i64 - 1 {
-        let total_length_bytes = &buffer[current_pos..current_pos + 8];
-        let total_length = u8_array_to_u64(&[
-            total_length_bytes[0],
-            total_length_bytes[1],
-            total_length_bytes[2],
-            total_length_bytes[3],
-            total_length_bytes[4],
-            total_length_bytes[5],
-            total_length_bytes[6],
-            total_length_bytes[7],
-        ]) as usize;
-        let bytes = &buffer[current_pos..current_pos + total_length];
+        return Ok(());
+    }
 
-        let command = Command::from(bytes);
-        match command {
-            Command::Set { key, value: _ } => {
-                let log_pointer = LogPointer::new(gen, current_pos as u64, total_length as u64);
-                if let Some(old_log_pointer) = index.insert(key, log_pointer) {
-                    uncompacted += old_log_pointer.len;
-                }
+    /// Merges every sealed generation on the background compaction thread and waits for it,
+    /// then collects the blob files nothing points into.
+    pub fn compact(&self) -> KVResult<()> {
+        self.compactor.request(true);
+        self.wait_for_compaction()?;
+        self.collect_blobs()?;
+        return Ok(());
+    }
+
+    /// Deletes the sealed blob files no key points into any more and returns how many. Does
+    /// nothing while a snapshot is open, as it may still read overwritten values. Values left
+    /// in mostly unused blob files are moved out by merges.
+    pub fn collect_blobs(&self) -> KVResult<usize> {
+        let sealed_files = self.blobs.sealed_files()?;
+        if sealed_files.is_empty() {
+            return Ok(0);
+        }
+
+        // Values written to a sealed file are referenced from the keydirs unless overwritten.
+        let live_bytes = {
+            let state = self.state.lock().unwrap();
+            if state.snapshots > 0 {
+                return Ok(0);
             }
-            Command::Remove { key } => {
-                if let Some(old_log_pointer) = index.remove(&key) {
-                    uncompacted += old_log_pointer.len;
-                }
-                uncompacted += total_length as u64;
+            state.blob_live_bytes()
+        };
+
+        let mut collected = 0;
+        for file in sealed_files {
+            if !live_bytes.contains_key(&file) {
+                remove_file(get_blob_file_dir(file, &self.path))?;
+                collected += 1;
             }
         }
 
-        current_pos += total_length as usize;
+        return Ok(collected);
     }
 
-    readers.insert(gen, reader);
+    /// Stops background merges from starting, and holds running ones at their next write.
+    pub fn pause_compaction(&self) {
+        self.compactor.pause();
+    }
 
-    return Ok(uncompacted);
-}
+    pub fn resume_compaction(&self) {
+        self.compactor.resume();
+    }
 
-fn get_log_file_dir(gen: u64, dir: &PathBuf) -> PathBuf {
-    let log_file_name = format!("{}.log", gen);
-    let log_path = dir.join(Path::new(&log_file_name));
-    return log_path;
+    /// Blocks until no merge is running or pending, returns right away while paused.
+    pub fn wait_for_compaction(&self) -> KVResult<()> {
+        match self.compactor.wait() {
+            Some(message) => Err(KVError::CompactionFailed(message)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn stats(&self) -> BitcaskStats {
+        let state = self.state.lock().unwrap();
+
+        let mut generations: Vec<GenerationStats> = state.gen_stats.values().cloned().collect();
+        generations.sort_by_key(|generation| generation.gen);
+        for generation in generations.iter_mut() {
+            generation.file_size = get_log_file_dir(generation.gen, &self.path)
+                .metadata()
+                .map_or(0, |metadata| metadata.len());
+        }
+
+        let namespaces: Vec<NamespaceStats> = state
+            .registry
+            .namespaces()
+            .into_iter()
+            .filter_map(|(name, id)| {
+                let namespace = state.namespaces.get(&id)?;
+                Some(NamespaceStats {
+                    name,
+                    key_count: namespace.keys.len() as u64,
+                    live_bytes: namespace.live_bytes,
+                    index_memory_bytes: namespace.keys.memory_bytes(),
+                })
+            })
+            .collect();
+
+        let mut stats = BitcaskStats {
+            key_count: namespaces.iter().map(|namespace| namespace.key_count).sum(),
+            generations,
+            uncompacted: state.uncompacted(),
+            index_memory_bytes: namespaces
+                .iter()
+                .map(|namespace| namespace.index_memory_bytes)
+                .sum(),
+            namespaces,
+            gets: state.gets,
+            sets: state.sets,
+            removes: state.removes,
+            write_batches: self.writer.batches(),
+            compactions: self.compactor.merges(),
+            ..BitcaskStats::default()
+        };
+
+        if let Some(cache) = &state.value_cache {
+            stats.cache_hits = cache.hits;
+            stats.cache_misses = cache.misses;
+            stats.cache_size = cache.size();
+        }
+
+        return stats;
+    }
+
+    /// A read-only view of the store as of now, unaffected by later writes and merges.
+    ///
+    /// Taking a snapshot does not copy the index, every write keeps the entry the snapshot saw
+    /// of its key, the first time the key changes. Merged files the snapshot still reads are
+    /// kept on disk until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        // The default namespace can not be dropped.
+        return self.snapshot_in(DEFAULT_NAMESPACE).unwrap();
+    }
+
+    pub(crate) fn snapshot_in(&self, namespace: u32) -> KVResult<Snapshot> {
+        let mut state = self.state.lock().unwrap();
+        let len = state.namespace(namespace)?.keys.len();
+
+        // Backups taken from the snapshot copy the active generation up to `active_end`.
+        let (active_gen, active_end) = self.writer.committed_end();
+        let mut gens: Vec<u64> = state.gen_stats.keys().cloned().collect();
+        gens.push(active_gen);
+        gens.sort();
+        gens.dedup();
+        for gen in &gens {
+            *state.pinned_gens.entry(*gen).or_insert(0) += 1;
+        }
+        state.snapshots += 1;
+        state.last_snapshot_id += 1;
+        let id = state.last_snapshot_id;
+        let view = SnapshotView {
+            len,
+            entries: HashMap::new(),
+        };
+        state
+            .namespaces
+            .get_mut(&namespace)
+            .unwrap()
+            .views
+            .insert(id, view);
+
+        return Ok(Snapshot {
+            path: self.path.clone(),
+            state: self.state.clone(),
+            writer: self.writer.clone(),
+            namespace,
+            id,
+            gens,
+            active_end,
+        });
+    }
+
+    /// Copies a consistent image of the store into the directory `dest` while it stays online.
+    /// Sealed generations are hard-linked, the active one is copied up to the last record
+    /// written when the backup started.
+    pub fn backup(&self, dest: &PathBuf) -> KVResult<()> {
+        let snapshot = self.snapshot();
+        return copy_generations(&self.path, dest, &snapshot.gens, snapshot.active_end, None);
+    }
+
+    /// Same as `backup`, only copying the generations sealed since the backup in `base` was
+    /// taken, along with the active one. `base` is either a full or an incremental backup.
+    pub fn backup_incremental(&self, dest: &PathBuf, base: &PathBuf) -> KVResult<()> {
+        let snapshot = self.snapshot();
+        return copy_generations(
+            &self.path,
+            dest,
+            &snapshot.gens,
+            snapshot.active_end,
+            Some(base),
+        );
+    }
+
+    /// Same as `backup`, into the single archive file `dest`.
+    pub fn backup_archive(&self, dest: &PathBuf) -> KVResult<()> {
+        let snapshot = self.snapshot();
+        return write_archive(&self.path, dest, &snapshot.gens, snapshot.active_end);
+    }
+
+    /// Streams every change committed after this call, in commit order.
+    ///
+    /// With `from_seq` the changes still in the logs from that sequence number on are read back
+    /// first, so a consumer can resume from the last sequence number it processed plus one.
+    pub fn subscribe(&self, from_seq: Option<u64>) -> KVResult<Receiver<ChangeEvent>> {
+        let (sender, receiver) = channel();
+        let mut tailer = LogTailer::new(&self.path);
+
+        let send_from_logs = |tailer: &mut LogTailer| -> KVResult<()> {
+            let from_seq = match from_seq {
+                Some(from_seq) => from_seq,
+                None => return Ok(()),
+            };
+            return tailer.read_new(|event| {
+                if event.seq >= from_seq {
+                    let _ = sender.send(event);
+                }
+            });
+        };
+
+        // Catch up without holding up writers, then read the last few records in between two
+        // write batches so no change is missed or sent twice.
+        send_from_logs(&mut tailer)?;
+        self.writer.while_idle(|| {
+            send_from_logs(&mut tailer)?;
+            self.changes.register(KeyFilter::All, sender.clone());
+            return Ok(receiver);
+        })
+    }
+
+    /// Notifies every change committed after this call to one key, or to every key under a
+    /// prefix, until the receiver is dropped.
+    pub fn watch(&self, key_or_prefix: KeyFilter) -> KVResult<Receiver<ChangeEvent>> {
+        let (sender, receiver) = channel();
+        self.writer
+            .while_idle(|| self.changes.register(key_or_prefix, sender));
+
+        return Ok(receiver);
+    }
+
+    /// Engine metrics in the Prometheus text exposition format.
+    pub fn render_metrics(&self) -> String {
+        return self.metrics.render();
+    }
+
+    /// Records the latency of one operation, a missing key is an answer rather than an error.
+    fn record_operation<T>(
+        &self,
+        op: &'static str,
+        duration: &Histogram,
+        errors: &AtomicU64,
+        start: Instant,
+        result: &KVResult<T>,
+    ) {
+        let elapsed = start.elapsed();
+        duration.observe(elapsed);
+        if self
+            .slow_operation_threshold
+            .is_some_and(|threshold| elapsed >= threshold)
+        {
+            self.events.on_event(&BitcaskEvent::SlowOperation {
+                op,
+                duration: elapsed,
+            });
+        }
+
+        match result {
+            Ok(_) | Err(KVError::KeyNoneExisted) => {}
+            Err(err) => {
+                errors.fetch_add(1, Ordering::Relaxed);
+                self.events.on_event(&BitcaskEvent::OperationFailed {
+                    op,
+                    error: format!("{:?}", err),
+                });
+            }
+        }
+    }
+
+    /// Requests a merge once the threshold is crossed and the merge policy would pick a
+    /// generation, stale bytes the policy leaves alone, such as tombstones, request nothing.
+    fn request_compaction_if_needed(&self) {
+        if !exceeds_threshold(&self.state, self.compaction_threshold) {
+            return;
+        }
+
+        let state = self.state.lock().unwrap();
+        // A failing merge reports its own error.
+        let selects = state
+            .merge_candidates(&self.path, self.writer.active_gen())
+            .map_or(true, |candidates| {
+                !self.merge_policy.select(&candidates).is_empty()
+            });
+        drop(state);
+        if selects {
+            self.compactor.request(false);
+        }
+    }
+}
+
+/// Point-in-time view of a store, see `Bitcask::snapshot`.
+pub struct Snapshot {
+    path: Arc<PathBuf>,
+    state: Arc<Mutex<BitcaskState>>,
+    writer: Arc<GroupCommitWriter>,
+    namespace: u32,
+    /// Id of the view of `namespace` the snapshot reads.
+    id: u64,
+    /// Pinned generations in ascending order, the last one was active when the snapshot was taken.
+    gens: Vec<u64>,
+    /// End of the last record written to the active generation when the snapshot was taken.
+    active_end: u64,
+}
+
+impl Snapshot {
+    /// Number of keys when the snapshot was taken, 0 once its namespace is dropped.
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        return state
+            .namespaces
+            .get(&self.namespace)
+            .map_or(0, |index| index.views[&self.id].len);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// The value of `key` when the snapshot was taken, never served from the value cache.
+    pub fn get(&self, key: Vec<u8>) -> KVResult<Option<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        let entry = state
+            .namespace(self.namespace)?
+            .entry(Some(self.id), &key)?;
+        let (log_pointer, chain) = match entry {
+            None => return Err(KVError::KeyNoneExisted),
+            Some(entry) => entry,
+        };
+
+        let active_gen = self.writer.active_gen();
+        let value = state.read_key(&self.path, active_gen, &key, &log_pointer, chain.as_ref())?;
+        return Ok(value.map(|value| value.value));
+    }
+
+    /// Every key and value of the snapshot, in log order. The pinned generations are read a
+    /// record at a time, so neither the keys nor the values are held in memory.
+    pub fn iter(&self) -> impl Iterator<Item = KVResult<(Vec<u8>, Vec<u8>)>> + '_ {
+        return SnapshotScan {
+            snapshot: self,
+            next_gen: 0,
+            scanner: None,
+        };
+    }
+
+    /// The value of `key` if its entry in the snapshot is the record at `log_pointer`.
+    fn value_at(&self, key: &[u8], log_pointer: &LogPointer) -> KVResult<Option<Vec<u8>>> {
+        let mut state = self.state.lock().unwrap();
+        let chain = match state.namespace(self.namespace)?.entry(Some(self.id), key)? {
+            Some((entry_log_pointer, chain)) if entry_log_pointer == *log_pointer => chain,
+            _ => return Ok(None),
+        };
+
+        let active_gen = self.writer.active_gen();
+        let value = state.read_key(&self.path, active_gen, key, log_pointer, chain.as_ref())?;
+        return Ok(value.map(|value| value.value));
+    }
+}
+
+/// Position of `Snapshot::iter` in the pinned generations.
+struct SnapshotScan<'a> {
+    snapshot: &'a Snapshot,
+    /// Index in `gens` of the generation to read once `scanner` is done.
+    next_gen: usize,
+    scanner: Option<HeadScanner>,
+}
+
+impl SnapshotScan<'_> {
+    /// The next key and value of the snapshot, `None` once every pinned generation is read.
+    fn next_entry(&mut self) -> KVResult<Option<(Vec<u8>, Vec<u8>)>> {
+        loop {
+            let scanner = match &mut self.scanner {
+                Some(scanner) => scanner,
+                None => {
+                    let gens = &self.snapshot.gens;
+                    if self.next_gen == gens.len() {
+                        return Ok(None);
+                    }
+                    let gen = gens[self.next_gen];
+                    self.next_gen += 1;
+                    // The active generation is read up to where it ended when the snapshot was taken.
+                    let end = match self.next_gen == gens.len() {
+                        true => Some(self.snapshot.active_end),
+                        false => None,
+                    };
+                    self.scanner
+                        .insert(HeadScanner::open(&self.snapshot.path, gen, end)?)
+                }
+            };
+
+            let (log_pointer, head) = match scanner.next_head()? {
+                Some(next) => next,
+                None => {
+                    self.scanner = None;
+                    continue;
+                }
+            };
+            if record_namespace(&head) != self.snapshot.namespace {
+                continue;
+            }
+            let key = head_key(&head);
+            if let Some(value) = self.snapshot.value_at(key, &log_pointer)? {
+                return Ok(Some((key.to_vec(), value)));
+            }
+        }
+    }
+}
+
+/// Ends after the first error.
+impl Iterator for SnapshotScan<'_> {
+    type Item = KVResult<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.next_entry().transpose();
+        if let Some(Err(_)) = next {
+            self.next_gen = self.snapshot.gens.len();
+            self.scanner = None;
+        }
+
+        return next;
+    }
+}
+
+/// Reads the records of a generation file one at a time, each only up to its value.
+struct HeadScanner {
+    reader: BufReader<File>,
+    gen: u64,
+    pos: u64,
+    end: u64,
+}
+
+impl HeadScanner {
+    /// Scanner over generation `gen` up to `end`, or up to the end of the file without one.
+    fn open(path: &PathBuf, gen: u64, end: Option<u64>) -> KVResult<HeadScanner> {
+        let file = File::open(get_log_file_dir(gen, path))?;
+        let end = match end {
+            Some(end) => end,
+            None => file.metadata()?.len(),
+        };
+
+        return Ok(HeadScanner {
+            reader: BufReader::new(file),
+            gen,
+            pos: 0,
+            end,
+        });
+    }
+
+    /// Pointer to the next record and its bytes up to its value, see `record_head_len`.
+    fn next_head(&mut self) -> KVResult<Option<(LogPointer, Vec<u8>)>> {
+        if self.pos + RECORD_HEADER_LEN as u64 > self.end {
+            return Ok(None);
+        }
+
+        let mut head = vec![0; RECORD_HEADER_LEN];
+        self.reader.read_exact(&mut head)?;
+        let len = claimed_record_len(&head);
+        // Opening the store refuses a generation with anything but a torn tail, left by an
+        // interrupted write, after its last record.
+        let head_len = match record_head_len(&head, len) {
+            Ok(head_len) if self.pos + len <= self.end => head_len,
+            _ => return Ok(None),
+        };
+        head.resize(head_len, 0);
+        self.reader.read_exact(&mut head[RECORD_HEADER_LEN..])?;
+        self.reader.seek_relative((len - head_len as u64) as i64)?;
+
+        let log_pointer = LogPointer::new(self.gen, self.pos, len);
+        self.pos += len;
+
+        return Ok(Some((log_pointer, head)));
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        state.snapshots -= 1;
+        if let Some(index) = state.namespaces.get_mut(&self.namespace) {
+            index.views.remove(&self.id);
+        }
+
+        let mut removable_gens = Vec::new();
+        for gen in &self.gens {
+            let pins = state.pinned_gens.get_mut(gen).unwrap();
+            *pins -= 1;
+            if *pins > 0 {
+                continue;
+            }
+
+            state.pinned_gens.remove(gen);
+            if state.retired_gens.remove(gen) {
+                state.readers.remove(gen);
+                removable_gens.push(*gen);
+            }
+        }
+        drop(state);
+
+        for gen in removable_gens {
+            let _ = remove_file(get_log_file_dir(gen, &self.path));
+        }
+    }
+}
+
+impl BitcaskState {
+    /// Keydir of namespace `namespace`, fails once it is dropped.
+    fn namespace(&self, namespace: u32) -> KVResult<&NamespaceIndex> {
+        return self
+            .namespaces
+            .get(&namespace)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "namespace has been dropped").into());
+    }
+
+    /// Record `key` of `namespace` points at, and its merge chain if it has one.
+    fn lookup(&self, namespace: u32, key: &[u8]) -> KVResult<(LogPointer, Option<MergeChain>)> {
+        let index = self.namespace(namespace)?;
+        match index.keys.get(key)? {
+            None => return Err(KVError::KeyNoneExisted),
+            Some(log_pointer) => return Ok((log_pointer, index.chains.get(key).cloned())),
+        }
+    }
+
+    /// Value and sequence number of `key`, read from the record at `log_pointer` or folded from
+    /// `chain`. `None` for a removal.
+    fn read_key(
+        &mut self,
+        path: &PathBuf,
+        active_gen: u64,
+        key: &[u8],
+        log_pointer: &LogPointer,
+        chain: Option<&MergeChain>,
+    ) -> KVResult<Option<ValueMeta>> {
+        if let Some(chain) = chain {
+            return Ok(Some(self.fold_chain(path, active_gen, key, chain)?));
+        }
+
+        let record = self.read_record(path, active_gen, log_pointer)?;
+        let seq = record_seq(&record);
+        return Ok(command_value(path, Command::from(record.as_slice()))?
+            .map(|(_, value)| ValueMeta { value, seq }));
+    }
+
+    /// Folds the operands of `chain` into its base with the merge operator, the sequence
+    /// number is the one of the last record.
+    fn fold_chain(
+        &mut self,
+        path: &PathBuf,
+        active_gen: u64,
+        key: &[u8],
+        chain: &MergeChain,
+    ) -> KVResult<ValueMeta> {
+        let mut existing = None;
+        let mut seq = 0;
+        if let Some(base) = &chain.base {
+            let record = self.read_record(path, active_gen, base)?;
+            seq = record_seq(&record);
+            existing =
+                command_value(path, Command::from(record.as_slice()))?.map(|(_, value)| value);
+        }
+
+        let mut operands = Vec::with_capacity(chain.operands.len());
+        for log_pointer in &chain.operands {
+            let record = self.read_record(path, active_gen, log_pointer)?;
+            seq = record_seq(&record);
+            match Command::from(record.as_slice()) {
+                Command::Increment { key: _, delta } => operands.push(Operand::Increment(delta)),
+                Command::Merge { key: _, operand } => operands.push(Operand::Merge(operand)),
+                _ => {
+                    let message = "merge chain holds a record that is no merge operand";
+                    return Err(Error::new(ErrorKind::InvalidData, message).into());
+                }
+            }
+        }
+
+        let value = fold_operands(key, existing, operands, self.merge_operator.as_deref())?;
+        return Ok(ValueMeta { value, seq });
+    }
+
+    /// Points `key` of `namespace` at its newly appended set record, whose value is in `blob`
+    /// if any.
+    fn index_set(
+        &mut self,
+        namespace: u32,
+        key: Vec<u8>,
+        log_pointer: LogPointer,
+        blob: Option<BlobRef>,
+    ) -> KVResult<()> {
+        self.sets += 1;
+        self.invalidate_cached_value(namespace, &key);
+
+        // Dropped while the record was written.
+        let index = match self.namespaces.get_mut(&namespace) {
+            Some(index) => index,
+            None => {
+                record_dead(&mut self.gen_stats, &log_pointer);
+                return Ok(());
+            }
+        };
+
+        // Writers apply their records in log order, this one is the newest of its key.
+        return index.insert(key, log_pointer, blob, &mut self.gen_stats);
+    }
+
+    /// Adds the newly appended merge operand of `key` of `namespace` to its chain, returns how
+    /// many operands the chain holds.
+    fn index_operand(
+        &mut self,
+        namespace: u32,
+        key: Vec<u8>,
+        log_pointer: LogPointer,
+    ) -> KVResult<usize> {
+        self.sets += 1;
+        self.invalidate_cached_value(namespace, &key);
+
+        match self.namespaces.get_mut(&namespace) {
+            Some(index) => return index.push_operand(key, log_pointer, &mut self.gen_stats),
+            None => {
+                record_dead(&mut self.gen_stats, &log_pointer);
+                return Ok(0);
+            }
+        }
+    }
+
+    /// Whether a chain holds `MAX_MERGE_CHAIN_LEN` operands or more, which a merge then folds.
+    fn has_full_chain(&self) -> bool {
+        return self.namespaces.values().any(|namespace| {
+            namespace
+                .chains
+                .values()
+                .any(|chain| chain.operands.len() >= MAX_MERGE_CHAIN_LEN)
+        });
+    }
+
+    /// Drops `key` of `namespace` from its keydir after its tombstone was appended.
+    fn index_remove(
+        &mut self,
+        namespace: u32,
+        key: &[u8],
+        log_pointer: LogPointer,
+    ) -> KVResult<()> {
+        self.removes += 1;
+        self.invalidate_cached_value(namespace, key);
+
+        let index = match self.namespaces.get_mut(&namespace) {
+            Some(index) => index,
+            None => {
+                record_dead(&mut self.gen_stats, &log_pointer);
+                return Ok(());
+            }
+        };
+
+        index.remove(key, &mut self.gen_stats)?;
+        record_tombstone(&mut self.gen_stats, &log_pointer);
+        return Ok(());
+    }
+
+    /// Drops the cached value of `key`, called whenever the log pointer of `key` changes.
+    fn invalidate_cached_value(&mut self, namespace: u32, key: &[u8]) {
+        if let Some(cache) = self.value_cache.as_mut() {
+            cache.invalidate(&cache_key(namespace, key));
+        }
+    }
+
+    /// Generation files a merge may read, retired files kept for snapshots are left out.
+    fn merge_input_gens(&self, path: &PathBuf) -> KVResult<Vec<u64>> {
+        let gens = get_sorted_gen_list(path)?
+            .into_iter()
+            .filter(|gen| !self.retired_gens.contains(gen))
+            .collect();
+        return Ok(gens);
+    }
+
+    /// Generations up to `active_gen` a merge may pick, with their statistics.
+    fn merge_candidates(&self, path: &PathBuf, active_gen: u64) -> KVResult<Vec<GenerationStats>> {
+        let gens = self.merge_input_gens(path)?;
+        let mut candidates: Vec<GenerationStats> = gens
+            .iter()
+            .filter(|gen| **gen <= active_gen && !self.merging_gens.contains(gen))
+            .map(|gen| {
+                self.gen_stats
+                    .get(gen)
+                    .cloned()
+                    .unwrap_or_else(|| GenerationStats::new(*gen))
+            })
+            .collect();
+        // Tombstones of the oldest file shadow nothing, merging it reclaims them. Files kept
+        // for snapshots count, they are read again if the store is reopened before they go.
+        if let Some(oldest) = candidates.first_mut() {
+            if get_sorted_gen_list(path)?.first() == Some(&oldest.gen) {
+                oldest.dead_bytes += oldest.tombstone_bytes;
+                oldest.tombstone_bytes = 0;
+            }
+        }
+
+        return Ok(candidates);
+    }
+
+    /// Bytes of the values still in use in every blob file, from the blob references of the
+    /// keydirs.
+    fn blob_live_bytes(&self) -> HashMap<u64, u64> {
+        let mut live_bytes = HashMap::new();
+        for namespace in self.namespaces.values() {
+            for blob in namespace.blobs.values() {
+                *live_bytes.entry(blob.file).or_insert(0) += blob.len;
+            }
+        }
+
+        return live_bytes;
+    }
+
+    /// Sealed blob files whose values in use are below `BLOB_FILE_REWRITE_RATIO` of their
+    /// size, a merge moves these values out so the files can be collected.
+    fn sparse_blob_files(&self, path: &PathBuf, blobs: &BlobStore) -> KVResult<HashSet<u64>> {
+        let live_bytes = self.blob_live_bytes();
+        let mut sparse_files = HashSet::new();
+        for file in blobs.sealed_files()? {
+            let file_len = metadata(get_blob_file_dir(file, path))?.len();
+            let file_live_bytes = live_bytes.get(&file).copied().unwrap_or(0);
+            if (file_live_bytes as f64) < file_len as f64 * BLOB_FILE_REWRITE_RATIO {
+                sparse_files.insert(file);
+            }
+        }
+
+        return Ok(sparse_files);
+    }
+
+    /// Stale bytes over all generations.
+    fn uncompacted(&self) -> u64 {
+        self.gen_stats
+            .values()
+            .map(|generation| generation.dead_bytes + generation.tombstone_bytes)
+            .sum()
+    }
+
+    fn read_record(
+        &mut self,
+        path: &PathBuf,
+        active_gen: u64,
+        log_pointer: &LogPointer,
+    ) -> KVResult<Vec<u8>> {
+        match self.reader(path, active_gen, log_pointer.gen)? {
+            LogReader::Active(reader) => {
+                return reader.read_at(log_pointer.pos, log_pointer.len);
+            }
+            LogReader::Sealed(reader) => {
+                return Ok(reader.read_at(log_pointer.pos, log_pointer.len)?.to_vec());
+            }
+        }
+    }
+
+    fn reader(&mut self, path: &PathBuf, active_gen: u64, gen: u64) -> KVResult<&mut LogReader> {
+        let sealed = gen < active_gen;
+
+        // Generations are registered lazily, and remapped once the writer has rotated past them.
+        let needs_reader = match self.readers.get(&gen) {
+            None => true,
+            Some(LogReader::Active(_)) => sealed,
+            Some(LogReader::Sealed(_)) => false,
+        };
+        if needs_reader {
+            let log_path = get_log_file_dir(gen, path);
+            let reader = if sealed {
+                LogReader::Sealed(MmapReader::new(&log_path)?)
+            } else {
+                LogReader::Active(BitcaskReader::new(&log_path)?)
+            };
+            self.readers.insert(gen, reader);
+        }
+
+        return Ok(self.readers.get_mut(&gen).unwrap());
+    }
+}
+
+/// Accounts a record the index now points at.
+fn record_live(gen_stats: &mut HashMap<u64, GenerationStats>, log_pointer: &LogPointer) {
+    let generation = gen_stats
+        .entry(log_pointer.gen)
+        .or_insert_with(|| GenerationStats::new(log_pointer.gen));
+    generation.live_bytes += log_pointer.len;
+    generation.live_keys += 1;
+}
+
+/// Key and value set by `command`, reading the value from its blob file when it has one.
+fn command_value(path: &PathBuf, command: Command) -> KVResult<Option<(Vec<u8>, Vec<u8>)>> {
+    match command {
+        Command::Set { key, value } => return Ok(Some((key, value))),
+        Command::SetBlob { key, blob } => return Ok(Some((key, read_blob(path, &blob)?))),
+        Command::Remove { key: _ } | Command::Batch { count: _ } => return Ok(None),
+        Command::Increment { key: _, delta: _ } | Command::Merge { key: _, operand: _ } => {
+            let message = "value is folded from merge operands";
+            return Err(Error::new(ErrorKind::InvalidData, message).into());
+        }
+    }
+}
+
+/// `command` encoded as a record of namespace `namespace`.
+fn namespaced_record(namespace: u32, command: &Command) -> Vec<u8> {
+    let mut record = command.parse();
+    if namespace != DEFAULT_NAMESPACE {
+        set_record_namespace(&mut record, namespace);
+    }
+
+    return record;
+}
+
+/// Key of the value cache, shared by every namespace.
+fn cache_key(namespace: u32, key: &[u8]) -> Vec<u8> {
+    let mut cache_key = Vec::with_capacity(size_of::<u32>() + key.len());
+    cache_key.extend_from_slice(&namespace.to_le_bytes());
+    cache_key.extend_from_slice(key);
+
+    return cache_key;
+}
+
+/// Accounts a record nothing points at.
+fn record_dead(gen_stats: &mut HashMap<u64, GenerationStats>, log_pointer: &LogPointer) {
+    let generation = gen_stats
+        .entry(log_pointer.gen)
+        .or_insert_with(|| GenerationStats::new(log_pointer.gen));
+    generation.dead_bytes += log_pointer.len;
+}
+
+fn record_tombstone(gen_stats: &mut HashMap<u64, GenerationStats>, log_pointer: &LogPointer) {
+    let generation = gen_stats
+        .entry(log_pointer.gen)
+        .or_insert_with(|| GenerationStats::new(log_pointer.gen));
+    generation.tombstone_bytes += log_pointer.len;
+}
+
+/// Accounts a record the index stopped pointing at.
+fn retire(gen_stats: &mut HashMap<u64, GenerationStats>, log_pointer: &LogPointer) {
+    if let Some(generation) = gen_stats.get_mut(&log_pointer.gen) {
+        generation.live_bytes = generation.live_bytes.saturating_sub(log_pointer.len);
+        generation.live_keys = generation.live_keys.saturating_sub(1);
+        generation.dead_bytes += log_pointer.len;
+    }
+}
+
+fn exceeds_threshold(state: &Mutex<BitcaskState>, compaction_threshold: Option<u64>) -> bool {
+    match compaction_threshold {
+        Some(compaction_threshold) => state.lock().unwrap().uncompacted() >= compaction_threshold,
+        None => false,
+    }
+}
+
+/// What a merge rewrites, settled once its generation is reserved.
+struct MergePlan {
+    merge_gen: u64,
+    /// In ascending order.
+    merged_gens: Vec<u64>,
+    /// Merged generations whose tombstones may still shadow records in the files left behind.
+    tombstone_gens: HashSet<u64>,
+    /// Blob files whose values are moved out.
+    sparse_blob_files: HashSet<u64>,
+    /// Chains touching a merged generation or holding `MAX_MERGE_CHAIN_LEN` operands, up to the
+    /// records written before the merge output.
+    /// Each is written as a set of its folded value, after every other record.
+    folded: Vec<(NamespacedKey, MergeChain)>,
+}
+
+/// Rewrites the live records of the generations picked by `merge_policy`, or of every
+/// generation when there is none, into one new generation and deletes the files it replaces.
+/// Full chains are folded into it as well, even when no generation is picked. Only the index
+/// swap at the end holds the state lock for long, reads and writes carry on
+/// against the old generations while records are copied. The merged files are read a record
+/// at a time and where each copied record came from is kept on disk, so a merge holds no key
+/// in memory but those of removed keys and of chains.
+fn merge_generations(
+    path: &PathBuf,
+    state: &Mutex<BitcaskState>,
+    writer: &GroupCommitWriter,
+    blobs: &BlobStore,
+    context: &CompactionContext,
+    merge_policy: Option<&dyn MergePolicy>,
+    events: &dyn EventListener,
+) -> KVResult<bool> {
+    let start = Instant::now();
+    let merged_gens = {
+        let mut state = state.lock().unwrap();
+
+        let candidates = state.merge_candidates(path, writer.active_gen())?;
+        let merged_gens: HashSet<u64> = match merge_policy {
+            Some(merge_policy) => merge_policy.select(&candidates).into_iter().collect(),
+            None => candidates.iter().map(|generation| generation.gen).collect(),
+        };
+        // With no generation picked, the merge only folds full chains.
+        if merged_gens.is_empty() && !state.has_full_chain() {
+            return Ok(false);
+        }
+        state.merging_gens.extend(merged_gens.iter());
+
+        merged_gens
+    };
+
+    // Reserved without the lock, as it waits for every record written so far to be applied,
+    // the index then holds every record of the merged files.
+    let prepared = writer.reserve_merge_gen().and_then(|merge_gen| {
+        let mut state = state.lock().unwrap();
+        let gens = get_sorted_gen_list(path)?;
+        let sparse_blob_files = state.sparse_blob_files(path, blobs)?;
+
+        // A chain touching a merged file, or a full one, is folded into a set, up to the
+        // records written before the merge output, which the records written since are
+        // applied on top of.
+        let mut folded = Vec::new();
+        for (id, namespace) in &state.namespaces {
+            for (key, chain) in &namespace.chains {
+                let merged = chain.operands.len() >= MAX_MERGE_CHAIN_LEN
+                    || chain
+                        .log_pointers()
+                        .any(|log_pointer| merged_gens.contains(&log_pointer.gen));
+                if !merged {
+                    continue;
+                }
+                let operands = chain
+                    .operands
+                    .iter()
+                    .take_while(|operand| operand.gen < merge_gen)
+                    .cloned()
+                    .collect();
+                let chain = MergeChain {
+                    base: chain.base,
+                    operands,
+                };
+                folded.push(((*id, key.clone()), chain));
+            }
+        }
+
+        // A tombstone shadows records in older files, it is dropped once no file older than
+        // its own is left behind, retired files included.
+        let mut sorted_merged_gens: Vec<u64> = merged_gens.iter().cloned().collect();
+        sorted_merged_gens.sort();
+        let tombstone_gens = sorted_merged_gens
+            .iter()
+            .filter(|gen| {
+                gens.iter()
+                    .any(|older_gen| older_gen < gen && !merged_gens.contains(older_gen))
+            })
+            .cloned()
+            .collect();
+
+        state.merging_gens.insert(merge_gen);
+
+        events.on_event(&BitcaskEvent::MergeStarted {
+            gens: sorted_merged_gens.clone(),
+            merge_gen,
+        });
+
+        return Ok(MergePlan {
+            merge_gen,
+            merged_gens: sorted_merged_gens,
+            tombstone_gens,
+            sparse_blob_files,
+            folded,
+        });
+    });
+    let plan = match prepared {
+        Ok(plan) => plan,
+        Err(err) => {
+            let mut state = state.lock().unwrap();
+            for gen in &merged_gens {
+                state.merging_gens.remove(gen);
+            }
+            return Err(err);
+        }
+    };
+    let merge_gen = plan.merge_gen;
+
+    let mut new_blobs = HashMap::new();
+    let pointers_path = merge_pointers_path(merge_gen, path);
+    let merged = copy_records(path, state, writer, blobs, context, &plan, &mut new_blobs).and_then(
+        |records| {
+            let output = MmapReader::new(&get_log_file_dir(merge_gen, path))?;
+            let old_pointers = MmapReader::new(&pointers_path)?;
+            Ok((records, output, old_pointers))
+        },
+    );
+    // Only a value that made it into the index keeps its new blob file.
+    let release_new_blobs = || {
+        for blob in new_blobs.values() {
+            blobs.release(blob);
+        }
+    };
+
+    let mut state = state.lock().unwrap();
+    for gen in merged_gens.iter().chain(Some(&merge_gen)) {
+        state.merging_gens.remove(gen);
+    }
+    let (records, output, old_pointers) = match merged {
+        Ok(merged) => merged,
+        Err(err) => {
+            release_new_blobs();
+            let _ = remove_file(&pointers_path);
+            return Err(err);
+        }
+    };
+
+    for gen in &merged_gens {
+        state.gen_stats.remove(gen);
+    }
+    state
+        .gen_stats
+        .insert(merge_gen, GenerationStats::new(merge_gen));
+
+    // The output starts with the sequence marker, then holds the records in the order their
+    // old pointers were kept, folded chains last.
+    let buffer = output.as_slice();
+    let mut pos = claimed_record_len(buffer) as usize;
+    let folded_start = records - plan.folded.len();
+    let mut failed = None;
+    for i in 0..records {
+        let len = claimed_record_len(&buffer[pos..]) as usize;
+        let record = &buffer[pos..pos + len];
+        let new_log_pointer = LogPointer::new(merge_gen, pos as u64, len as u64);
+        pos += len;
+        let old_pointer_pos = i * LogPointer::ENCODED_LEN;
+        let log_pointer = LogPointer::decode(&old_pointers.as_slice()[old_pointer_pos..]);
+        let id = record_namespace(record);
+        let key = head_key(record);
+
+        let state = &mut *state;
+        let namespace = state.namespaces.get_mut(&id);
+        // Records overwritten or removed, or whose namespace was dropped, while the merge ran
+        // are dead.
+        let applied = match namespace {
+            _ if is_removal(record) => {
+                record_tombstone(&mut state.gen_stats, &new_log_pointer);
+                continue;
+            }
+            Some(namespace) if i >= folded_start => {
+                let folded = &plan.folded[i - folded_start].1;
+                let unchanged = namespace.chains.get(key).is_some_and(|chain| {
+                    chain.base == folded.base && chain.operands.starts_with(&folded.operands)
+                });
+                if !unchanged {
+                    record_dead(&mut state.gen_stats, &new_log_pointer);
+                    continue;
+                }
+                namespace.fold(key, folded, new_log_pointer, &mut state.gen_stats)
+            }
+            Some(namespace) => match namespace.keys.get(key) {
+                Ok(current) if current != Some(log_pointer) => {
+                    record_dead(&mut state.gen_stats, &new_log_pointer);
+                    continue;
+                }
+                Ok(_) => {
+                    let blob = new_blobs.get(&i).or(namespace.blobs.get(key)).copied();
+                    namespace.insert(key.to_vec(), new_log_pointer, blob, &mut state.gen_stats)
+                }
+                Err(err) => Err(err),
+            },
+            None => {
+                record_dead(&mut state.gen_stats, &new_log_pointer);
+                continue;
+            }
+        };
+        state.invalidate_cached_value(id, key);
+        if let Err(err) = applied {
+            failed = Some(err);
+            break;
+        }
+    }
+
+    release_new_blobs();
+    state.readers.insert(merge_gen, LogReader::Sealed(output));
+
+    // Keys not applied yet still point into the merged generations, they are kept.
+    if let Some(err) = failed {
+        drop(state);
+        drop(old_pointers);
+        let _ = remove_file(&pointers_path);
+        return Err(err);
+    }
+
+    for gen in &merged_gens {
+        state.readers.remove(gen);
+    }
+
+    // Files still read by a snapshot are deleted once the last such snapshot is dropped.
+    let mut removable_gens = Vec::new();
+    for gen in &merged_gens {
+        if state.pinned_gens.contains_key(gen) {
+            state.retired_gens.insert(*gen);
+        } else {
+            removable_gens.push(*gen);
+        }
+    }
+    drop(state);
+
+    drop(old_pointers);
+    remove_file(&pointers_path)?;
+    for gen in removable_gens {
+        remove_file(get_log_file_dir(gen, path))?;
+    }
+
+    events.on_event(&BitcaskEvent::MergeFinished {
+        merge_gen,
+        records,
+        duration: start.elapsed(),
+    });
+
+    return Ok(true);
+}
+
+/// Path of the file a merge into `merge_gen` keeps the old pointers of its records in.
+fn merge_pointers_path(merge_gen: u64, path: &PathBuf) -> PathBuf {
+    return get_log_file_dir(merge_gen, path).with_extension(MERGE_POINTERS_EXTENSION);
+}
+
+/// Writes the records of `plan` to its generation, returns how many. `new_blobs` receives the
+/// blob each relocated value went to by the position of its record.
+#[allow(clippy::too_many_arguments)]
+fn copy_records(
+    path: &PathBuf,
+    state: &Mutex<BitcaskState>,
+    writer: &GroupCommitWriter,
+    blobs: &BlobStore,
+    context: &CompactionContext,
+    plan: &MergePlan,
+    new_blobs: &mut HashMap<usize, BlobRef>,
+) -> KVResult<usize> {
+    // Written aside and renamed once whole, a failed or interrupted merge leaves no
+    // generation behind.
+    let merge_gen = plan.merge_gen;
+    let merge_path = get_log_file_dir(merge_gen, path).with_extension(MERGE_FILE_EXTENSION);
+    let result = write_merge_file(
+        path,
+        state,
+        writer,
+        blobs,
+        context,
+        plan,
+        new_blobs,
+        &merge_path,
+    )
+    .and_then(|records| {
+        rename(&merge_path, get_log_file_dir(merge_gen, path))?;
+        File::open(path.as_path())?.sync_all()?;
+        Ok(records)
+    });
+    if result.is_err() {
+        let _ = remove_file(&merge_path);
+    }
+
+    return result;
+}
+
+/// A merge output being written, along with the side file of the pointers its records were
+/// copied from.
+struct MergeOutput {
+    records: BufWriter<File>,
+    old_pointers: BufWriter<File>,
+    count: usize,
+}
+
+impl MergeOutput {
+    /// Notes that the record just written was copied from `log_pointer`.
+    fn copied_from(&mut self, log_pointer: &LogPointer) -> KVResult<()> {
+        self.old_pointers.write_all(&log_pointer.encode())?;
+        self.count += 1;
+        return Ok(());
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_merge_file(
+    path: &PathBuf,
+    state: &Mutex<BitcaskState>,
+    writer: &GroupCommitWriter,
+    blobs: &BlobStore,
+    context: &CompactionContext,
+    plan: &MergePlan,
+    new_blobs: &mut HashMap<usize, BlobRef>,
+    merge_path: &Path,
+) -> KVResult<usize> {
+    let mut output = MergeOutput {
+        records: BufWriter::new(File::create(merge_path)?),
+        old_pointers: BufWriter::new(File::create(merge_pointers_path(plan.merge_gen, path))?),
+        count: 0,
+    };
+
+    // Records dropped by the merge may hold the highest sequence number handed out, an empty
+    // batch carrying it keeps it from being handed out again once the store is reopened.
+    let mut seq_marker = Command::Batch { count: 0 }.parse();
+    set_record_seq(&mut seq_marker, writer.last_seq());
+    output.records.write_all(&seq_marker)?;
+
+    // Merged generations are only removed once the merge is done, so their records are
+    // copied straight from the files, a chunk at a time and without the state lock. Of the
+    // tombstones of each removed key only the newest is kept.
+    let mut sources = HashMap::new();
+    let mut tombstones = HashMap::new();
+    for gen in &plan.merged_gens {
+        sources.insert(*gen, File::open(get_log_file_dir(*gen, path))?);
+        let source = sources.get_mut(gen).unwrap();
+        let mut scanner = HeadScanner::open(path, *gen, None)?;
+        while let Some((log_pointer, head)) = scanner.next_head()? {
+            let id = record_namespace(&head);
+            let key = head_key(&head);
+            let relocate = {
+                let state = state.lock().unwrap();
+                let namespace = match state.namespaces.get(&id) {
+                    Some(namespace) => namespace,
+                    None => continue,
+                };
+                if namespace.keys.get(key)? == Some(log_pointer)
+                    && !namespace.chains.contains_key(key)
+                {
+                    namespace
+                        .blobs
+                        .get(key)
+                        .is_some_and(|blob| plan.sparse_blob_files.contains(&blob.file))
+                } else {
+                    if is_removal(&head)
+                        && plan.tombstone_gens.contains(gen)
+                        && namespace.needs_tombstone(key)?
+                    {
+                        tombstones.insert((id, key.to_vec()), log_pointer);
+                    }
+                    continue;
+                }
+            };
+
+            if relocate {
+                let mut record = vec![0; log_pointer.len as usize];
+                source.seek(SeekFrom::Start(log_pointer.pos))?;
+                source.read_exact(&mut record)?;
+                if let Some((new_record, new_blob)) = relocate_blob(path, blobs, id, &record)? {
+                    new_blobs.insert(output.count, new_blob);
+                    record = new_record;
+                }
+                context.throttle(record.len() as u64);
+                output.records.write_all(&record)?;
+            } else {
+                copy_record(source, &log_pointer, context, &mut output.records)?;
+            }
+            output.copied_from(&log_pointer)?;
+        }
+    }
+
+    for log_pointer in tombstones.values() {
+        let source = sources.get_mut(&log_pointer.gen).unwrap();
+        copy_record(source, log_pointer, context, &mut output.records)?;
+        output.copied_from(log_pointer)?;
+    }
+
+    for ((id, key), chain) in &plan.folded {
+        let folded = {
+            let mut state = state.lock().unwrap();
+            state.fold_chain(path, writer.active_gen(), key, chain)?
+        };
+        let command = Command::Set {
+            key: key.clone(),
+            value: folded.value,
+        };
+        let mut record = namespaced_record(*id, &command);
+        set_record_seq(&mut record, folded.seq);
+        context.throttle(record.len() as u64);
+        output.records.write_all(&record)?;
+        // Folded from a whole chain, which is kept in `plan` instead.
+        output.copied_from(&LogPointer::new(0, 0, 0))?;
+    }
+
+    if !new_blobs.is_empty() {
+        blobs.sync()?;
+    }
+    output.records.flush()?;
+    output.records.get_ref().sync_all()?;
+    output.old_pointers.flush()?;
+
+    return Ok(output.count);
+}
+
+/// Copies the record at `log_pointer` from `source`, its generation file, to `merge_writer` in
+/// chunks of `STREAM_CHUNK_LEN` bytes, each throttled through `context`.
+fn copy_record(
+    source: &mut File,
+    log_pointer: &LogPointer,
+    context: &CompactionContext,
+    merge_writer: &mut dyn Write,
+) -> KVResult<()> {
+    source.seek(SeekFrom::Start(log_pointer.pos))?;
+    let mut reader = source.take(log_pointer.len);
+    let mut buffer = vec![0; (STREAM_CHUNK_LEN as u64).min(log_pointer.len) as usize];
+
+    while reader.limit() > 0 {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => {
+                let message = format!(
+                    "record at gen {} pos {} is cut short",
+                    log_pointer.gen, log_pointer.pos
+                );
+                return Err(Error::new(ErrorKind::UnexpectedEof, message).into());
+            }
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        context.throttle(read as u64);
+        merge_writer.write_all(&buffer[..read])?;
+    }
+
+    return Ok(());
+}
+
+/// The blob set `record` of `namespace` with its value copied to the blob file being written,
+/// and the new blob. `None` when the value is gone, its key was overwritten since and its blob
+/// file collected.
+fn relocate_blob(
+    path: &PathBuf,
+    blobs: &BlobStore,
+    namespace: u32,
+    record: &[u8],
+) -> KVResult<Option<(Vec<u8>, BlobRef)>> {
+    let (key, blob) = match Command::from(record) {
+        Command::SetBlob { key, blob } => (key, blob),
+        _ => return Ok(None),
+    };
+    let value = match read_blob(path, &blob) {
+        Ok(value) => value,
+        Err(KVError::IOError(err)) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let new_blob = blobs.write(&value)?;
+    let command = Command::SetBlob {
+        key,
+        blob: new_blob,
+    };
+    let mut new_record = namespaced_record(namespace, &command);
+    set_record_seq(&mut new_record, record_seq(record));
+
+    return Ok(Some((new_record, new_blob)));
+}
+
+/// Removes the output of merges interrupted before they were done and the values spooled by
+/// writes that never finished.
+fn remove_unfinished_files(path: &PathBuf) -> KVResult<()> {
+    for dir_entry in read_dir(path)? {
+        let entry_path = dir_entry?.path();
+        let extension = entry_path.extension();
+        if entry_path.is_file()
+            && (extension == Some(MERGE_FILE_EXTENSION.as_ref())
+                || extension == Some(MERGE_POINTERS_EXTENSION.as_ref())
+                || extension == Some(SPOOL_FILE_EXTENSION.as_ref()))
+        {
+            remove_file(entry_path)?;
+        }
+    }
+
+    return Ok(());
+}
+
+/// Copies the `len` bytes of a value from `reader` to a new file at `spool_path`, returns a
+/// reader over them.
+fn spool_value(spool_path: &Path, reader: &mut dyn Read, len: u64) -> KVResult<BufReader<File>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(spool_path)?;
+    let mut writer = BufWriter::new(file);
+    copy_value(reader, &mut writer, len, &mut crc32fast::Hasher::new())?;
+
+    let mut file = writer.into_inner().map_err(|err| err.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+
+    return Ok(BufReader::new(file));
+}
+
+pub(crate) fn get_sorted_gen_list(path: &PathBuf) -> KVResult<Vec<u64>> {
+    let mut entries: Vec<u64> = Vec::new();
+
+    for dir_entry in read_dir(path)? {
+        let entry_path = dir_entry?.path();
+        if !entry_path.is_file() || entry_path.extension() != Some("log".as_ref()) {
+            continue;
+        }
+
+        let gen = entry_path
+            .file_stem()
+            .and_then(|file_stem| file_stem.to_str())
+            .and_then(|file_stem| file_stem.parse::<u64>().ok());
+        if let Some(gen) = gen {
+            entries.push(gen);
+        }
+    }
+
+    entries.sort();
+
+    return Ok(entries);
+}
+
+/// Adds the records of generation `gen` to the keydirs of their namespaces, returns the highest
+/// sequence number in it and whether the file ends with a whole record. Records of dropped
+/// namespaces and of batches cut short are dead.
+fn load_index(
+    gen: u64,
+    path: &PathBuf,
+    readers: &mut HashMap<u64, LogReader>,
+    namespaces: &mut HashMap<u32, NamespaceIndex>,
+    gen_stats: &mut HashMap<u64, GenerationStats>,
+    events: &dyn EventListener,
+) -> KVResult<(u64, bool)> {
+    let log_path = get_log_file_dir(gen, path);
+    let reader = MmapReader::new(&log_path)?;
+
+    let buffer = reader.as_slice();
+    gen_stats.insert(gen, GenerationStats::new(gen));
+
+    let positions = record_positions(buffer);
+    let end = positions.last().map_or(0, |(pos, len)| pos + len);
+    if end < buffer.len() {
+        check_tail(gen, &buffer[end..], end)?;
+        events.on_event(&BitcaskEvent::RecordsSkipped {
+            gen,
+            pos: end as u64,
+            bytes: (buffer.len() - end) as u64,
+        });
+    }
+
+    let mut clean = end == buffer.len();
+    let mut last_seq = 0;
+    for (i, (current_pos, total_length)) in positions.iter().cloned().enumerate() {
+        let bytes = &buffer[current_pos..current_pos + total_length];
+        let log_pointer = LogPointer::new(gen, current_pos as u64, total_length as u64);
+        last_seq = last_seq.max(record_seq(bytes));
+
+        let command = Command::from(bytes);
+        if let Command::Batch { count } = command {
+            // The sequence number marker of a merge output.
+            if count == 0 {
+                continue;
+            }
+            if positions.len() - i - 1 < count as usize {
+                // The batch was being written when the store went down, it is the end of
+                // the file.
+                for (pos, len) in &positions[i..] {
+                    record_dead(gen_stats, &LogPointer::new(gen, *pos as u64, *len as u64));
+                }
+                clean = false;
+                break;
+            }
+            record_dead(gen_stats, &log_pointer);
+            continue;
+        }
+
+        let index = match namespaces.get_mut(&record_namespace(bytes)) {
+            Some(index) => index,
+            None => {
+                record_dead(gen_stats, &log_pointer);
+                continue;
+            }
+        };
+        match command {
+            Command::Set { key, value: _ } => {
+                index.insert(key, log_pointer, None, gen_stats)?;
+            }
+            Command::SetBlob { key, blob } => {
+                index.insert(key, log_pointer, Some(blob), gen_stats)?;
+            }
+            Command::Increment { key, delta: _ } | Command::Merge { key, operand: _ } => {
+                index.push_operand(key, log_pointer, gen_stats)?;
+            }
+            Command::Remove { key } => {
+                // A merge copies a tombstone it keeps into a file loaded after the ones left
+                // behind, which may hold newer records of the key.
+                let seq = record_seq(bytes);
+                let loaded_seq =
+                    |log_pointer: &LogPointer| loaded_record_seq(log_pointer, gen, buffer, readers);
+                let newer = match index.keys.get(&key)? {
+                    Some(current) => loaded_seq(&current)? > seq,
+                    None => false,
+                };
+                if newer {
+                    index.remove_before(&key, seq, loaded_seq, gen_stats)?;
+                } else {
+                    index.remove(&key, gen_stats)?;
+                }
+                record_tombstone(gen_stats, &log_pointer);
+            }
+            Command::Batch { count: _ } => {}
+        }
+    }
+
+    readers.insert(gen, LogReader::Sealed(reader));
+
+    return Ok((last_seq, clean));
+}
+
+/// Sequence number of the record at `log_pointer` while generation `gen`, whose bytes are
+/// `buffer`, is loaded after the generations in `readers`.
+fn loaded_record_seq(
+    log_pointer: &LogPointer,
+    gen: u64,
+    buffer: &[u8],
+    readers: &HashMap<u64, LogReader>,
+) -> KVResult<u64> {
+    let record = match readers.get(&log_pointer.gen) {
+        _ if log_pointer.gen == gen => &buffer[log_pointer.pos as usize..],
+        Some(LogReader::Sealed(reader)) => reader.read_at(log_pointer.pos, log_pointer.len)?,
+        _ => return Err(Error::new(ErrorKind::NotFound, "generation is not loaded").into()),
+    };
+    return Ok(record_seq(record));
+}
+
+/// Fails for bytes `tail` following the last good record at `end` of generation `gen`, unless
+/// they are left by an interrupted write. A merge would drop the records after a corrupt one,
+/// so those files are left to `kvs repair`.
+fn check_tail(gen: u64, tail: &[u8], end: usize) -> KVResult<()> {
+    let defect = match check_record(tail) {
+        Ok(_) => return Ok(()),
+        Err(defect) => defect,
+    };
+
+    let whole_magic = tail.len() >= RECORD_MAGIC.len();
+    if end == 0 && (defect == RecordDefect::Version || defect == RecordDefect::Magic && whole_magic)
+    {
+        let message = format!(
+            "generation {} is not in record format version {}",
+            gen, RECORD_FORMAT_VERSION
+        );
+        return Err(Error::new(ErrorKind::InvalidData, message).into());
+    }
+    if next_record_start(tail).is_some() {
+        let message = format!(
+            "generation {} is corrupt at byte {} ({}), run kvs repair",
+            gen, end, defect
+        );
+        return Err(Error::new(ErrorKind::InvalidData, message).into());
+    }
+
+    return Ok(());
+}
+
+/// Offset and length of every record in `buffer`, holding the content of one generation file.
+/// Stops at a tail too short to hold the record it announces, as left by an interrupted write,
+/// and at the first record failing `check_record`.
+pub(crate) fn record_positions(buffer: &[u8]) -> Vec<(usize, usize)> {
+    let mut positions = Vec::new();
+    let mut current_pos = 0;
+
+    while let Ok(total_length) = check_record(&buffer[current_pos..]) {
+        positions.push((current_pos, total_length));
+        current_pos += total_length;
+    }
+
+    return positions;
+}
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn get_log_file_dir(gen: u64, dir: &PathBuf) -> PathBuf {
+    let log_file_name = format!("{}.log", gen);
+    let log_path = dir.join(Path::new(&log_file_name));
+    return log_path;
+}
+
+#[test]
+fn bitcask_read_from_active_file() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+
+    store_engine
+        .set(b"key1".to_vec(), b"value1".to_vec())
+        .unwrap();
+
+    let actual_value = store_engine.get(b"key1".to_vec()).unwrap();
+    assert_eq!(Some(b"value1".to_vec()), actual_value);
+}
+
+#[test]
+fn bitcask_read_from_sealed_file() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine
+            .set(b"key1".to_vec(), b"value1".to_vec())
+            .unwrap();
+        store_engine
+            .set(b"key2".to_vec(), b"value2".to_vec())
+            .unwrap();
+        store_engine.remove(b"key2".to_vec()).unwrap();
+        store_engine
+            .set(
+                b"filler".to_vec(),
+                vec![b'v'; MAX_ACTIVE_LOG_FILE_SIZE as usize],
+            )
+            .unwrap();
+    }
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert!(store_engine.writer.active_gen() > 0);
+
+    let actual_value = store_engine.get(b"key1".to_vec()).unwrap();
+    assert_eq!(Some(b"value1".to_vec()), actual_value);
+    assert!(store_engine.get(b"key2".to_vec()).is_err());
+}
+
+#[test]
+fn bitcask_reopen_reuse_last_file() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    for i in 0..3 {
+        let store_engine = Bitcask::open(&path).unwrap();
+        assert_eq!(0, store_engine.writer.active_gen());
+        store_engine
+            .set(format!("key{}", i).into_bytes(), b"value".to_vec())
+            .unwrap();
+    }
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert_eq!(vec![0], get_sorted_gen_list(&path).unwrap());
+    for i in 0..3 {
+        assert_eq!(
+            Some(b"value".to_vec()),
+            store_engine.get(format!("key{}", i).into_bytes()).unwrap()
+        );
+    }
+}
+
+#[test]
+fn bitcask_rotate_active_file() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+    let value = vec![b'v'; 4096];
+
+    for i in 0..(MAX_ACTIVE_LOG_FILE_SIZE / 4096 + 1) {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), value.clone())
+            .unwrap();
+    }
+
+    assert!(store_engine.writer.active_gen() > 0);
+    assert_eq!(Some(value), store_engine.get(b"key0".to_vec()).unwrap());
+}
+
+#[test]
+fn bitcask_value_cache_hit_and_invalidate() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let options = BitcaskOptions {
+        value_cache_capacity: Some(1024),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&temp_dir.path().to_path_buf(), options).unwrap();
+
+    store_engine
+        .set(b"key1".to_vec(), b"value1".to_vec())
+        .unwrap();
+    store_engine.get(b"key1".to_vec()).unwrap();
+    store_engine.get(b"key1".to_vec()).unwrap();
+
+    let stats = store_engine.stats();
+    assert_eq!(1, stats.cache_hits);
+    assert_eq!(1, stats.cache_misses);
+
+    store_engine
+        .set(b"key1".to_vec(), b"value2".to_vec())
+        .unwrap();
+    assert_eq!(
+        Some(b"value2".to_vec()),
+        store_engine.get(b"key1".to_vec()).unwrap()
+    );
+
+    store_engine.remove(b"key1".to_vec()).unwrap();
+    assert!(store_engine.get(b"key1".to_vec()).is_err());
+    assert_eq!(0, store_engine.stats().cache_size);
+}
+
+#[test]
+fn bitcask_concurrent_sync_writes() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let options = BitcaskOptions {
+        sync_writes: true,
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&temp_dir.path().to_path_buf(), options).unwrap();
+
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let store_engine = store_engine.clone();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    let key = format!("key{}-{}", thread_id, i).into_bytes();
+                    store_engine.set(key, vec![b'v'; 64]).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+    for thread_id in 0..8 {
+        for i in 0..50 {
+            let key = format!("key{}-{}", thread_id, i).into_bytes();
+            assert_eq!(Some(vec![b'v'; 64]), store_engine.get(key).unwrap());
+        }
+    }
+}
+
+#[test]
+fn bitcask_concurrent_set_and_remove_in_log_order() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let store_engine = store_engine.clone();
+            std::thread::spawn(move || {
+                for i in 0..100 {
+                    if (thread_id + i) % 2 == 0 {
+                        store_engine.set(b"key".to_vec(), vec![b'v'; 16]).unwrap();
+                    } else {
+                        store_engine.remove(b"key".to_vec()).unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+
+    // The index is rebuilt from the log on open, it must agree with the one kept in memory.
+    let in_memory = store_engine.get(b"key".to_vec()).ok().flatten();
+    drop(store_engine);
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert_eq!(in_memory, store_engine.get(b"key".to_vec()).ok().flatten());
+}
+
+#[test]
+fn bitcask_compact_sealed_generations() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = BitcaskOptions {
+        compaction_threshold: None,
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+
+    for i in 0..100 {
+        store_engine
+            .set(b"key1".to_vec(), format!("value{}", i).into_bytes())
+            .unwrap();
+        store_engine
+            .set(b"key2".to_vec(), format!("value{}", i).into_bytes())
+            .unwrap();
+    }
+    store_engine.remove(b"key2".to_vec()).unwrap();
+    store_engine.compact().unwrap();
+
+    assert_eq!(1, store_engine.stats().compactions);
+    assert_eq!(0, store_engine.state.lock().unwrap().uncompacted());
+    assert_eq!(2, get_sorted_gen_list(&path).unwrap().len());
+    assert_eq!(
+        Some(b"value99".to_vec()),
+        store_engine.get(b"key1".to_vec()).unwrap()
+    );
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert_eq!(
+        Some(b"value99".to_vec()),
+        store_engine.get(b"key1".to_vec()).unwrap()
+    );
+    assert!(store_engine.get(b"key2".to_vec()).is_err());
+}
+
+#[test]
+fn bitcask_background_compaction_on_threshold() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let options = BitcaskOptions {
+        compaction_threshold: Some(1024),
+        compaction_bytes_per_sec: Some(1024 * 1024),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&temp_dir.path().to_path_buf(), options).unwrap();
+
+    store_engine.pause_compaction();
+    for i in 0..100 {
+        store_engine
+            .set(b"key1".to_vec(), format!("value{}", i).into_bytes())
+            .unwrap();
+    }
+    store_engine.wait_for_compaction().unwrap();
+    assert_eq!(0, store_engine.stats().compactions);
+
+    store_engine.resume_compaction();
+    store_engine.wait_for_compaction().unwrap();
+
+    assert!(store_engine.stats().compactions >= 1);
+    assert_eq!(
+        Some(b"value99".to_vec()),
+        store_engine.get(b"key1".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_compaction_request_only_when_policy_selects() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = BitcaskOptions {
+        compaction_threshold: Some(1),
+        merge_policy: Arc::new(FixedMergePolicy(vec![])),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    store_engine.pause_compaction();
+
+    for i in 0..10 {
+        let key = format!("key{}", i).into_bytes();
+        store_engine.set(key.clone(), b"value".to_vec()).unwrap();
+        store_engine.remove(key).unwrap();
+    }
+    assert!(store_engine.stats().uncompacted > 0);
+    assert!(!store_engine.compactor.is_requested());
+    drop(store_engine);
+
+    let options = BitcaskOptions {
+        compaction_threshold: Some(1),
+        merge_policy: Arc::new(FixedMergePolicy(vec![0])),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    store_engine.pause_compaction();
+    store_engine
+        .set(b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+    assert!(store_engine.compactor.is_requested());
+}
+
+#[test]
+fn bitcask_merge_policy_keep_tombstones_of_partial_merge() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        for i in 0..50 {
+            store_engine
+                .set(format!("key{}", i).into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+        store_engine
+            .set(b"gone".to_vec(), b"value".to_vec())
+            .unwrap();
+    }
+    seal_last_generation(&path);
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.remove(b"gone".to_vec()).unwrap();
+        for i in 0..100 {
+            store_engine
+                .set(b"hot".to_vec(), format!("value{}", i).into_bytes())
+                .unwrap();
+        }
+    }
+    seal_last_generation(&path);
+
+    let options = BitcaskOptions {
+        compaction_threshold: Some(1),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    {
+        let state = store_engine.state.lock().unwrap();
+        assert_eq!(50, state.gen_stats[&0].live_keys);
+        assert_eq!(1, state.gen_stats[&1].live_keys);
+        assert!(state.gen_stats[&1].fragmentation() > 0.9);
+    }
+
+    store_engine
+        .set(b"trigger".to_vec(), b"value".to_vec())
+        .unwrap();
+    store_engine.wait_for_compaction().unwrap();
+
+    assert_eq!(1, store_engine.stats().compactions);
+    let gens = get_sorted_gen_list(&path).unwrap();
+    assert!(gens.contains(&0));
+    assert!(!gens.contains(&1));
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert!(store_engine.get(b"gone".to_vec()).is_err());
+    assert_eq!(
+        Some(b"value99".to_vec()),
+        store_engine.get(b"hot".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_stats_count_keys_and_operations() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+
+    store_engine
+        .set(b"key1".to_vec(), b"value1".to_vec())
+        .unwrap();
+    store_engine
+        .set(b"key1".to_vec(), b"value2".to_vec())
+        .unwrap();
+    store_engine
+        .set(b"key2".to_vec(), b"value1".to_vec())
+        .unwrap();
+    store_engine.remove(b"key2".to_vec()).unwrap();
+    store_engine.get(b"key1".to_vec()).unwrap();
+
+    let stats = store_engine.stats();
+    assert_eq!(1, stats.key_count);
+    assert_eq!((1, 3, 1), (stats.gets, stats.sets, stats.removes));
+    assert_eq!(1, stats.generations.len());
+
+    let generation = &stats.generations[0];
+    assert_eq!(1, generation.live_keys);
+    assert_eq!(generation.file_size, generation.total_bytes());
+    assert!(generation.tombstone_bytes > 0);
+    assert_eq!(
+        generation.dead_bytes + generation.tombstone_bytes,
+        stats.uncompacted
+    );
+    assert!(stats.index_memory_bytes > 0);
+}
+
+#[test]
+fn bitcask_metrics_record_operations() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+
+    store_engine
+        .set(b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+    store_engine.get(b"key".to_vec()).unwrap();
+    assert!(store_engine.get(b"missing".to_vec()).is_err());
+    store_engine.remove(b"key".to_vec()).unwrap();
+    store_engine.increment(b"counter".to_vec(), 1).unwrap();
+    // No merge operator is registered.
+    assert!(store_engine.merge(b"list".to_vec(), b"a".to_vec()).is_err());
+
+    let metrics = &store_engine.metrics;
+    assert_eq!(1, metrics.set_duration.count());
+    assert_eq!(2, metrics.get_duration.count());
+    assert_eq!(1, metrics.remove_duration.count());
+    assert_eq!(1, metrics.increment_duration.count());
+    assert_eq!(1, metrics.merge_errors.load(Ordering::Relaxed));
+    assert_eq!(0, metrics.set_errors.load(Ordering::Relaxed));
+    assert_eq!(0, metrics.get_errors.load(Ordering::Relaxed));
+    assert!(metrics.bytes_written.load(Ordering::Relaxed) > 0);
+    assert!(metrics.bytes_read.load(Ordering::Relaxed) > 0);
+
+    let text = store_engine.render_metrics();
+    assert!(text.contains("kvs_operation_duration_seconds_count{op=\"get\"} 2\n"));
+    assert!(text.contains("kvs_errors_total{op=\"get\"} 0\n"));
+}
+
+#[test]
+fn bitcask_events_recovery_and_merge() {
+    use crate::storage::bitcask::events::CollectEvents;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine
+            .set(b"key".to_vec(), b"value".to_vec())
+            .unwrap();
+    }
+    let log_len = std::fs::metadata(get_log_file_dir(0, &path)).unwrap().len();
+    // A record header cut short by a crash.
+    OpenOptions::new()
+        .append(true)
+        .open(get_log_file_dir(0, &path))
+        .unwrap()
+        .write_all(&[40, 0, 0])
+        .unwrap();
+
+    let events = Arc::new(CollectEvents::default());
+    let options = BitcaskOptions {
+        event_listener: events.clone(),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"key".to_vec()).unwrap()
+    );
+    store_engine.compact().unwrap();
+
+    let events = events.0.lock().unwrap();
+    assert_eq!(
+        BitcaskEvent::RecordsSkipped {
+            gen: 0,
+            pos: log_len,
+            bytes: 3
+        },
+        events[0]
+    );
+    assert!(matches!(
+        events[1],
+        BitcaskEvent::Opened {
+            generations: 1,
+            keys: 1,
+            ..
+        }
+    ));
+    assert!(events.contains(&BitcaskEvent::MergeStarted {
+        gens: vec![0, 1],
+        merge_gen: 2
+    }));
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, BitcaskEvent::MergeFinished { records: 1, .. })));
+}
+
+#[test]
+fn bitcask_subscribe_ordered_changes() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+    let receiver = store_engine.subscribe(None).unwrap();
+
+    let handles: Vec<_> = (0..4)
+        .map(|thread| {
+            let store_engine = store_engine.clone();
+            std::thread::spawn(move || {
+                for i in 0..25 {
+                    let key = format!("key{}", thread).into_bytes();
+                    store_engine
+                        .set(key, format!("{}", i).into_bytes())
+                        .unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    store_engine.remove(b"key0".to_vec()).unwrap();
+
+    let events: Vec<ChangeEvent> = receiver.try_iter().collect();
+    let seqs: Vec<u64> = events.iter().map(|event| event.seq).collect();
+    assert_eq!((1..=101).collect::<Vec<u64>>(), seqs);
+    for thread in 0..4 {
+        let key = format!("key{}", thread).into_bytes();
+        let values: Vec<Vec<u8>> = events
+            .iter()
+            .filter(|event| event.key == key && event.value.is_some())
+            .map(|event| event.value.clone().unwrap())
+            .collect();
+        let expected: Vec<Vec<u8>> = (0..25).map(|i| format!("{}", i).into_bytes()).collect();
+        assert_eq!(expected, values);
+    }
+    assert_eq!(
+        ChangeEvent {
+            seq: 101,
+            namespace: DEFAULT_NAMESPACE,
+            key: b"key0".to_vec(),
+            value: None,
+            operand: false,
+        },
+        events[100]
+    );
+}
+
+#[test]
+fn bitcask_subscribe_resume_from_logs() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        for i in 0..3 {
+            store_engine
+                .set(format!("key{}", i).into_bytes(), b"value".to_vec())
+                .unwrap();
+        }
+    }
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    store_engine.remove(b"key0".to_vec()).unwrap();
+    let receiver = store_engine.subscribe(Some(2)).unwrap();
+    store_engine
+        .set(b"key3".to_vec(), b"value".to_vec())
+        .unwrap();
+
+    let events: Vec<(u64, Vec<u8>, bool)> = receiver
+        .try_iter()
+        .map(|event| (event.seq, event.key, event.value.is_some()))
+        .collect();
+    assert_eq!(
+        vec![
+            (2, b"key1".to_vec(), true),
+            (3, b"key2".to_vec(), true),
+            (4, b"key0".to_vec(), false),
+            (5, b"key3".to_vec(), true),
+        ],
+        events
+    );
+}
+
+#[test]
+fn bitcask_watch_key_and_prefix() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+    let key_receiver = store_engine
+        .watch(KeyFilter::Key(b"config".to_vec()))
+        .unwrap();
+    let prefix_receiver = store_engine
+        .watch(KeyFilter::Prefix(b"config/".to_vec()))
+        .unwrap();
+
+    let writer = store_engine.clone();
+    std::thread::spawn(move || {
+        writer.set(b"other".to_vec(), b"value".to_vec()).unwrap();
+        writer.set(b"config/a".to_vec(), b"1".to_vec()).unwrap();
+        writer.set(b"config".to_vec(), b"2".to_vec()).unwrap();
+        writer.remove(b"config/a".to_vec()).unwrap();
+    });
+
+    let timeout = std::time::Duration::from_secs(5);
+    let event = key_receiver.recv_timeout(timeout).unwrap();
+    assert_eq!(
+        (b"config".to_vec(), Some(b"2".to_vec())),
+        (event.key, event.value)
+    );
+
+    let first = prefix_receiver.recv_timeout(timeout).unwrap();
+    let second = prefix_receiver.recv_timeout(timeout).unwrap();
+    assert_eq!(
+        vec![
+            (b"config/a".to_vec(), Some(b"1".to_vec())),
+            (b"config/a".to_vec(), None)
+        ],
+        vec![(first.key, first.value), (second.key, second.value)]
+    );
+    assert!(key_receiver.try_recv().is_err());
+}
+
+#[test]
+fn bitcask_seq_survive_reopen_and_compaction() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.set(b"key1".to_vec(), b"a".to_vec()).unwrap();
+        store_engine.set(b"key2".to_vec(), b"b".to_vec()).unwrap();
+        store_engine.set(b"key1".to_vec(), b"c".to_vec()).unwrap();
+        assert_eq!(
+            Some(ValueMeta {
+                value: b"c".to_vec(),
+                seq: 3
+            }),
+            store_engine.get_with_meta(b"key1".to_vec()).unwrap()
+        );
+    }
+
+    {
+        // The newest record is a tombstone the compaction drops.
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.remove(b"key2".to_vec()).unwrap();
+        store_engine.compact().unwrap();
+        assert_eq!(0, tombstones_on_disk(&path));
+    }
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    store_engine.set(b"key3".to_vec(), b"d".to_vec()).unwrap();
+
+    let seq_of = |key: &[u8]| {
+        store_engine
+            .get_with_meta(key.to_vec())
+            .unwrap()
+            .map(|meta| meta.seq)
+    };
+    assert_eq!(Some(3), seq_of(b"key1"));
+    assert_eq!(Some(5), seq_of(b"key3"));
+    assert!(store_engine.get_with_meta(b"key2".to_vec()).is_err());
+}
+
+#[test]
+fn bitcask_snapshot_pin_merged_generations() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+
+    for i in 0..10 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), b"old".to_vec())
+            .unwrap();
+    }
+    let snapshot = store_engine.snapshot();
+
+    store_engine.set(b"key0".to_vec(), b"new".to_vec()).unwrap();
+    store_engine.remove(b"key1".to_vec()).unwrap();
+    store_engine
+        .set(b"key10".to_vec(), b"new".to_vec())
+        .unwrap();
+    store_engine.compact().unwrap();
+
+    assert!(get_log_file_dir(0, &path).exists());
+    assert_eq!(
+        Some(b"old".to_vec()),
+        snapshot.get(b"key0".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"old".to_vec()),
+        snapshot.get(b"key1".to_vec()).unwrap()
+    );
+    assert!(snapshot.get(b"key10".to_vec()).is_err());
+
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = snapshot.iter().map(|entry| entry.unwrap()).collect();
+    assert_eq!(10, entries.len());
+    assert!(entries.iter().all(|(_, value)| value == b"old"));
+    // Written in key order, so log order is key order too.
+    assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+    // The next merge must not pick up the file kept for the snapshot.
+    store_engine.compact().unwrap();
+    assert!(get_log_file_dir(0, &path).exists());
+
+    drop(snapshot);
+    assert!(!get_log_file_dir(0, &path).exists());
+    assert_eq!(
+        Some(b"new".to_vec()),
+        store_engine.get(b"key0".to_vec()).unwrap()
+    );
+    assert!(store_engine.get(b"key1".to_vec()).is_err());
+}
+
+#[test]
+fn bitcask_snapshot_keep_only_changed_entries() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+
+    for i in 0..100 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), b"old".to_vec())
+            .unwrap();
+    }
+    let snapshot = store_engine.snapshot();
+    store_engine.set(b"key0".to_vec(), b"new".to_vec()).unwrap();
+    store_engine
+        .set(b"key0".to_vec(), b"newer".to_vec())
+        .unwrap();
+    store_engine.remove(b"key1".to_vec()).unwrap();
+    store_engine.compact().unwrap();
+
+    // Merged keys changed their pointers too.
+    let kept_entries = |state: &BitcaskState| {
+        let index = &state.namespaces[&DEFAULT_NAMESPACE];
+        index.views.get(&snapshot.id).map(|view| view.entries.len())
+    };
+    assert_eq!(Some(100), kept_entries(&store_engine.state.lock().unwrap()));
+    assert_eq!(100, snapshot.len());
+    assert_eq!(
+        Some(b"old".to_vec()),
+        snapshot.get(b"key0".to_vec()).unwrap()
+    );
+    let values: Vec<Vec<u8>> = snapshot.iter().map(|entry| entry.unwrap().1).collect();
+    assert_eq!(vec![b"old".to_vec(); 100], values);
+
+    let id = snapshot.id;
+    drop(snapshot);
+    let state = store_engine.state.lock().unwrap();
+    assert!(!state.namespaces[&DEFAULT_NAMESPACE].views.contains_key(&id));
+    drop(state);
+    let merge_leftovers = read_dir(&path)
+        .unwrap()
+        .any(|entry| entry.unwrap().path().extension() == Some(MERGE_POINTERS_EXTENSION.as_ref()));
+    assert!(!merge_leftovers);
+
+    // Without a merge only the keys written are kept.
+    let snapshot = store_engine.snapshot();
+    store_engine.set(b"key2".to_vec(), b"new".to_vec()).unwrap();
+    store_engine.remove(b"key3".to_vec()).unwrap();
+    store_engine
+        .set(b"key2".to_vec(), b"newer".to_vec())
+        .unwrap();
+    let state = store_engine.state.lock().unwrap();
+    assert_eq!(
+        2,
+        state.namespaces[&DEFAULT_NAMESPACE].views[&snapshot.id]
+            .entries
+            .len()
+    );
+}
+
+#[test]
+fn bitcask_tombstone_kept_while_retired_file_on_disk() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine
+            .set(b"key".to_vec(), b"value".to_vec())
+            .unwrap();
+        let snapshot = store_engine.snapshot();
+
+        // Generation 0 is merged and retired, but stays on disk for the snapshot.
+        store_engine.compact().unwrap();
+        assert!(get_log_file_dir(0, &path).exists());
+
+        store_engine.remove(b"key".to_vec()).unwrap();
+        store_engine.compact().unwrap();
+        assert_eq!(1, tombstones_on_disk(&path));
+
+        // The store goes down before the snapshot lets go of the retired file.
+        let retired = std::fs::read(get_log_file_dir(0, &path)).unwrap();
+        drop(snapshot);
+        drop(store_engine);
+        std::fs::write(get_log_file_dir(0, &path), retired).unwrap();
+    }
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert!(store_engine.get(b"key".to_vec()).is_err());
+}
+
+#[test]
+fn bitcask_backup_and_restore() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().join("store");
+    let store_engine = Bitcask::open(&path).unwrap();
+
+    for i in 0..100 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), vec![b'v'; 20 * 1024])
+            .unwrap();
+    }
+    store_engine.remove(b"key0".to_vec()).unwrap();
+    assert!(store_engine.writer.active_gen() > 0);
+
+    // A record still being appended to the active file is left out.
+    OpenOptions::new()
+        .append(true)
+        .open(get_log_file_dir(store_engine.writer.active_gen(), &path))
+        .unwrap()
+        .write_all(&[60, 0, 0, 0])
+        .unwrap();
+
+    let backup_dir = temp_dir.path().join("backup");
+    let archive = temp_dir.path().join("backup.kvs");
+    store_engine.backup(&backup_dir).unwrap();
+    store_engine.backup_archive(&archive).unwrap();
+    assert!(store_engine.backup(&backup_dir).is_err());
+
+    for (backup, restored) in [(backup_dir, "from_dir"), (archive, "from_archive")] {
+        let restored = temp_dir.path().join(restored);
+        crate::storage::bitcask::backup::restore(&backup, &restored).unwrap();
+
+        let restored_engine = Bitcask::open(&restored).unwrap();
+        assert_eq!(99, restored_engine.stats().key_count);
+        assert!(restored_engine.get(b"key0".to_vec()).is_err());
+        assert_eq!(
+            Some(vec![b'v'; 20 * 1024]),
+            restored_engine.get(b"key99".to_vec()).unwrap()
+        );
+        assert!(crate::storage::bitcask::backup::restore(&backup, &restored).is_err());
+    }
+}
+
+#[test]
+fn bitcask_incremental_backup_chain() {
+    use crate::storage::bitcask::backup::{read_manifest, restore_chain};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().join("store");
+    let store_engine = Bitcask::open(&path).unwrap();
+    let backups: Vec<PathBuf> = (0..3)
+        .map(|i| temp_dir.path().join(format!("backup{}", i)))
+        .collect();
+
+    for i in 0..3 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), b"removed later".to_vec())
+            .unwrap();
+    }
+    let write_round = |round: usize| {
+        for i in 3..60 {
+            store_engine
+                .set(
+                    format!("key{}", i).into_bytes(),
+                    vec![round as u8; 20 * 1024],
+                )
+                .unwrap();
+        }
+        store_engine
+            .remove(format!("key{}", round).into_bytes())
+            .unwrap();
+    };
+
+    write_round(0);
+    store_engine.backup(&backups[0]).unwrap();
+    write_round(1);
+    store_engine
+        .backup_incremental(&backups[1], &backups[0])
+        .unwrap();
+    write_round(2);
+    store_engine.compact().unwrap();
+    store_engine
+        .backup_incremental(&backups[2], &backups[1])
+        .unwrap();
+
+    let full = read_manifest(&backups[0]).unwrap();
+    let incremental = read_manifest(&backups[1]).unwrap();
+    assert_eq!(Some(full.id), incremental.base_id);
+    assert!(incremental
+        .copied_gens
+        .iter()
+        .all(|gen| *gen >= full.active_gen));
+
+    let restored = temp_dir.path().join("restored");
+    assert!(restore_chain(&[backups[0].clone(), backups[2].clone()], &restored).is_err());
+    restore_chain(&backups, &restored).unwrap();
+
+    let restored_engine = Bitcask::open(&restored).unwrap();
+    assert_eq!(57, restored_engine.stats().key_count);
+    for i in 0..60 {
+        let key = format!("key{}", i).into_bytes();
+        match i {
+            0..=2 => assert!(restored_engine.get(key).is_err()),
+            _ => assert_eq!(Some(vec![2; 20 * 1024]), restored_engine.get(key).unwrap()),
+        }
+    }
+}
+
+#[test]
+fn bitcask_write_batch() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().join("store");
+    let store_engine = Bitcask::open(&path).unwrap();
+    store_engine
+        .set(b"gone".to_vec(), b"value".to_vec())
+        .unwrap();
+
+    store_engine
+        .write_batch(vec![
+            (b"a".to_vec(), Some(b"1".to_vec())),
+            (b"b".to_vec(), Some(b"2".to_vec())),
+            (b"a".to_vec(), Some(b"3".to_vec())),
+            (b"gone".to_vec(), None),
+        ])
+        .unwrap();
+    assert_eq!(
+        Some(b"3".to_vec()),
+        store_engine.get(b"a".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"2".to_vec()),
+        store_engine.get(b"b".to_vec()).unwrap()
+    );
+    assert!(store_engine.get(b"gone".to_vec()).is_err());
+
+    drop(store_engine);
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert_eq!(
+        Some(b"3".to_vec()),
+        store_engine.get(b"a".to_vec()).unwrap()
+    );
+    assert!(store_engine.get(b"gone".to_vec()).is_err());
+}
+
+#[test]
+fn bitcask_write_empty_batch() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+
+    store_engine.write_batch(vec![]).unwrap();
+    store_engine
+        .set(b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"key".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_refuse_other_record_format() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine
+            .set(b"key".to_vec(), b"value".to_vec())
+            .unwrap();
+    }
+
+    let log_path = get_log_file_dir(0, &path);
+    let mut buffer = std::fs::read(&log_path).unwrap();
+    buffer[RECORD_MAGIC.len()] = RECORD_FORMAT_VERSION + 1;
+    std::fs::write(&log_path, &buffer).unwrap();
+
+    match Bitcask::open(&path) {
+        Err(KVError::IOError(err)) => assert_eq!(ErrorKind::InvalidData, err.kind()),
+        _ => panic!("a log of another format was opened"),
+    }
+}
+
+#[cfg(test)]
+struct FixedMergePolicy(Vec<u64>);
+
+#[cfg(test)]
+impl MergePolicy for FixedMergePolicy {
+    fn select(&self, generations: &[GenerationStats]) -> Vec<u64> {
+        generations
+            .iter()
+            .map(|generation| generation.gen)
+            .filter(|gen| self.0.contains(gen))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn seal_last_generation(path: &PathBuf) {
+    // An empty next file is the one the store reopens to, leaving the last one sealed.
+    let next_gen = get_sorted_gen_list(path)
+        .unwrap()
+        .last()
+        .map_or(0, |gen| gen + 1);
+    std::fs::File::create(get_log_file_dir(next_gen, path)).unwrap();
+}
+
+#[cfg(test)]
+fn tombstones_on_disk(path: &PathBuf) -> usize {
+    let mut tombstones = 0;
+    for gen in get_sorted_gen_list(path).unwrap() {
+        let buffer = std::fs::read(get_log_file_dir(gen, path)).unwrap();
+        for (pos, len) in record_positions(&buffer) {
+            if let Command::Remove { key: _ } = Command::from(&buffer[pos..pos + len]) {
+                tombstones += 1;
+            }
+        }
+    }
+    return tombstones;
+}
+
+#[test]
+fn bitcask_unfinished_merge_removed_on_open() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.set(b"key".to_vec(), b"old".to_vec()).unwrap();
+        store_engine.set(b"key".to_vec(), b"new".to_vec()).unwrap();
+    }
+    // A merge output cut short, holding the old value.
+    let record = Command::Set {
+        key: b"key".to_vec(),
+        value: b"old".to_vec(),
+    }
+    .parse();
+    let merge_path = get_log_file_dir(1, &path).with_extension(MERGE_FILE_EXTENSION);
+    std::fs::write(&merge_path, &record).unwrap();
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert!(!merge_path.exists());
+    assert_eq!(vec![0], get_sorted_gen_list(&path).unwrap());
+    assert_eq!(
+        Some(b"new".to_vec()),
+        store_engine.get(b"key".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_tombstones_survive_partial_merges() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.set(b"gone".to_vec(), b"old".to_vec()).unwrap();
+        store_engine
+            .set(b"kept".to_vec(), b"value".to_vec())
+            .unwrap();
+    }
+    seal_last_generation(&path);
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.remove(b"gone".to_vec()).unwrap();
+        store_engine
+            .set(b"gone".to_vec(), b"again".to_vec())
+            .unwrap();
+        store_engine.remove(b"gone".to_vec()).unwrap();
+    }
+    assert_eq!(vec![0, 1], get_sorted_gen_list(&path).unwrap());
+    seal_last_generation(&path);
+
+    // Only the generation holding the tombstones is merged, the value in 0 is still there.
+    let options = BitcaskOptions {
+        compaction_threshold: Some(1),
+        merge_policy: Arc::new(FixedMergePolicy(vec![1])),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    store_engine
+        .set(b"trigger".to_vec(), b"value".to_vec())
+        .unwrap();
+    store_engine.wait_for_compaction().unwrap();
+    assert_eq!(vec![0, 2, 3, 4], get_sorted_gen_list(&path).unwrap());
+    {
+        // The single kept tombstone is not garbage, so the merge output is not merged again.
+        let state = store_engine.state.lock().unwrap();
+        assert_eq!(0, state.gen_stats[&3].dead_bytes);
+        assert!(state.gen_stats[&3].tombstone_bytes > 0);
+        assert_eq!(0.0, state.gen_stats[&3].fragmentation());
+    }
+    assert_eq!(1, tombstones_on_disk(&path));
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert!(store_engine.get(b"gone".to_vec()).is_err());
+
+    // Once every older file is merged as well, the tombstone goes.
+    store_engine.compact().unwrap();
+    assert_eq!(0, tombstones_on_disk(&path));
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert!(store_engine.get(b"gone".to_vec()).is_err());
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"kept".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_merged_tombstones_keep_newer_writes() {
+    use crate::storage::bitcask::merge_operator::AppendMergeOperator;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = |merge_policy: Vec<u64>| BitcaskOptions {
+        compaction_threshold: Some(1),
+        merge_policy: Arc::new(FixedMergePolicy(merge_policy)),
+        merge_operator: Some(Arc::new(AppendMergeOperator)),
+        ..BitcaskOptions::default()
+    };
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.set(b"list".to_vec(), b"old".to_vec()).unwrap();
+        store_engine
+            .set(b"reset".to_vec(), b"old".to_vec())
+            .unwrap();
+    }
+    seal_last_generation(&path);
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.remove(b"list".to_vec()).unwrap();
+        store_engine.remove(b"reset".to_vec()).unwrap();
+    }
+    seal_last_generation(&path);
+    {
+        let store_engine = Bitcask::open_with_options(&path, options(vec![])).unwrap();
+        store_engine.merge(b"list".to_vec(), b"a".to_vec()).unwrap();
+        store_engine.merge(b"list".to_vec(), b"b".to_vec()).unwrap();
+        store_engine
+            .set(b"reset".to_vec(), b"new".to_vec())
+            .unwrap();
+    }
+    assert_eq!(vec![0, 1, 2], get_sorted_gen_list(&path).unwrap());
+    seal_last_generation(&path);
+
+    // Only the generation of the tombstones is merged, the newer writes are left behind.
+    let store_engine = Bitcask::open_with_options(&path, options(vec![1])).unwrap();
+    store_engine
+        .set(b"trigger".to_vec(), b"value".to_vec())
+        .unwrap();
+    store_engine.wait_for_compaction().unwrap();
+    assert!(!get_sorted_gen_list(&path).unwrap().contains(&1));
+    assert_eq!(1, tombstones_on_disk(&path));
+    drop(store_engine);
+
+    let store_engine = Bitcask::open_with_options(&path, options(vec![])).unwrap();
+    assert_eq!(
+        Some(b"ab".to_vec()),
+        store_engine.get(b"list".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"new".to_vec()),
+        store_engine.get(b"reset".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_counter_after_removal_survives_merge() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.set(b"key".to_vec(), b"5".to_vec()).unwrap();
+    }
+    seal_last_generation(&path);
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.remove(b"key".to_vec()).unwrap();
+    }
+    seal_last_generation(&path);
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.increment(b"key".to_vec(), 1).unwrap();
+        assert_eq!(
+            Some(b"1".to_vec()),
+            store_engine.get(b"key".to_vec()).unwrap()
+        );
+    }
+    seal_last_generation(&path);
+
+    let options = BitcaskOptions {
+        compaction_threshold: Some(1),
+        merge_policy: Arc::new(FixedMergePolicy(vec![1])),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    store_engine
+        .set(b"trigger".to_vec(), b"value".to_vec())
+        .unwrap();
+    store_engine.wait_for_compaction().unwrap();
+    assert!(!get_sorted_gen_list(&path).unwrap().contains(&1));
+    // Without the tombstone the set in generation 0 would become the base of the counter.
+    assert_eq!(1, tombstones_on_disk(&path));
+    assert_eq!(
+        Some(b"1".to_vec()),
+        store_engine.get(b"key".to_vec()).unwrap()
+    );
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert_eq!(
+        Some(b"1".to_vec()),
+        store_engine.get(b"key".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_tombstones_of_oldest_generation_dropped() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine
+            .set(b"gone".to_vec(), b"value".to_vec())
+            .unwrap();
+        store_engine.remove(b"gone".to_vec()).unwrap();
+    }
+    seal_last_generation(&path);
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine
+            .set(b"newer".to_vec(), b"value".to_vec())
+            .unwrap();
+    }
+    seal_last_generation(&path);
+
+    // Nothing is older than generation 0, its tombstone is garbage to the merge policy.
+    let options = BitcaskOptions {
+        compaction_threshold: Some(1),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    store_engine
+        .set(b"trigger".to_vec(), b"value".to_vec())
+        .unwrap();
+    store_engine.wait_for_compaction().unwrap();
+
+    assert!(!get_sorted_gen_list(&path).unwrap().contains(&0));
+    assert_eq!(0, tombstones_on_disk(&path));
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert!(store_engine.get(b"gone".to_vec()).is_err());
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"newer".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_blob_values() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = || BitcaskOptions {
+        blob_threshold: Some(100),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    store_engine.set(b"big".to_vec(), vec![1; 1000]).unwrap();
+    store_engine
+        .set(b"small".to_vec(), b"value".to_vec())
+        .unwrap();
+    assert_eq!(
+        vec![0],
+        crate::storage::bitcask::blob::get_sorted_blob_files(&path).unwrap()
+    );
+    assert!(std::fs::metadata(get_log_file_dir(0, &path)).unwrap().len() < 1000);
+    assert_eq!(
+        Some(vec![1; 1000]),
+        store_engine.get(b"big".to_vec()).unwrap()
+    );
+    drop(store_engine);
+
+    // Blob file 0 is sealed once reopened, but "big" still points into it.
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    assert_eq!(
+        Some(vec![1; 1000]),
+        store_engine.get(b"big".to_vec()).unwrap()
+    );
+    assert_eq!(0, store_engine.collect_blobs().unwrap());
+
+    let snapshot = store_engine.snapshot();
+    store_engine.set(b"big".to_vec(), vec![2; 1000]).unwrap();
+    assert_eq!(0, store_engine.collect_blobs().unwrap());
+    assert_eq!(Some(vec![1; 1000]), snapshot.get(b"big".to_vec()).unwrap());
+    drop(snapshot);
+
+    store_engine.compact().unwrap();
+    assert_eq!(
+        vec![1],
+        crate::storage::bitcask::blob::get_sorted_blob_files(&path).unwrap()
+    );
+    assert_eq!(
+        Some(vec![2; 1000]),
+        store_engine.get(b"big".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"small".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_merge_relocate_sparse_blob_file() {
+    use crate::storage::bitcask::blob::get_sorted_blob_files;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = || BitcaskOptions {
+        blob_threshold: Some(100),
+        max_blob_file_size: 4000,
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    for key in ["a", "b", "c", "kept"] {
+        store_engine
+            .set(key.as_bytes().to_vec(), vec![1; 1000])
+            .unwrap();
+    }
+    for key in ["a", "b", "c"] {
+        store_engine
+            .set(key.as_bytes().to_vec(), vec![2; 1000])
+            .unwrap();
+    }
+    assert_eq!(0, store_engine.collect_blobs().unwrap());
+
+    // "kept" alone pins blob file 0 until a merge moves it out.
+    store_engine.compact().unwrap();
+    assert!(!get_sorted_blob_files(&path).unwrap().contains(&0));
+    assert_eq!(
+        Some(vec![1; 1000]),
+        store_engine.get(b"kept".to_vec()).unwrap()
+    );
+    drop(store_engine);
+
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    assert_eq!(
+        Some(vec![1; 1000]),
+        store_engine.get(b"kept".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(vec![2; 1000]),
+        store_engine.get(b"a".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_backup_blob_values() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().join("store");
+    let options = BitcaskOptions {
+        blob_threshold: Some(100),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    store_engine.set(b"big".to_vec(), vec![1; 1000]).unwrap();
+
+    let backup_dir = temp_dir.path().join("backup");
+    let archive = temp_dir.path().join("backup.kvs");
+    store_engine.backup(&backup_dir).unwrap();
+    store_engine.backup_archive(&archive).unwrap();
+
+    for (backup, restored) in [(backup_dir, "from_dir"), (archive, "from_archive")] {
+        let restored = temp_dir.path().join(restored);
+        crate::storage::bitcask::backup::restore(&backup, &restored).unwrap();
+
+        let restored_engine = Bitcask::open(&restored).unwrap();
+        assert_eq!(
+            Some(vec![1; 1000]),
+            restored_engine.get(b"big".to_vec()).unwrap()
+        );
+    }
+}
+
+#[test]
+fn bitcask_backup_stop_at_snapshot() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().join("store");
+    let store_engine = Bitcask::open(&path).unwrap();
+    store_engine.set(b"before".to_vec(), b"1".to_vec()).unwrap();
+
+    let snapshot = store_engine.snapshot();
+    store_engine.set(b"after".to_vec(), b"2".to_vec()).unwrap();
+    let backup = temp_dir.path().join("backup");
+    copy_generations(&path, &backup, &snapshot.gens, snapshot.active_end, None).unwrap();
+
+    let restored = temp_dir.path().join("restored");
+    crate::storage::bitcask::backup::restore(&backup, &restored).unwrap();
+    let restored_engine = Bitcask::open(&restored).unwrap();
+    assert_eq!(
+        Some(b"1".to_vec()),
+        restored_engine.get(b"before".to_vec()).unwrap()
+    );
+    assert!(matches!(
+        restored_engine.get(b"after".to_vec()),
+        Err(KVError::KeyNoneExisted)
+    ));
+}
+
+#[test]
+fn bitcask_incremental_backup_skip_sealed_blob_files() {
+    use crate::storage::bitcask::backup::{read_manifest, restore_chain};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().join("store");
+    let options = BitcaskOptions {
+        blob_threshold: Some(100),
+        max_blob_file_size: MAX_ACTIVE_LOG_FILE_SIZE,
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    let value_len = MAX_ACTIVE_LOG_FILE_SIZE as usize / 4;
+    for i in 0..6 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), vec![i as u8; value_len])
+            .unwrap();
+    }
+
+    let backups = [temp_dir.path().join("full"), temp_dir.path().join("incr")];
+    store_engine.backup(&backups[0]).unwrap();
+    store_engine
+        .set(b"key6".to_vec(), vec![6; value_len])
+        .unwrap();
+    store_engine
+        .backup_incremental(&backups[1], &backups[0])
+        .unwrap();
+
+    let full = read_manifest(&backups[0]).unwrap();
+    let incremental = read_manifest(&backups[1]).unwrap();
+    assert!(full.blob_files.len() > 1);
+    assert_eq!(full.blob_files, full.copied_blob_files);
+    for file in &full.blob_files[..full.blob_files.len() - 1] {
+        assert!(!incremental.copied_blob_files.contains(file));
+        assert!(!get_blob_file_dir(*file, &backups[1]).exists());
+    }
+
+    let restored = temp_dir.path().join("restored");
+    restore_chain(&backups, &restored).unwrap();
+    let restored_engine = Bitcask::open(&restored).unwrap();
+    for i in 0..7 {
+        assert_eq!(
+            Some(vec![i as u8; value_len]),
+            restored_engine
+                .get(format!("key{}", i).into_bytes())
+                .unwrap()
+        );
+    }
+}
+
+#[test]
+fn bitcask_streamed_values() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+    let watched = store_engine.watch(KeyFilter::All).unwrap();
+
+    let len = 300 * 1024;
+    store_engine
+        .set_from_reader(b"big".to_vec(), &mut std::io::repeat(7).take(len), len)
+        .unwrap();
+    let mut reader = store_engine.get_reader(b"big".to_vec()).unwrap().unwrap();
+    assert_eq!(len, reader.len());
+    let mut value = Vec::new();
+    reader.read_to_end(&mut value).unwrap();
+    assert_eq!(vec![7; len as usize], value);
+    assert_eq!(Some(value), watched.recv().unwrap().value);
+
+    // A reader ending early leaves nothing behind.
+    let result = store_engine.set_from_reader(b"big".to_vec(), &mut &[1, 2, 3][..], 10);
+    assert!(result.is_err());
+    store_engine
+        .set(b"small".to_vec(), b"value".to_vec())
+        .unwrap();
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert_eq!(
+        Some(vec![7; len as usize]),
+        store_engine.get(b"big".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"small".to_vec()).unwrap()
+    );
+
+    // Damage the last byte of the value.
+    let log_pointer = store_engine.state.lock().unwrap().namespaces[&DEFAULT_NAMESPACE]
+        .keys
+        .get(b"big")
+        .unwrap()
+        .unwrap();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(get_log_file_dir(log_pointer.gen, &path))
+        .unwrap();
+    file.seek(SeekFrom::Start(log_pointer.pos + log_pointer.len - 1))
+        .unwrap();
+    file.write_all(&[8]).unwrap();
+    let mut reader = store_engine.get_reader(b"big".to_vec()).unwrap().unwrap();
+    let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+}
+
+#[test]
+fn bitcask_streamed_value_not_block_writes() {
+    struct BlockingReader(Receiver<()>);
+
+    impl Read for BlockingReader {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.0.recv().unwrap();
+            buf[0] = 7;
+            return Ok(1);
+        }
+    }
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+
+    let (unblock, blocked) = channel();
+    let streamer = store_engine.clone();
+    let streaming = std::thread::spawn(move || {
+        streamer.set_from_reader(b"slow".to_vec(), &mut BlockingReader(blocked), 2)
+    });
+    unblock.send(()).unwrap();
+
+    // Written while the streamed value is still being read.
+    store_engine
+        .set(b"fast".to_vec(), b"value".to_vec())
+        .unwrap();
+    unblock.send(()).unwrap();
+    streaming.join().unwrap().unwrap();
+
+    assert_eq!(
+        Some(vec![7, 7]),
+        store_engine.get(b"slow".to_vec()).unwrap()
+    );
+    let spooled = read_dir(&path)
+        .unwrap()
+        .any(|entry| entry.unwrap().path().extension() == Some(SPOOL_FILE_EXTENSION.as_ref()));
+    assert!(!spooled);
+}
+
+#[test]
+fn bitcask_stream_reject_bad_key_len() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+    store_engine
+        .set(b"key".to_vec(), b"value".to_vec())
+        .unwrap();
+
+    let log_pointer = store_engine.state.lock().unwrap().namespaces[&DEFAULT_NAMESPACE]
+        .keys
+        .get(b"key")
+        .unwrap()
+        .unwrap();
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(get_log_file_dir(log_pointer.gen, &path))
+        .unwrap();
+    // The key length is the last field of the header.
+    let key_len_offset = RECORD_HEADER_LEN - size_of::<u64>();
+    file.seek(SeekFrom::Start(log_pointer.pos + key_len_offset as u64))
+        .unwrap();
+    file.write_all(&u64::MAX.to_le_bytes()).unwrap();
+
+    match store_engine.get_reader(b"key".to_vec()) {
+        Err(KVError::IOError(err)) => assert_eq!(ErrorKind::InvalidData, err.kind()),
+        _ => panic!("a record with a bad key length was read"),
+    }
+}
+
+#[test]
+fn bitcask_merge_copy_large_values() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+
+    let value: Vec<u8> = (0..3 * STREAM_CHUNK_LEN + 5).map(|i| i as u8).collect();
+    store_engine.set(b"big".to_vec(), value.clone()).unwrap();
+    store_engine.remove(b"big".to_vec()).unwrap();
+    store_engine.set(b"big".to_vec(), value.clone()).unwrap();
+    store_engine
+        .set(b"small".to_vec(), b"value".to_vec())
+        .unwrap();
+    store_engine.compact().unwrap();
+    drop(store_engine);
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert_eq!(Some(value), store_engine.get(b"big".to_vec()).unwrap());
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"small".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_streamed_blob_values() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = BitcaskOptions {
+        blob_threshold: Some(100),
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+
+    store_engine
+        .set_from_reader(b"big".to_vec(), &mut std::io::repeat(7).take(1000), 1000)
+        .unwrap();
+    assert!(std::fs::metadata(get_log_file_dir(0, &path)).unwrap().len() < 1000);
+    let mut value = Vec::new();
+    store_engine
+        .get_reader(b"big".to_vec())
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut value)
+        .unwrap();
+    assert_eq!(vec![7; 1000], value);
+}
+
+#[test]
+fn bitcask_compact_keydir() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = || BitcaskOptions {
+        keydir: crate::storage::bitcask::keydir::KeyDirKind::Compact,
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    for i in 0..1000 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), vec![b'v'; 100])
+            .unwrap();
+    }
+    for i in 0..500 {
+        store_engine
+            .remove(format!("key{}", i).into_bytes())
+            .unwrap();
+    }
+    let snapshot = store_engine.snapshot();
+    store_engine
+        .set(b"key999".to_vec(), b"new".to_vec())
+        .unwrap();
+    store_engine.compact().unwrap();
+
+    assert_eq!(500, snapshot.iter().count());
+    assert_eq!(
+        Some(vec![b'v'; 100]),
+        snapshot.get(b"key999".to_vec()).unwrap()
+    );
+    drop(snapshot);
+    drop(store_engine);
+
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    assert_eq!(500, store_engine.stats().key_count);
+    assert!(store_engine.get(b"key0".to_vec()).is_err());
+    assert_eq!(
+        Some(b"new".to_vec()),
+        store_engine.get(b"key999".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_disk_keydir() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = || BitcaskOptions {
+        // A few pages, so the table is read from its file and grows while the log is loaded.
+        keydir: crate::storage::bitcask::keydir::KeyDirKind::OnDisk {
+            cache_bytes: 16 * 1024,
+        },
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    for i in 0..10_000 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), vec![b'v'; 10])
+            .unwrap();
+    }
+    for i in 0..5000 {
+        store_engine
+            .remove(format!("key{}", i).into_bytes())
+            .unwrap();
+    }
+    store_engine.compact().unwrap();
+    drop(store_engine);
+
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    assert_eq!(5000, store_engine.stats().key_count);
+    assert!(store_engine.get(b"key0".to_vec()).is_err());
+    for i in 5000..10_000 {
+        assert_eq!(
+            Some(vec![b'v'; 10]),
+            store_engine.get(format!("key{}", i).into_bytes()).unwrap()
+        );
+    }
+}
+
+#[test]
+fn bitcask_namespaces() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = || BitcaskOptions {
+        value_cache_capacity: Some(1024),
+        ..BitcaskOptions::default()
+    };
+    {
+        let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+        let users = store_engine.create_namespace("users").unwrap();
+        let orders = store_engine.create_namespace("orders").unwrap();
+        assert!(store_engine.create_namespace("users").is_err());
+        assert!(store_engine.create_namespace("default").is_err());
+
+        store_engine
+            .set(b"key".to_vec(), b"default".to_vec())
+            .unwrap();
+        users.set(b"key".to_vec(), b"user".to_vec()).unwrap();
+        assert_eq!(Some(b"user".to_vec()), users.get(b"key".to_vec()).unwrap());
+        assert!(orders.get(b"key".to_vec()).is_err());
+
+        store_engine
+            .write_batch_across(vec![
+                (&orders, b"key".to_vec(), Some(b"order".to_vec())),
+                (&users, b"key".to_vec(), None),
+                (&users, b"other".to_vec(), Some(b"user".to_vec())),
+            ])
+            .unwrap();
+        assert!(users.get(b"key".to_vec()).is_err());
+
+        let stats = store_engine.stats();
+        let key_counts: Vec<(&str, u64)> = stats
+            .namespaces
+            .iter()
+            .map(|namespace| (namespace.name.as_str(), namespace.key_count))
+            .collect();
+        assert_eq!(
+            vec![("default", 1), ("orders", 1), ("users", 1)],
+            key_counts
+        );
+        assert_eq!(3, stats.key_count);
+    }
+
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    assert_eq!(
+        vec!["default", "orders", "users"],
+        store_engine.list_namespaces()
+    );
+    let users = store_engine.namespace("users").unwrap();
+    assert_eq!(
+        Some(b"user".to_vec()),
+        users.get(b"other".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"default".to_vec()),
+        store_engine.get(b"key".to_vec()).unwrap()
+    );
+
+    store_engine.drop_namespace("users").unwrap();
+    assert!(store_engine.drop_namespace("default").is_err());
+    assert!(store_engine.namespace("users").is_err());
+    assert!(users.get(b"other".to_vec()).is_err());
+    assert!(users.set(b"other".to_vec(), b"user".to_vec()).is_err());
+
+    let users = store_engine.create_namespace("users").unwrap();
+    assert!(users.get(b"other".to_vec()).is_err());
+    assert!(store_engine.stats().uncompacted > 0);
+    drop(store_engine);
+
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    let users = store_engine.namespace("users").unwrap();
+    assert!(users.get(b"other".to_vec()).is_err());
+    let orders = store_engine.namespace("orders").unwrap();
+    assert_eq!(
+        Some(b"order".to_vec()),
+        orders.get(b"key".to_vec()).unwrap()
+    );
+
+    store_engine.compact().unwrap();
+    let stats = store_engine.stats();
+    assert_eq!(0, stats.uncompacted);
+    assert_eq!(2, stats.key_count);
+    assert_eq!(
+        Some(b"order".to_vec()),
+        orders.get(b"key".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_merge_reclaims_dropped_namespace() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let log_bytes = || -> u64 {
+        get_sorted_gen_list(&path)
+            .unwrap()
+            .iter()
+            .map(|gen| metadata(get_log_file_dir(*gen, &path)).unwrap().len())
+            .sum()
+    };
+    let options = BitcaskOptions {
+        compaction_threshold: None,
+        ..BitcaskOptions::default()
+    };
+    let store_engine = Bitcask::open_with_options(&path, options).unwrap();
+    store_engine
+        .set(b"kept".to_vec(), b"value".to_vec())
+        .unwrap();
+    let logs = store_engine.create_namespace("logs").unwrap();
+    for i in 0..100 {
+        logs.set(format!("key{}", i).into_bytes(), vec![0; 1000])
+            .unwrap();
+    }
+    let before_drop = log_bytes();
+    assert!(before_drop > 100_000);
+
+    store_engine.drop_namespace("logs").unwrap();
+    assert_eq!(before_drop, log_bytes());
+    assert!(store_engine.stats().uncompacted >= 100_000);
+
+    store_engine.compact().unwrap();
+    assert!(log_bytes() < 1000);
+    assert_eq!(0, store_engine.stats().uncompacted);
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"kept".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_namespace_streams_and_snapshots() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = || BitcaskOptions {
+        blob_threshold: Some(16),
+        ..BitcaskOptions::default()
+    };
+    {
+        let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+        let users = store_engine.create_namespace("users").unwrap();
+        store_engine
+            .set(b"default".to_vec(), b"value".to_vec())
+            .unwrap();
+        users
+            .set_from_reader(b"small".to_vec(), &mut &b"short"[..], 5)
+            .unwrap();
+        let blob_value = vec![7; 64];
+        users
+            .set_from_reader(b"large".to_vec(), &mut blob_value.as_slice(), 64)
+            .unwrap();
+        assert!(store_engine.get(b"small".to_vec()).is_err());
+    }
+
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    let users = store_engine.namespace("users").unwrap();
+    let mut value = Vec::new();
+    users
+        .get_reader(b"large".to_vec())
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut value)
+        .unwrap();
+    assert_eq!(vec![7; 64], value);
+    let meta = users.get_with_meta(b"small".to_vec()).unwrap().unwrap();
+    assert_eq!(b"short".to_vec(), meta.value);
+    assert!(store_engine.get_with_meta(b"small".to_vec()).is_err());
+
+    let snapshot = users.snapshot().unwrap();
+    users.set(b"small".to_vec(), b"changed".to_vec()).unwrap();
+    assert_eq!(2, snapshot.len());
+    assert_eq!(
+        Some(b"short".to_vec()),
+        snapshot.get(b"small".to_vec()).unwrap()
+    );
+    let mut keys: Vec<Vec<u8>> = snapshot.iter().map(|entry| entry.unwrap().0).collect();
+    keys.sort();
+    assert_eq!(vec![b"large".to_vec(), b"small".to_vec()], keys);
+
+    store_engine.drop_namespace("users").unwrap();
+    assert!(snapshot.get(b"small".to_vec()).is_err());
+    assert!(users.snapshot().is_err());
+    drop(snapshot);
+}
+
+#[test]
+fn bitcask_torn_batch_not_applied() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine
+            .set(b"before".to_vec(), b"value".to_vec())
+            .unwrap();
+        store_engine
+            .write_batch(vec![
+                (b"key1".to_vec(), Some(b"value1".to_vec())),
+                (b"key2".to_vec(), Some(b"value2".to_vec())),
+            ])
+            .unwrap();
+    }
+
+    // Cut the last record of the batch short, as a crash in the middle of the write would.
+    let gen = *get_sorted_gen_list(&path).unwrap().last().unwrap();
+    let file = OpenOptions::new()
+        .write(true)
+        .open(get_log_file_dir(gen, &path))
+        .unwrap();
+    file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert_eq!(
+        Some(b"value".to_vec()),
+        store_engine.get(b"before".to_vec()).unwrap()
+    );
+    assert!(store_engine.get(b"key1".to_vec()).is_err());
+    assert!(store_engine.get(b"key2".to_vec()).is_err());
+}
+
+#[test]
+fn bitcask_increment_and_merge_operator() {
+    use crate::storage::bitcask::merge_operator::AppendMergeOperator;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let options = || BitcaskOptions {
+        merge_operator: Some(Arc::new(AppendMergeOperator)),
+        ..BitcaskOptions::default()
+    };
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine.increment(b"counter".to_vec(), 5).unwrap();
+        store_engine.increment(b"counter".to_vec(), -2).unwrap();
+        assert_eq!(
+            Some(b"3".to_vec()),
+            store_engine.get(b"counter".to_vec()).unwrap()
+        );
+        assert!(store_engine.merge(b"list".to_vec(), b"a".to_vec()).is_err());
+        store_engine.set(b"text".to_vec(), b"abc".to_vec()).unwrap();
+        store_engine.increment(b"text".to_vec(), 1).unwrap();
+        assert!(store_engine.get(b"text".to_vec()).is_err());
+    }
+
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    assert_eq!(
+        Some(b"3".to_vec()),
+        store_engine.get(b"counter".to_vec()).unwrap()
+    );
+    store_engine.set(b"list".to_vec(), b"a".to_vec()).unwrap();
+    store_engine.merge(b"list".to_vec(), b"b".to_vec()).unwrap();
+    let snapshot = store_engine.snapshot();
+    store_engine.merge(b"list".to_vec(), b"c".to_vec()).unwrap();
+    assert_eq!(
+        Some(b"abc".to_vec()),
+        store_engine.get(b"list".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"ab".to_vec()),
+        snapshot.get(b"list".to_vec()).unwrap()
+    );
+    drop(snapshot);
+    let mut value = Vec::new();
+    store_engine
+        .get_reader(b"list".to_vec())
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut value)
+        .unwrap();
+    assert_eq!(b"abc".to_vec(), value);
+
+    // Sets and removals end the chain before them.
+    store_engine.set(b"text".to_vec(), b"10".to_vec()).unwrap();
+    store_engine.increment(b"text".to_vec(), 1).unwrap();
+    store_engine.remove(b"counter".to_vec()).unwrap();
+    store_engine.increment(b"counter".to_vec(), 7).unwrap();
+    assert_eq!(
+        Some(b"11".to_vec()),
+        store_engine.get(b"text".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"7".to_vec()),
+        store_engine.get(b"counter".to_vec()).unwrap()
+    );
+    store_engine.increment(b"big".to_vec(), i64::MAX).unwrap();
+    store_engine.increment(b"big".to_vec(), 1).unwrap();
+    assert!(store_engine.get(b"big".to_vec()).is_err());
+    store_engine.remove(b"big".to_vec()).unwrap();
+
+    let namespace = store_engine.create_namespace("counters").unwrap();
+    namespace.increment(b"counter".to_vec(), 2).unwrap();
+    assert_eq!(
+        Some(b"2".to_vec()),
+        namespace.get(b"counter".to_vec()).unwrap()
+    );
+
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let store_engine = store_engine.clone();
+            std::thread::spawn(move || {
+                for _ in 0..25 {
+                    store_engine.increment(b"shared".to_vec(), 1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(
+        Some(b"100".to_vec()),
+        store_engine.get(b"shared".to_vec()).unwrap()
+    );
+
+    // A merge folds every chain into a single set.
+    store_engine.compact().unwrap();
+    let stats = store_engine.stats();
+    assert_eq!(0, stats.uncompacted);
+    assert_eq!(
+        Some(b"100".to_vec()),
+        store_engine.get(b"shared".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"abc".to_vec()),
+        store_engine.get(b"list".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"2".to_vec()),
+        namespace.get(b"counter".to_vec()).unwrap()
+    );
+    assert!(
+        store_engine.state.lock().unwrap().namespaces[&DEFAULT_NAMESPACE]
+            .chains
+            .is_empty()
+    );
+    store_engine.increment(b"shared".to_vec(), 1).unwrap();
+    drop(namespace);
+    drop(store_engine);
+
+    let store_engine = Bitcask::open_with_options(&path, options()).unwrap();
+    assert_eq!(
+        Some(b"101".to_vec()),
+        store_engine.get(b"shared".to_vec()).unwrap()
+    );
+    assert_eq!(
+        Some(b"7".to_vec()),
+        store_engine.get(b"counter".to_vec()).unwrap()
+    );
+}
+
+#[test]
+fn bitcask_merge_full_chain() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    {
+        let store_engine = Bitcask::open(&path).unwrap();
+        store_engine
+            .set(b"counter".to_vec(), b"10".to_vec())
+            .unwrap();
+        for _ in 0..MAX_MERGE_CHAIN_LEN {
+            store_engine.increment(b"counter".to_vec(), 1).unwrap();
+        }
+        // Nothing else is due for a merge, the full chain starts one.
+        store_engine.wait_for_compaction().unwrap();
+
+        assert!(
+            store_engine.state.lock().unwrap().namespaces[&DEFAULT_NAMESPACE]
+                .chains
+                .is_empty()
+        );
+        assert_eq!(1, store_engine.stats().compactions);
+        store_engine.increment(b"counter".to_vec(), 1).unwrap();
+        let expected = (11 + MAX_MERGE_CHAIN_LEN).to_string().into_bytes();
+        assert_eq!(
+            Some(expected),
+            store_engine.get(b"counter".to_vec()).unwrap()
+        );
+    }
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    let expected = (11 + MAX_MERGE_CHAIN_LEN).to_string().into_bytes();
+    assert_eq!(
+        Some(expected),
+        store_engine.get(b"counter".to_vec()).unwrap()
+    );
 }
@@ -0,0 +1,250 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::error::KVResult;
+use crate::storage::bitcask::stream::copy_value;
+use crate::utils::{u64_to_u8_array, u8_array_to_u64};
+
+/// Directory inside the store holding the blob files.
+pub(crate) const BLOB_DIR_NAME: &str = "blobs";
+
+/// Where a value written to a blob file lives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlobRef {
+    pub file: u64,
+    pub offset: u64,
+    pub len: u64,
+    /// CRC32 of the value.
+    pub checksum: u32,
+}
+
+impl BlobRef {
+    /// File, offset and length, then the checksum.
+    pub const ENCODED_LEN: usize = 3 * size_of::<u64>() + size_of::<u32>();
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = Vec::with_capacity(BlobRef::ENCODED_LEN);
+        res.extend_from_slice(&u64_to_u8_array(self.file));
+        res.extend_from_slice(&u64_to_u8_array(self.offset));
+        res.extend_from_slice(&u64_to_u8_array(self.len));
+        res.extend_from_slice(&self.checksum.to_le_bytes());
+        return res;
+    }
+
+    pub fn decode(data: &[u8]) -> BlobRef {
+        let read_u64 = |pos: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[pos..pos + size_of::<u64>()]);
+            return u8_array_to_u64(&bytes);
+        };
+        let mut checksum = [0; 4];
+        checksum.copy_from_slice(&data[3 * size_of::<u64>()..BlobRef::ENCODED_LEN]);
+
+        return BlobRef {
+            file: read_u64(0),
+            offset: read_u64(size_of::<u64>()),
+            len: read_u64(2 * size_of::<u64>()),
+            checksum: u32::from_le_bytes(checksum),
+        };
+    }
+}
+
+/// Appends large values to blob files, which are only ever appended to and deleted whole.
+pub(crate) struct BlobStore {
+    path: PathBuf,
+    max_file_size: u64,
+    sync_writes: bool,
+    state: Mutex<BlobWriterState>,
+}
+
+struct BlobWriterState {
+    file: u64,
+    /// Opened on the first write, so opening a store never leaves an empty blob file behind.
+    writer: Option<File>,
+    pos: u64,
+    /// Values written whose key record is not appended yet, by blob file.
+    pending: HashMap<u64, usize>,
+}
+
+impl BlobStore {
+    pub fn new(path: &PathBuf, max_file_size: u64, sync_writes: bool) -> KVResult<BlobStore> {
+        let file = get_sorted_blob_files(path)?
+            .last()
+            .map_or(0, |file| file + 1);
+
+        return Ok(BlobStore {
+            path: path.to_owned(),
+            max_file_size,
+            sync_writes,
+            state: Mutex::new(BlobWriterState {
+                file,
+                writer: None,
+                pos: 0,
+                pending: HashMap::new(),
+            }),
+        });
+    }
+
+    /// Appends `value` and returns where it went. The blob file is kept until `release` is
+    /// called, once the record referring to the value is in the log.
+    pub fn write(&self, value: &[u8]) -> KVResult<BlobRef> {
+        return self.write_from_reader(&mut &value[..], value.len() as u64);
+    }
+
+    /// Same as `write` for a value of `len` bytes read from `reader` in chunks. Other blob
+    /// writes wait until it is copied.
+    pub fn write_from_reader(&self, reader: &mut dyn Read, len: u64) -> KVResult<BlobRef> {
+        let mut state = self.state.lock().unwrap();
+        if state.writer.is_some() && state.pos + len > self.max_file_size {
+            state.file += 1;
+            state.writer = None;
+            state.pos = 0;
+        }
+        if state.writer.is_none() {
+            create_dir_all(self.path.join(BLOB_DIR_NAME))?;
+            let writer = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(get_blob_file_dir(state.file, &self.path))?;
+            state.pos = writer.metadata()?.len();
+            state.writer = Some(writer);
+        }
+
+        let pos = state.pos;
+        let writer = state.writer.as_mut().unwrap();
+        let mut hasher = crc32fast::Hasher::new();
+        if let Err(err) = copy_value(reader, writer, len, &mut hasher) {
+            // Drop what was copied of the value.
+            writer.set_len(pos)?;
+            return Err(err);
+        }
+        if self.sync_writes {
+            writer.sync_data()?;
+        }
+
+        let blob = BlobRef {
+            file: state.file,
+            offset: pos,
+            len,
+            checksum: hasher.finalize(),
+        };
+        state.pos += blob.len;
+        *state.pending.entry(blob.file).or_insert(0) += 1;
+
+        return Ok(blob);
+    }
+
+    pub fn release(&self, blob: &BlobRef) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pending) = state.pending.get_mut(&blob.file) {
+            *pending -= 1;
+            if *pending == 0 {
+                state.pending.remove(&blob.file);
+            }
+        }
+    }
+
+    /// Flushes the blob file being written to disk.
+    pub fn sync(&self) -> KVResult<()> {
+        if let Some(writer) = &self.state.lock().unwrap().writer {
+            writer.sync_data()?;
+        }
+
+        return Ok(());
+    }
+
+    /// Blob files no longer written to and with no write in flight. Every value in them that
+    /// is still in use is referenced from the index.
+    pub fn sealed_files(&self) -> KVResult<Vec<u64>> {
+        let state = self.state.lock().unwrap();
+        let files = get_sorted_blob_files(&self.path)?
+            .into_iter()
+            .filter(|file| *file < state.file && !state.pending.contains_key(file))
+            .collect();
+
+        return Ok(files);
+    }
+}
+
+/// Reads a value back from its blob file and checks it against its checksum.
+pub(crate) fn read_blob(path: &PathBuf, blob: &BlobRef) -> KVResult<Vec<u8>> {
+    let mut file = File::open(get_blob_file_dir(blob.file, path))?;
+    file.seek(SeekFrom::Start(blob.offset))?;
+    let mut value = vec![0; blob.len as usize];
+    file.read_exact(&mut value)?;
+
+    if crc32fast::hash(&value) != blob.checksum {
+        let message = format!("blob {} at {} fails its checksum", blob.file, blob.offset);
+        return Err(Error::new(ErrorKind::InvalidData, message).into());
+    }
+
+    return Ok(value);
+}
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn get_blob_file_dir(file: u64, path: &PathBuf) -> PathBuf {
+    return path.join(BLOB_DIR_NAME).join(format!("{}.blob", file));
+}
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn get_sorted_blob_files(path: &PathBuf) -> KVResult<Vec<u64>> {
+    let blob_dir = path.join(BLOB_DIR_NAME);
+    if !blob_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut files = Vec::new();
+    for dir_entry in read_dir(blob_dir)? {
+        let entry_path = dir_entry?.path();
+        if entry_path.extension() != Some("blob".as_ref()) {
+            continue;
+        }
+
+        let file = entry_path
+            .file_stem()
+            .and_then(|file_stem| file_stem.to_str())
+            .and_then(|file_stem| file_stem.parse::<u64>().ok());
+        if let Some(file) = file {
+            files.push(file);
+        }
+    }
+    files.sort();
+
+    return Ok(files);
+}
+
+#[test]
+fn blob_store_write_read_rotate() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let blob_store = BlobStore::new(&path, 100, false).unwrap();
+    assert!(get_sorted_blob_files(&path).unwrap().is_empty());
+
+    let first = blob_store.write(&[1; 60]).unwrap();
+    let second = blob_store.write(&[2; 60]).unwrap();
+    assert_eq!((0, 0), (first.file, first.offset));
+    assert_eq!((1, 0), (second.file, second.offset));
+    assert_eq!(vec![1; 60], read_blob(&path, &first).unwrap());
+    assert_eq!(vec![2; 60], read_blob(&path, &second).unwrap());
+
+    // File 0 is sealed, but its value is not in the log yet.
+    assert!(blob_store.sealed_files().unwrap().is_empty());
+    blob_store.release(&first);
+    assert_eq!(vec![0], blob_store.sealed_files().unwrap());
+
+    let corrupt = BlobRef {
+        checksum: first.checksum + 1,
+        ..first
+    };
+    assert!(read_blob(&path, &corrupt).is_err());
+
+    // A reopened store writes to a new file.
+    let blob_store = BlobStore::new(&path, 100, false).unwrap();
+    assert_eq!(2, blob_store.write(&[3; 10]).unwrap().file);
+}
@@ -0,0 +1,461 @@
+#![allow(clippy::needless_return)]
+
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use crate::error::{KVError, KVResult};
+use crate::storage::bitcask::bitcask_engine::{get_log_file_dir, get_sorted_gen_list};
+use crate::storage::bitcask::blob::read_blob;
+use crate::storage::bitcask::command::{
+    check_record, claimed_record_len, head_key, record_namespace, record_seq, set_record_seq,
+    Command, RECORD_HEADER_LEN,
+};
+use crate::storage::bitcask::events::{BitcaskEvent, EventListener};
+use crate::storage::bitcask::log_pointer::LogPointer;
+use crate::storage::bitcask::namespace::DEFAULT_NAMESPACE;
+
+/// One committed `set`, `remove`, `increment` or `merge`, `value` is `None` for a removal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangeEvent {
+    pub seq: u64,
+    /// Id of the namespace of `key`, `DEFAULT_NAMESPACE` for keys written through `Bitcask`.
+    pub namespace: u32,
+    pub key: Vec<u8>,
+    pub value: Option<Vec<u8>>,
+    /// Whether `value` is a merge operand rather than the new value, the delta in decimal for
+    /// an increment.
+    pub operand: bool,
+}
+
+impl ChangeEvent {
+    /// `None` for the record starting a batch. Fails when the value is in a blob file that
+    /// has been collected since.
+    fn new(path: &PathBuf, record: &[u8]) -> KVResult<Option<ChangeEvent>> {
+        let seq = record_seq(record);
+        let namespace = record_namespace(record);
+        let (key, value, operand) = match Command::from(record) {
+            Command::Set { key, value } => (key, Some(value), false),
+            Command::SetBlob { key, blob } => (key, Some(read_blob(path, &blob)?), false),
+            Command::Remove { key } => (key, None, false),
+            Command::Increment { key, delta } => (key, Some(delta.to_string().into_bytes()), true),
+            Command::Merge { key, operand } => (key, Some(operand), true),
+            Command::Batch { count: _ } => return Ok(None),
+        };
+
+        return Ok(Some(ChangeEvent {
+            seq,
+            namespace,
+            key,
+            value,
+            operand,
+        }));
+    }
+
+    /// The change `record` makes without its value, enough to tell whom it was meant for.
+    fn without_value(record: &[u8]) -> ChangeEvent {
+        let key = match Command::from(record) {
+            Command::Set { key, value: _ }
+            | Command::SetBlob { key, blob: _ }
+            | Command::Remove { key }
+            | Command::Increment { key, delta: _ }
+            | Command::Merge { key, operand: _ } => key,
+            Command::Batch { count: _ } => Vec::new(),
+        };
+
+        return ChangeEvent {
+            seq: record_seq(record),
+            namespace: record_namespace(record),
+            key,
+            value: None,
+            operand: false,
+        };
+    }
+}
+
+/// Which keys a subscriber is told about.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyFilter {
+    All,
+    Key(Vec<u8>),
+    Prefix(Vec<u8>),
+}
+
+impl KeyFilter {
+    /// `All` matches the changes of every namespace, the others only keys of the default one.
+    pub fn matches_event(&self, event: &ChangeEvent) -> bool {
+        if *self != KeyFilter::All && event.namespace != DEFAULT_NAMESPACE {
+            return false;
+        }
+
+        return self.matches(&event.key);
+    }
+
+    pub fn matches(&self, key: &[u8]) -> bool {
+        match self {
+            KeyFilter::All => true,
+            KeyFilter::Key(filter_key) => filter_key.as_slice() == key,
+            KeyFilter::Prefix(prefix) => key.starts_with(prefix),
+        }
+    }
+}
+
+/// Numbers committed records and hands them to every subscriber in commit order.
+///
+/// Sequence numbers are stamped into the records, so they survive restarts and merges.
+pub struct ChangeFeed {
+    path: PathBuf,
+    events: Arc<dyn EventListener>,
+    state: Mutex<FeedState>,
+}
+
+struct FeedState {
+    next_seq: u64,
+    subscribers: Vec<(KeyFilter, Sender<ChangeEvent>)>,
+}
+
+impl ChangeFeed {
+    /// `next_seq` is the number given to the next committed record.
+    pub fn new(path: &PathBuf, next_seq: u64, events: Arc<dyn EventListener>) -> ChangeFeed {
+        ChangeFeed {
+            path: path.to_owned(),
+            events,
+            state: Mutex::new(FeedState {
+                next_seq,
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn register(&self, filter: KeyFilter, subscriber: Sender<ChangeEvent>) {
+        self.state
+            .lock()
+            .unwrap()
+            .subscribers
+            .push((filter, subscriber));
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        return !self.state.lock().unwrap().subscribers.is_empty();
+    }
+
+    /// The sequence number of the last record numbered so far, 0 before the first.
+    pub fn last_seq(&self) -> u64 {
+        return self.state.lock().unwrap().next_seq.saturating_sub(1);
+    }
+
+    /// Stamps the next sequence numbers into `records`, about to be written in this order.
+    pub fn number(&self, records: &mut [(u64, Vec<u8>)]) {
+        let mut state = self.state.lock().unwrap();
+        for (_, record) in records.iter_mut() {
+            set_record_seq(record, state.next_seq);
+            state.next_seq += 1;
+        }
+    }
+
+    /// Sends the numbered `records` to the subscribers, subscribers whose receiver is gone
+    /// are dropped. So are the subscribers of a change whose value can not be read back, the
+    /// failure is reported as an event, they see the feed end rather than miss the change.
+    pub fn publish(&self, records: &[(u64, Vec<u8>)]) {
+        let mut state = self.state.lock().unwrap();
+        if state.subscribers.is_empty() {
+            return;
+        }
+
+        let mut events = Vec::with_capacity(records.len());
+        let mut dropped = Vec::new();
+        for (_, record) in records {
+            match ChangeEvent::new(&self.path, record) {
+                Ok(event) => events.extend(event),
+                Err(err) => {
+                    self.events.on_event(&BitcaskEvent::ChangeDropped {
+                        seq: record_seq(record),
+                        error: format!("{:?}", err),
+                    });
+                    dropped.push(ChangeEvent::without_value(record));
+                }
+            }
+        }
+        state.subscribers.retain(|(filter, subscriber)| {
+            !dropped.iter().any(|event| filter.matches_event(event))
+                && events
+                    .iter()
+                    .filter(|event| filter.matches_event(event))
+                    .all(|event| subscriber.send(event.clone()).is_ok())
+        });
+    }
+
+    /// Drops the subscribers of the set record starting with the numbered `head` whose value
+    /// could not be read back to be sent, and reports `err` as an event.
+    pub fn drop_change(&self, head: &[u8], err: KVError) {
+        self.events.on_event(&BitcaskEvent::ChangeDropped {
+            seq: record_seq(head),
+            error: format!("{:?}", err),
+        });
+
+        let dropped = ChangeEvent {
+            seq: record_seq(head),
+            namespace: record_namespace(head),
+            key: head_key(head).to_vec(),
+            value: None,
+            operand: false,
+        };
+        let mut state = self.state.lock().unwrap();
+        state
+            .subscribers
+            .retain(|(filter, _)| !filter.matches_event(&dropped));
+    }
+}
+
+/// Follows the generation files of a store and reads back the records appended to them,
+/// also when they are written by another process.
+pub struct LogTailer {
+    path: PathBuf,
+    gen: u64,
+    pos: u64,
+    last_seq: u64,
+}
+
+impl LogTailer {
+    pub fn new(path: &PathBuf) -> LogTailer {
+        LogTailer {
+            path: path.to_owned(),
+            gen: 0,
+            pos: 0,
+            last_seq: 0,
+        }
+    }
+
+    /// Hands `on_event` every whole record appended since the last call in sequence order, a
+    /// record still being written is returned by a later call. Generations below the one being
+    /// followed are never revisited, and records a merge copied into a newer file are not
+    /// returned again. After an error the records not returned yet are read again by the next
+    /// call.
+    ///
+    /// Only the position of each new record is kept in memory, the records are read back and
+    /// handed over one at a time.
+    pub fn read_new(&mut self, mut on_event: impl FnMut(ChangeEvent)) -> KVResult<()> {
+        let mut pointers = Vec::new();
+        let (end_gen, end_pos) = self.scan_new(&mut pointers)?;
+
+        // Merge output holds its records in no particular order.
+        pointers.sort_by_key(|(seq, _)| *seq);
+        let mut file: Option<(u64, File)> = None;
+        for (seq, log_pointer) in pointers {
+            if file.as_ref().is_none_or(|(gen, _)| *gen != log_pointer.gen) {
+                match File::open(get_log_file_dir(log_pointer.gen, &self.path)) {
+                    Ok(opened) => file = Some((log_pointer.gen, opened)),
+                    // Merged away since it was scanned, the next call finds the merge output.
+                    Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            let (_, file) = file.as_mut().unwrap();
+            let mut record = vec![0; log_pointer.len as usize];
+            file.seek(SeekFrom::Start(log_pointer.pos))?;
+            file.read_exact(&mut record)?;
+
+            // A value whose blob is collected has been overwritten, the newer value follows.
+            match ChangeEvent::new(&self.path, &record) {
+                Ok(event) => event.into_iter().for_each(&mut on_event),
+                Err(KVError::IOError(err)) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+            self.last_seq = seq;
+        }
+        self.gen = end_gen;
+        self.pos = end_pos;
+
+        return Ok(());
+    }
+
+    /// Adds the sequence numbers and pointers of the records after the followed position not
+    /// returned yet to `pointers`, returns the end of the last whole record. A batch still being written in the newest
+    /// generation ends the scan, the next call starts over from it. In an older generation it
+    /// was cut short by a crash and is never finished, so it is skipped.
+    fn scan_new(&self, pointers: &mut Vec<(u64, LogPointer)>) -> KVResult<(u64, u64)> {
+        let (mut current_gen, mut current_pos) = (self.gen, self.pos);
+
+        let gens = get_sorted_gen_list(&self.path)?;
+        for (i, gen) in gens.iter().copied().enumerate() {
+            if gen < current_gen {
+                continue;
+            }
+            if gen > current_gen {
+                current_gen = gen;
+                current_pos = 0;
+            }
+
+            let file = match File::open(get_log_file_dir(gen, &self.path)) {
+                Ok(file) => file,
+                // Merged away since the directory was listed.
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            let end = file.metadata()?.len();
+            let mut reader = BufReader::new(file);
+            reader.seek(SeekFrom::Start(current_pos))?;
+
+            let mut batch = Vec::new();
+            let mut batch_left = 0;
+            let mut pos = current_pos;
+            while let Some(record) = next_record(&mut reader, end.saturating_sub(pos))? {
+                let log_pointer = LogPointer::new(gen, pos, record.len() as u64);
+                pos += log_pointer.len;
+
+                if let Command::Batch { count } = Command::from(record.as_slice()) {
+                    batch_left = count;
+                } else {
+                    let seq = record_seq(&record);
+                    if seq > self.last_seq {
+                        batch.push((seq, log_pointer));
+                    }
+                    batch_left = batch_left.saturating_sub(1);
+                }
+                if batch_left == 0 {
+                    pointers.append(&mut batch);
+                    current_pos = pos;
+                }
+            }
+
+            let is_newest = i + 1 == gens.len();
+            if is_newest && current_pos < end {
+                break;
+            }
+        }
+
+        return Ok((current_gen, current_pos));
+    }
+}
+
+/// The next record of `reader` if it is whole and among the `left` bytes still in the file,
+/// `None` at a record still being written or cut short.
+fn next_record(reader: &mut impl Read, left: u64) -> KVResult<Option<Vec<u8>>> {
+    if left < RECORD_HEADER_LEN as u64 {
+        return Ok(None);
+    }
+
+    let mut record = vec![0; RECORD_HEADER_LEN];
+    reader.read_exact(&mut record)?;
+    let len = claimed_record_len(&record);
+    if len < RECORD_HEADER_LEN as u64 || len > left {
+        return Ok(None);
+    }
+    record.resize(len as usize, 0);
+    reader.read_exact(&mut record[RECORD_HEADER_LEN..])?;
+
+    return Ok(check_record(&record).ok().map(|_| record));
+}
+
+#[cfg(test)]
+fn missing_blob_record(key: &[u8]) -> Vec<u8> {
+    use crate::storage::bitcask::blob::BlobRef;
+
+    let blob = BlobRef {
+        file: 7,
+        offset: 0,
+        len: 10,
+        checksum: 0,
+    };
+    let mut record = Command::SetBlob {
+        key: key.to_vec(),
+        blob,
+    }
+    .parse();
+    set_record_seq(&mut record, 1);
+
+    return record;
+}
+
+#[test]
+fn change_feed_drop_subscribers_of_unreadable_change() {
+    use std::sync::mpsc::channel;
+
+    use crate::storage::bitcask::events::CollectEvents;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let events = Arc::new(CollectEvents::default());
+    let feed = ChangeFeed::new(&temp_dir.path().to_path_buf(), 1, events.clone());
+    let (subscriber, changes) = channel();
+    feed.register(KeyFilter::Key(b"key".to_vec()), subscriber);
+    let (other_subscriber, other_changes) = channel();
+    feed.register(KeyFilter::Key(b"other".to_vec()), other_subscriber);
+
+    feed.publish(&[(0, missing_blob_record(b"key"))]);
+
+    assert!(changes.recv().is_err());
+    assert!(other_changes.try_recv().is_err());
+    assert!(feed.has_subscribers());
+    assert!(matches!(
+        events.0.lock().unwrap()[0],
+        BitcaskEvent::ChangeDropped { seq: 1, .. }
+    ));
+}
+
+#[test]
+fn log_tailer_fail_on_unreadable_change() {
+    use std::fs::write;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let blob_dir = path.join(crate::storage::bitcask::blob::BLOB_DIR_NAME);
+    std::fs::create_dir_all(&blob_dir).unwrap();
+    // The value fails its checksum.
+    write(blob_dir.join("7.blob"), vec![1; 10]).unwrap();
+    write(get_log_file_dir(0, &path), missing_blob_record(b"key")).unwrap();
+
+    let mut tailer = LogTailer::new(&path);
+    assert!(tailer.read_new(|_| {}).is_err());
+    assert!(tailer.read_new(|_| {}).is_err());
+}
+
+#[test]
+fn log_tailer_retry_batch_still_being_written() {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let numbered = |command: Command, seq| {
+        let mut record = command.parse();
+        set_record_seq(&mut record, seq);
+        return record;
+    };
+    let set = |key: &[u8], seq| {
+        numbered(
+            Command::Set {
+                key: key.to_vec(),
+                value: b"value".to_vec(),
+            },
+            seq,
+        )
+    };
+    let mut log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(get_log_file_dir(0, &path))
+        .unwrap();
+    log.write_all(&set(b"key1", 1)).unwrap();
+    log.write_all(&numbered(Command::Batch { count: 2 }, 2))
+        .unwrap();
+    log.write_all(&set(b"key2", 3)).unwrap();
+
+    let mut tailer = LogTailer::new(&path);
+    let mut events = Vec::new();
+    tailer.read_new(|event| events.push(event)).unwrap();
+    assert_eq!(
+        vec![1],
+        events.iter().map(|event| event.seq).collect::<Vec<_>>()
+    );
+
+    log.write_all(&set(b"key3", 4)).unwrap();
+    events.clear();
+    tailer.read_new(|event| events.push(event)).unwrap();
+    assert_eq!(
+        vec![3, 4],
+        events.iter().map(|event| event.seq).collect::<Vec<_>>()
+    );
+    assert_eq!(b"key3".to_vec(), events[1].key);
+}
@@ -1,14 +1,79 @@
+#![allow(clippy::needless_return)]
+
+use crate::storage::bitcask::blob::BlobRef;
 use crate::utils::{u64_to_u8_array, u8_array_to_u64};
+use std::fmt;
 use std::mem::size_of;
 
+/// First bytes of every record, lets a scan find the next record after a corrupt region.
+pub const RECORD_MAGIC: [u8; 4] = [0xc5, 0x1f, 0xb1, 0x7c];
+
+/// Version of the record layout, changed whenever the layout does. Stores written in another
+/// layout are refused rather than read as corrupt.
+pub const RECORD_FORMAT_VERSION: u8 = 1;
+
+/// Offset of the format version, after the magic.
+const VERSION_OFFSET: usize = RECORD_MAGIC.len();
+
+/// Offset of the total length, after the format version.
+const TOTAL_LEN_OFFSET: usize = VERSION_OFFSET + size_of::<u8>();
+
+/// Offset of the checksum, after the total length.
+pub const CHECKSUM_OFFSET: usize = TOTAL_LEN_OFFSET + size_of::<u64>();
+
+/// Offset of the command type, the checksum covers the record from here to its end.
+const TYPE_OFFSET: usize = CHECKSUM_OFFSET + size_of::<u32>();
+
+/// Offset of the sequence number, after the command type.
+const SEQ_OFFSET: usize = TYPE_OFFSET + size_of::<u8>();
+
+/// Offset of the namespace id, after the sequence number.
+const NAMESPACE_OFFSET: usize = SEQ_OFFSET + size_of::<u64>();
+
+/// Offset of the key length, after the namespace id.
+const KEY_LEN_OFFSET: usize = NAMESPACE_OFFSET + size_of::<u32>();
+
+/// Magic, format version, total length, checksum, command type, sequence number, namespace id and key length,
+/// shared by every record.
+pub const RECORD_HEADER_LEN: usize = KEY_LEN_OFFSET + size_of::<u64>();
+
 enum CommandPrefix {
     Set = 0x00,
     Remove = 0x01,
+    SetBlob = 0x02,
+    Batch = 0x03,
+    Increment = 0x04,
+    Merge = 0x05,
 }
 
 pub enum Command {
-    Set { key: Vec<u8>, value: Vec<u8> },
-    Remove { key: Vec<u8> },
+    Set {
+        key: Vec<u8>,
+        value: Vec<u8>,
+    },
+    Remove {
+        key: Vec<u8>,
+    },
+    /// A set whose value lives in a blob file.
+    SetBlob {
+        key: Vec<u8>,
+        blob: BlobRef,
+    },
+    /// Comes right before the `count` records of one batch, which are only applied when all of
+    /// them made it to the log. Has an empty key.
+    Batch {
+        count: u64,
+    },
+    /// Adds `delta` to the 64-bit integer value of `key` when it is read or merged.
+    Increment {
+        key: Vec<u8>,
+        delta: i64,
+    },
+    /// An operand handed to the merge operator of the store when `key` is read or merged.
+    Merge {
+        key: Vec<u8>,
+        operand: Vec<u8>,
+    },
 }
 
 impl Command {
@@ -19,19 +84,20 @@ impl Command {
                 let command_type_byte = CommandPrefix::Set as u8;
                 let command_key_size = key.len() as u64;
                 let command_value_size = value.len() as u64;
-                let total_size = size_of::<u64>()
-                    + size_of::<u8>()
-                    + size_of::<u64>()
-                    + key.len()
-                    + size_of::<u64>()
-                    + value.len();
+                let total_size = RECORD_HEADER_LEN + key.len() + size_of::<u64>() + value.len();
 
+                res.append(&mut RECORD_MAGIC.to_vec());
+                res.push(RECORD_FORMAT_VERSION);
                 res.append(&mut u64_to_u8_array(total_size as u64).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
                 res.push(command_type_byte);
+                res.append(&mut u64_to_u8_array(0).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
                 res.append(&mut u64_to_u8_array(command_key_size).to_vec());
                 res.append(&mut key.clone());
                 res.append(&mut u64_to_u8_array(command_value_size).to_vec());
                 res.append(&mut value.clone());
+                update_record_checksum(&mut res);
 
                 return res;
             }
@@ -39,12 +105,94 @@ impl Command {
                 let mut res = Vec::new();
                 let command_type_byte = CommandPrefix::Remove as u8;
                 let command_key_size = key.len() as u64;
-                let total_size = size_of::<u64>() + size_of::<u8>() + size_of::<u64>() + key.len();
+                let total_size = RECORD_HEADER_LEN + key.len();
 
+                res.append(&mut RECORD_MAGIC.to_vec());
+                res.push(RECORD_FORMAT_VERSION);
                 res.append(&mut u64_to_u8_array(total_size as u64).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
                 res.push(command_type_byte);
+                res.append(&mut u64_to_u8_array(0).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
                 res.append(&mut u64_to_u8_array(command_key_size).to_vec());
                 res.append(&mut key.clone());
+                update_record_checksum(&mut res);
+                return res;
+            }
+            Command::SetBlob { key, blob } => {
+                let mut res = Vec::new();
+                let command_type_byte = CommandPrefix::SetBlob as u8;
+                let command_key_size = key.len() as u64;
+                let total_size = RECORD_HEADER_LEN + key.len() + BlobRef::ENCODED_LEN;
+
+                res.append(&mut RECORD_MAGIC.to_vec());
+                res.push(RECORD_FORMAT_VERSION);
+                res.append(&mut u64_to_u8_array(total_size as u64).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
+                res.push(command_type_byte);
+                res.append(&mut u64_to_u8_array(0).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
+                res.append(&mut u64_to_u8_array(command_key_size).to_vec());
+                res.append(&mut key.clone());
+                res.append(&mut blob.encode());
+                update_record_checksum(&mut res);
+                return res;
+            }
+            Command::Increment { key, delta } => {
+                let mut res = Vec::new();
+                let command_type_byte = CommandPrefix::Increment as u8;
+                let command_key_size = key.len() as u64;
+                let total_size = RECORD_HEADER_LEN + key.len() + size_of::<i64>();
+
+                res.append(&mut RECORD_MAGIC.to_vec());
+                res.push(RECORD_FORMAT_VERSION);
+                res.append(&mut u64_to_u8_array(total_size as u64).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
+                res.push(command_type_byte);
+                res.append(&mut u64_to_u8_array(0).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
+                res.append(&mut u64_to_u8_array(command_key_size).to_vec());
+                res.append(&mut key.clone());
+                res.append(&mut delta.to_le_bytes().to_vec());
+                update_record_checksum(&mut res);
+                return res;
+            }
+            Command::Merge { key, operand } => {
+                let mut res = Vec::new();
+                let command_type_byte = CommandPrefix::Merge as u8;
+                let command_key_size = key.len() as u64;
+                let command_operand_size = operand.len() as u64;
+                let total_size = RECORD_HEADER_LEN + key.len() + size_of::<u64>() + operand.len();
+
+                res.append(&mut RECORD_MAGIC.to_vec());
+                res.push(RECORD_FORMAT_VERSION);
+                res.append(&mut u64_to_u8_array(total_size as u64).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
+                res.push(command_type_byte);
+                res.append(&mut u64_to_u8_array(0).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
+                res.append(&mut u64_to_u8_array(command_key_size).to_vec());
+                res.append(&mut key.clone());
+                res.append(&mut u64_to_u8_array(command_operand_size).to_vec());
+                res.append(&mut operand.clone());
+                update_record_checksum(&mut res);
+                return res;
+            }
+            Command::Batch { count } => {
+                let mut res = Vec::new();
+                let command_type_byte = CommandPrefix::Batch as u8;
+                let total_size = RECORD_HEADER_LEN + size_of::<u64>();
+
+                res.append(&mut RECORD_MAGIC.to_vec());
+                res.push(RECORD_FORMAT_VERSION);
+                res.append(&mut u64_to_u8_array(total_size as u64).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
+                res.push(command_type_byte);
+                res.append(&mut u64_to_u8_array(0).to_vec());
+                res.append(&mut 0u32.to_le_bytes().to_vec());
+                res.append(&mut u64_to_u8_array(0).to_vec());
+                res.append(&mut u64_to_u8_array(*count).to_vec());
+                update_record_checksum(&mut res);
                 return res;
             }
         }
@@ -53,15 +201,19 @@ impl Command {
 
 impl From<&[u8]> for Command {
     fn from(data: &[u8]) -> Command {
-        let mut _current_pos = 0;
+        let mut _current_pos = TOTAL_LEN_OFFSET;
 
         let _total_size = &data[_current_pos.._current_pos + size_of::<u64>()];
 
         _current_pos += size_of::<u64>();
+        _current_pos += size_of::<u32>();
 
         let command_type_byte = &data[_current_pos.._current_pos + size_of::<u8>()];
         _current_pos += size_of::<u8>();
 
+        _current_pos += size_of::<u64>();
+        _current_pos += size_of::<u32>();
+
         let command_key_size_bytes = &data[_current_pos.._current_pos + size_of::<u64>()];
         let command_key_size = u8_array_to_u64(&[
             command_key_size_bytes[0],
@@ -99,6 +251,29 @@ impl From<&[u8]> for Command {
                 key: key_bytes.to_vec(),
                 value: value_bytes.to_vec(),
             }
+        } else if command_type_byte[0] == CommandPrefix::SetBlob as u8 {
+            Command::SetBlob {
+                key: key_bytes.to_vec(),
+                blob: BlobRef::decode(&data[_current_pos.._current_pos + BlobRef::ENCODED_LEN]),
+            }
+        } else if command_type_byte[0] == CommandPrefix::Increment as u8 {
+            let mut delta = [0; 8];
+            delta.copy_from_slice(&data[_current_pos.._current_pos + size_of::<i64>()]);
+            Command::Increment {
+                key: key_bytes.to_vec(),
+                delta: i64::from_le_bytes(delta),
+            }
+        } else if command_type_byte[0] == CommandPrefix::Merge as u8 {
+            let operand_size = read_u64(data, _current_pos) as usize;
+            _current_pos += size_of::<u64>();
+            Command::Merge {
+                key: key_bytes.to_vec(),
+                operand: data[_current_pos.._current_pos + operand_size].to_vec(),
+            }
+        } else if command_type_byte[0] == CommandPrefix::Batch as u8 {
+            Command::Batch {
+                count: read_u64(data, _current_pos),
+            }
         } else {
             Command::Remove {
                 key: key_bytes.to_vec(),
@@ -106,3 +281,423 @@ impl From<&[u8]> for Command {
         }
     }
 }
+
+/// Sequence number of an encoded record.
+pub fn record_seq(record: &[u8]) -> u64 {
+    return read_u64(record, SEQ_OFFSET);
+}
+
+/// Stamps `seq` into an encoded record, `parse` leaves it at 0 until the record is appended.
+pub fn set_record_seq(record: &mut [u8], seq: u64) {
+    record[SEQ_OFFSET..SEQ_OFFSET + size_of::<u64>()].copy_from_slice(&u64_to_u8_array(seq));
+    update_record_checksum(record);
+}
+
+/// Namespace id of an encoded record, 0 for the default namespace.
+pub fn record_namespace(record: &[u8]) -> u32 {
+    let mut namespace = [0; 4];
+    namespace.copy_from_slice(&record[NAMESPACE_OFFSET..KEY_LEN_OFFSET]);
+    return u32::from_le_bytes(namespace);
+}
+
+/// Moves an encoded record to `namespace`, `parse` puts every record in the default one.
+pub fn set_record_namespace(record: &mut [u8], namespace: u32) {
+    record[NAMESPACE_OFFSET..KEY_LEN_OFFSET].copy_from_slice(&namespace.to_le_bytes());
+    update_record_checksum(record);
+}
+
+/// Start of a set record of `key` in `namespace` and a `value_len` bytes value, up to the value.
+/// Its checksum is only known once the value is written, see `record_hasher`.
+pub fn set_record_head(namespace: u32, key: &[u8], value_len: u64) -> Vec<u8> {
+    let mut res = Vec::new();
+    let total_size = (RECORD_HEADER_LEN + key.len() + size_of::<u64>()) as u64 + value_len;
+
+    res.append(&mut RECORD_MAGIC.to_vec());
+    res.push(RECORD_FORMAT_VERSION);
+    res.append(&mut u64_to_u8_array(total_size).to_vec());
+    res.append(&mut 0u32.to_le_bytes().to_vec());
+    res.push(CommandPrefix::Set as u8);
+    res.append(&mut u64_to_u8_array(0).to_vec());
+    res.append(&mut namespace.to_le_bytes().to_vec());
+    res.append(&mut u64_to_u8_array(key.len() as u64).to_vec());
+    res.append(&mut key.to_vec());
+    res.append(&mut u64_to_u8_array(value_len).to_vec());
+
+    return res;
+}
+
+/// Bytes of the record whose first `RECORD_HEADER_LEN` bytes are `header` up to its value,
+/// the whole record for anything but a set. `len` is the length the record is known to have,
+/// the lengths in `header` are checked against it.
+pub fn record_head_len(header: &[u8], len: u64) -> Result<usize, RecordDefect> {
+    if !header.starts_with(&RECORD_MAGIC) {
+        return Err(RecordDefect::Magic);
+    }
+    if header[VERSION_OFFSET] != RECORD_FORMAT_VERSION {
+        return Err(RecordDefect::Version);
+    }
+
+    let total_len = read_u64(header, TOTAL_LEN_OFFSET);
+    if total_len != len || total_len < RECORD_HEADER_LEN as u64 {
+        return Err(RecordDefect::Length);
+    }
+
+    let key_len = read_u64(header, KEY_LEN_OFFSET);
+    let body_len = total_len - RECORD_HEADER_LEN as u64;
+    if header[TYPE_OFFSET] == CommandPrefix::Set as u8 {
+        return match key_len.checked_add(size_of::<u64>() as u64) {
+            Some(head_body_len) if head_body_len <= body_len => {
+                Ok(RECORD_HEADER_LEN + head_body_len as usize)
+            }
+            _ => Err(RecordDefect::Length),
+        };
+    }
+    if key_len > body_len {
+        return Err(RecordDefect::Length);
+    }
+
+    return Ok(total_len as usize);
+}
+
+/// Whether `record` is a removal.
+pub fn is_removal(record: &[u8]) -> bool {
+    return record[TYPE_OFFSET] == CommandPrefix::Remove as u8;
+}
+
+/// Key of the record starting with `head`, as checked by `record_head_len`, empty for a batch.
+pub fn head_key(head: &[u8]) -> &[u8] {
+    let key_len = read_u64(head, KEY_LEN_OFFSET) as usize;
+    return &head[RECORD_HEADER_LEN..RECORD_HEADER_LEN + key_len];
+}
+
+/// Length of the value following `head`, a set record up to its value, `None` for any other
+/// record.
+pub fn head_value_len(head: &[u8]) -> Option<u64> {
+    if head[TYPE_OFFSET] != CommandPrefix::Set as u8 {
+        return None;
+    }
+
+    return Some(read_u64(head, head.len() - size_of::<u64>()));
+}
+
+/// Checksum stored in a record.
+pub fn record_checksum(record: &[u8]) -> u32 {
+    let mut checksum = [0; 4];
+    checksum.copy_from_slice(&record[CHECKSUM_OFFSET..TYPE_OFFSET]);
+    return u32::from_le_bytes(checksum);
+}
+
+/// Hasher over the part of `head` covered by the checksum, the value is fed into it as it is
+/// streamed.
+pub fn record_hasher(head: &[u8]) -> crc32fast::Hasher {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&head[TYPE_OFFSET..]);
+    return hasher;
+}
+
+fn update_record_checksum(record: &mut [u8]) {
+    let checksum = crc32fast::hash(&record[TYPE_OFFSET..]);
+    record[CHECKSUM_OFFSET..TYPE_OFFSET].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Why a record failed `check_record`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordDefect {
+    /// Does not start with `RECORD_MAGIC`.
+    Magic,
+    /// Written in another layout than `RECORD_FORMAT_VERSION`.
+    Version,
+    /// The file ends before the record does.
+    Truncated,
+    /// The lengths in the record do not add up.
+    Length,
+    /// Not a known command type.
+    Type,
+    Checksum,
+}
+
+impl fmt::Display for RecordDefect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let defect = match self {
+            RecordDefect::Magic => "magic",
+            RecordDefect::Version => "version",
+            RecordDefect::Truncated => "truncated",
+            RecordDefect::Length => "length",
+            RecordDefect::Type => "type",
+            RecordDefect::Checksum => "checksum",
+        };
+        write!(f, "{}", defect)
+    }
+}
+
+/// Length of the record starting `buffer`, checked against the end of `buffer`.
+pub fn record_len(buffer: &[u8]) -> Result<usize, RecordDefect> {
+    if !buffer.starts_with(&RECORD_MAGIC[..buffer.len().min(RECORD_MAGIC.len())]) {
+        return Err(RecordDefect::Magic);
+    }
+    if buffer.len() > VERSION_OFFSET && buffer[VERSION_OFFSET] != RECORD_FORMAT_VERSION {
+        return Err(RecordDefect::Version);
+    }
+    if buffer.len() < RECORD_HEADER_LEN {
+        return Err(RecordDefect::Truncated);
+    }
+
+    let total_len = read_u64(buffer, TOTAL_LEN_OFFSET) as usize;
+    if total_len < RECORD_HEADER_LEN {
+        return Err(RecordDefect::Length);
+    }
+    if total_len > buffer.len() {
+        return Err(RecordDefect::Truncated);
+    }
+
+    return Ok(total_len);
+}
+
+/// Length a record claims in its header `header`, not checked against anything.
+pub fn claimed_record_len(header: &[u8]) -> u64 {
+    return read_u64(header, TOTAL_LEN_OFFSET);
+}
+
+/// Checks the record starting `buffer` and returns its length. A record passing the check is
+/// safe to hand to `Command::from`.
+pub fn check_record(buffer: &[u8]) -> Result<usize, RecordDefect> {
+    let total_len = record_len(buffer)?;
+    let record = &buffer[..total_len];
+
+    let key_len = read_u64(record, KEY_LEN_OFFSET);
+    let body_len = (total_len - RECORD_HEADER_LEN) as u64;
+    let expected_len = match record[TYPE_OFFSET] {
+        byte if byte == CommandPrefix::Set as u8 || byte == CommandPrefix::Merge as u8 => {
+            let value_len_end = key_len.checked_add(size_of::<u64>() as u64);
+            if value_len_end.is_none_or(|end| end > body_len) {
+                return Err(RecordDefect::Length);
+            }
+            let value_len = read_u64(record, RECORD_HEADER_LEN + key_len as usize);
+            key_len.checked_add(size_of::<u64>() as u64 + value_len)
+        }
+        byte if byte == CommandPrefix::Remove as u8 => Some(key_len),
+        byte if byte == CommandPrefix::SetBlob as u8 => {
+            key_len.checked_add(BlobRef::ENCODED_LEN as u64)
+        }
+        byte if byte == CommandPrefix::Batch as u8 => key_len.checked_add(size_of::<u64>() as u64),
+        byte if byte == CommandPrefix::Increment as u8 => {
+            key_len.checked_add(size_of::<i64>() as u64)
+        }
+        _ => return Err(RecordDefect::Type),
+    };
+    if expected_len != Some(body_len) {
+        return Err(RecordDefect::Length);
+    }
+
+    if record_checksum(record) != crc32fast::hash(&record[TYPE_OFFSET..]) {
+        return Err(RecordDefect::Checksum);
+    }
+
+    return Ok(total_len);
+}
+
+/// Key and claimed length of a record at the start of the corrupt bytes `buffer`, when the key
+/// length and type in its header are still plausible. Nothing else of the record is trusted.
+pub fn salvage_record_key(buffer: &[u8]) -> Option<(Vec<u8>, usize)> {
+    if buffer.len() < RECORD_HEADER_LEN {
+        return None;
+    }
+    let known_type = [
+        CommandPrefix::Set as u8,
+        CommandPrefix::Remove as u8,
+        CommandPrefix::SetBlob as u8,
+        CommandPrefix::Increment as u8,
+        CommandPrefix::Merge as u8,
+    ]
+    .contains(&buffer[TYPE_OFFSET]);
+    if !known_type {
+        return None;
+    }
+
+    let key_len = read_u64(buffer, KEY_LEN_OFFSET);
+    if key_len > (buffer.len() - RECORD_HEADER_LEN) as u64 {
+        return None;
+    }
+    let key = buffer[RECORD_HEADER_LEN..RECORD_HEADER_LEN + key_len as usize].to_vec();
+
+    return Some((key, read_u64(buffer, TOTAL_LEN_OFFSET) as usize));
+}
+
+/// Offset of the first record in `buffer` passing `check_record`.
+pub fn next_record_start(buffer: &[u8]) -> Option<usize> {
+    return buffer
+        .windows(RECORD_MAGIC.len())
+        .enumerate()
+        .filter(|(_, window)| *window == RECORD_MAGIC)
+        .map(|(pos, _)| pos)
+        .find(|pos| check_record(&buffer[*pos..]).is_ok());
+}
+
+fn read_u64(buffer: &[u8], pos: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buffer[pos..pos + size_of::<u64>()]);
+    return u8_array_to_u64(&bytes);
+}
+
+#[test]
+fn command_record_seq_round_trip() {
+    let mut record = Command::Set {
+        key: b"key".to_vec(),
+        value: b"value".to_vec(),
+    }
+    .parse();
+    assert_eq!(0, record_seq(&record));
+
+    set_record_seq(&mut record, 42);
+    assert_eq!(42, record_seq(&record));
+    match Command::from(record.as_slice()) {
+        Command::Set { key, value } => {
+            assert_eq!((b"key".to_vec(), b"value".to_vec()), (key, value))
+        }
+        _ => panic!("expected a set"),
+    }
+}
+
+#[test]
+fn command_check_record() {
+    let mut record = Command::Set {
+        key: b"key".to_vec(),
+        value: b"value".to_vec(),
+    }
+    .parse();
+    set_record_seq(&mut record, 7);
+    record.extend_from_slice(&[1, 2, 3]);
+    assert_eq!(Ok(record.len() - 3), check_record(&record));
+
+    let record_len = record.len() - 3;
+    assert_eq!(
+        Err(RecordDefect::Truncated),
+        check_record(&record[..record_len - 1])
+    );
+
+    let mut corrupt = record.clone();
+    corrupt[record_len - 1] ^= 0xff;
+    assert_eq!(Err(RecordDefect::Checksum), check_record(&corrupt));
+
+    let mut corrupt = record.clone();
+    corrupt[TYPE_OFFSET] = 9;
+    assert_eq!(Err(RecordDefect::Type), check_record(&corrupt));
+
+    let mut corrupt = record.clone();
+    corrupt[KEY_LEN_OFFSET] = 200;
+    assert_eq!(Err(RecordDefect::Length), check_record(&corrupt));
+
+    let mut corrupt = record.clone();
+    corrupt[0] = 0;
+    assert_eq!(Err(RecordDefect::Magic), check_record(&corrupt));
+
+    let mut garbage = vec![0; 5];
+    garbage.extend_from_slice(&RECORD_MAGIC);
+    garbage.extend_from_slice(&record);
+    assert_eq!(Some(9), next_record_start(&garbage));
+    assert_eq!(None, next_record_start(&record[1..]));
+
+    let remove = Command::Remove {
+        key: b"key".to_vec(),
+    }
+    .parse();
+    assert_eq!(Ok(remove.len()), check_record(&remove));
+}
+
+#[test]
+fn command_blob_record_round_trip() {
+    let blob = BlobRef {
+        file: 3,
+        offset: 1024,
+        len: 4096,
+        checksum: 42,
+    };
+    let record = Command::SetBlob {
+        key: b"key".to_vec(),
+        blob,
+    }
+    .parse();
+    assert_eq!(Ok(record.len()), check_record(&record));
+
+    match Command::from(record.as_slice()) {
+        Command::SetBlob { key, blob: decoded } => {
+            assert_eq!((b"key".to_vec(), blob), (key, decoded))
+        }
+        _ => panic!("expected a blob set"),
+    }
+}
+
+#[test]
+fn command_streamed_set_record() {
+    let record = Command::Set {
+        key: b"key".to_vec(),
+        value: b"value".to_vec(),
+    }
+    .parse();
+
+    let mut head = set_record_head(0, b"key", 5);
+    let len = head.len() as u64 + 5;
+    assert_eq!(
+        Ok(head.len()),
+        record_head_len(&head[..RECORD_HEADER_LEN], len)
+    );
+    assert_eq!(b"key", head_key(&head));
+    let mut bad_head = head.clone();
+    bad_head[KEY_LEN_OFFSET..KEY_LEN_OFFSET + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(Err(RecordDefect::Length), record_head_len(&bad_head, len));
+    assert_eq!(Err(RecordDefect::Length), record_head_len(&head, len + 1));
+    assert_eq!(Some(5), head_value_len(&head));
+    let mut hasher = record_hasher(&head);
+    hasher.update(b"value");
+    head.extend_from_slice(b"value");
+    head[CHECKSUM_OFFSET..TYPE_OFFSET].copy_from_slice(&hasher.finalize().to_le_bytes());
+    assert_eq!(record, head);
+}
+
+#[test]
+fn command_namespace_and_batch_records() {
+    let mut record = Command::Remove {
+        key: b"key".to_vec(),
+    }
+    .parse();
+    assert_eq!(0, record_namespace(&record));
+
+    set_record_namespace(&mut record, 7);
+    set_record_seq(&mut record, 42);
+    assert_eq!((7, 42), (record_namespace(&record), record_seq(&record)));
+    assert_eq!(Ok(record.len()), check_record(&record));
+
+    let batch = Command::Batch { count: 3 }.parse();
+    assert_eq!(Ok(batch.len()), check_record(&batch));
+    match Command::from(batch.as_slice()) {
+        Command::Batch { count } => assert_eq!(3, count),
+        _ => panic!("expected a batch"),
+    }
+}
+
+#[test]
+fn command_operand_records_round_trip() {
+    let record = Command::Increment {
+        key: b"key".to_vec(),
+        delta: -3,
+    }
+    .parse();
+    assert_eq!(Ok(record.len()), check_record(&record));
+    match Command::from(record.as_slice()) {
+        Command::Increment { key, delta } => assert_eq!((b"key".to_vec(), -3), (key, delta)),
+        _ => panic!("expected an increment"),
+    }
+
+    let record = Command::Merge {
+        key: b"key".to_vec(),
+        operand: b"operand".to_vec(),
+    }
+    .parse();
+    assert_eq!(Ok(record.len()), check_record(&record));
+    match Command::from(record.as_slice()) {
+        Command::Merge { key, operand } => {
+            assert_eq!((b"key".to_vec(), b"operand".to_vec()), (key, operand))
+        }
+        _ => panic!("expected a merge operand"),
+    }
+}
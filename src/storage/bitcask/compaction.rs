@@ -0,0 +1,250 @@
+#![allow(clippy::needless_return)]
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::error::KVResult;
+
+/// Runs merges on dedicated background threads so `set` and `remove` never wait on them.
+///
+/// At most `max_concurrent_merges` merges run at once, each on its own worker thread, and the
+/// bytes they copy are throttled through one shared `CompactionContext`.
+pub struct Compactor {
+    context: Arc<CompactionContext>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+pub struct CompactionContext {
+    control: Mutex<CompactionControl>,
+    changed: Condvar,
+    bytes_per_sec: Option<u64>,
+    throttle: Mutex<ThrottleState>,
+}
+
+#[derive(Default)]
+struct CompactionControl {
+    paused: bool,
+    shutdown: bool,
+    requested: bool,
+    forced: bool,
+    running: usize,
+    merges: u64,
+    last_error: Option<String>,
+}
+
+struct ThrottleState {
+    window_start: Instant,
+    window_bytes: u64,
+}
+
+impl Compactor {
+    /// Spawns the worker threads, `merge` is called with `forced` set when the merge was asked
+    /// for explicitly rather than by a write crossing the compaction threshold, and returns
+    /// whether it found anything to merge.
+    pub fn start<F>(max_concurrent_merges: usize, bytes_per_sec: Option<u64>, merge: F) -> Compactor
+    where
+        F: Fn(&CompactionContext, bool) -> KVResult<bool> + Send + Sync + 'static,
+    {
+        let context = Arc::new(CompactionContext {
+            control: Mutex::new(CompactionControl::default()),
+            changed: Condvar::new(),
+            bytes_per_sec,
+            throttle: Mutex::new(ThrottleState {
+                window_start: Instant::now(),
+                window_bytes: 0,
+            }),
+        });
+        let merge = Arc::new(merge);
+
+        let workers = (0..max_concurrent_merges.max(1))
+            .map(|_| {
+                let context = context.clone();
+                let merge = merge.clone();
+                spawn(move || run_worker(&context, &*merge))
+            })
+            .collect();
+
+        return Compactor { context, workers };
+    }
+
+    pub fn request(&self, forced: bool) {
+        let mut control = self.context.control.lock().unwrap();
+        control.requested = true;
+        control.forced |= forced;
+        self.context.changed.notify_all();
+    }
+
+    pub fn pause(&self) {
+        let mut control = self.context.control.lock().unwrap();
+        control.paused = true;
+        self.context.changed.notify_all();
+    }
+
+    pub fn resume(&self) {
+        let mut control = self.context.control.lock().unwrap();
+        control.paused = false;
+        self.context.changed.notify_all();
+    }
+
+    /// Blocks until no merge is running or requested, returns immediately while paused.
+    /// The error of the last failed merge, if any, is handed back once.
+    pub fn wait(&self) -> Option<String> {
+        let mut control = self.context.control.lock().unwrap();
+        while !control.shutdown && !control.paused && (control.running > 0 || control.requested) {
+            control = self.context.changed.wait(control).unwrap();
+        }
+
+        return control.last_error.take();
+    }
+
+    /// Whether a merge is requested and not picked up yet.
+    #[cfg(test)]
+    pub fn is_requested(&self) -> bool {
+        self.context.control.lock().unwrap().requested
+    }
+
+    pub fn merges(&self) -> u64 {
+        self.context.control.lock().unwrap().merges
+    }
+}
+
+impl Drop for Compactor {
+    fn drop(&mut self) {
+        {
+            let mut control = self.context.control.lock().unwrap();
+            control.shutdown = true;
+            self.context.changed.notify_all();
+        }
+
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn run_worker<F>(context: &CompactionContext, merge: &F)
+where
+    F: Fn(&CompactionContext, bool) -> KVResult<bool>,
+{
+    loop {
+        let forced = {
+            let mut control = context.control.lock().unwrap();
+            while !control.shutdown && (control.paused || !control.requested) {
+                control = context.changed.wait(control).unwrap();
+            }
+            if control.shutdown {
+                return;
+            }
+
+            control.requested = false;
+            control.running += 1;
+            std::mem::take(&mut control.forced)
+        };
+
+        let result = merge(context, forced);
+
+        let mut control = context.control.lock().unwrap();
+        control.running -= 1;
+        match result {
+            Ok(true) => control.merges += 1,
+            Ok(false) => {}
+            Err(err) => control.last_error = Some(format!("{:?}", err)),
+        }
+        context.changed.notify_all();
+    }
+}
+
+impl CompactionContext {
+    /// Called by a merge for every `bytes` it copies, blocks while compaction is paused and
+    /// sleeps long enough to keep all merges together under `bytes_per_sec`.
+    pub fn throttle(&self, bytes: u64) {
+        {
+            let mut control = self.control.lock().unwrap();
+            while control.paused && !control.shutdown {
+                control = self.changed.wait(control).unwrap();
+            }
+            if control.shutdown {
+                return;
+            }
+        }
+
+        let bytes_per_sec = match self.bytes_per_sec {
+            Some(bytes_per_sec) if bytes_per_sec > 0 => bytes_per_sec,
+            _ => return,
+        };
+
+        let delay = {
+            let mut throttle = self.throttle.lock().unwrap();
+
+            // Forget budget left over from idle periods so a merge can not burst through it.
+            let expected = expected_duration(throttle.window_bytes, bytes_per_sec);
+            if throttle.window_start.elapsed() > expected + Duration::from_secs(1) {
+                throttle.window_start = Instant::now();
+                throttle.window_bytes = 0;
+            }
+
+            throttle.window_bytes += bytes;
+            expected_duration(throttle.window_bytes, bytes_per_sec)
+                .checked_sub(throttle.window_start.elapsed())
+        };
+
+        if let Some(delay) = delay {
+            sleep(delay);
+        }
+    }
+}
+
+fn expected_duration(bytes: u64, bytes_per_sec: u64) -> Duration {
+    Duration::from_secs_f64(bytes as f64 / bytes_per_sec as f64)
+}
+
+#[test]
+fn compaction_throttle_limits_rate() {
+    let compactor = Compactor::start(1, Some(16 * 1024), |context, _| {
+        for _ in 0..8 {
+            context.throttle(1024);
+        }
+        Ok(true)
+    });
+
+    let start = Instant::now();
+    compactor.request(true);
+    compactor.wait();
+
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert_eq!(1, compactor.merges());
+}
+
+#[test]
+fn compaction_pause_and_resume() {
+    let compactor = Compactor::start(2, None, |_, _| Ok(true));
+
+    compactor.pause();
+    compactor.request(false);
+    compactor.wait();
+    assert_eq!(0, compactor.merges());
+
+    compactor.resume();
+    compactor.wait();
+    assert_eq!(1, compactor.merges());
+}
+
+#[test]
+fn compaction_run_merges_concurrently() {
+    // Each merge waits for another one to run next to it.
+    let both_running = Arc::new(std::sync::Barrier::new(2));
+    let compactor = Compactor::start(2, None, move |_, _| {
+        both_running.wait();
+        Ok(true)
+    });
+
+    compactor.request(true);
+    while compactor.context.control.lock().unwrap().running == 0 {
+        std::thread::yield_now();
+    }
+    compactor.request(true);
+    compactor.wait();
+
+    assert_eq!(2, compactor.merges());
+}
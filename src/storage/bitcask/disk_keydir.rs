@@ -0,0 +1,484 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{create_dir_all, remove_file, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::error::KVResult;
+use crate::storage::bitcask::keydir::{hash, KeyDir};
+use crate::storage::bitcask::log_pointer::LogPointer;
+
+/// Directory inside the store holding the table files of on-disk keydirs.
+const KEYDIR_DIR_NAME: &str = "keydir";
+
+const PAGE_SIZE: usize = 4096;
+
+/// Bytes in use in the page, then the id of the next page of the bucket, 0 for none.
+const PAGE_HEADER_LEN: usize = size_of::<u16>() + size_of::<u64>();
+
+/// Key length, then generation, offset and length of the record after the key.
+const ENTRY_FIXED_LEN: usize = size_of::<u16>() + 3 * size_of::<u64>();
+
+/// Longer keys do not fit a page and are kept in memory.
+const MAX_PAGE_KEY_LEN: usize = PAGE_SIZE - PAGE_HEADER_LEN - ENTRY_FIXED_LEN;
+
+const INITIAL_BUCKETS: u64 = 64;
+
+/// Average keys per bucket before the table doubles, most buckets then fit one page.
+const KEYS_PER_BUCKET: u64 = 64;
+
+const BLOOM_BITS_PER_KEY: u64 = 10;
+
+/// With 10 bits per key, about 1% of lookups for absent keys still read a page.
+const BLOOM_HASHES: u64 = 7;
+
+static NEXT_TABLE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps the keydir in a hash table file of fixed-size pages, one chain of pages per bucket,
+/// so only a bounded cache of pages is held in memory. A bloom filter answers most lookups
+/// for absent keys without reading a page.
+///
+/// The table is rebuilt from the log on every open and never synced.
+pub(crate) struct DiskKeyDir {
+    table: Mutex<Table>,
+}
+
+impl DiskKeyDir {
+    #[allow(clippy::ptr_arg)]
+    pub fn new(path: &PathBuf, cache_bytes: u64) -> KVResult<DiskKeyDir> {
+        let cache_pages = (cache_bytes as usize / PAGE_SIZE).max(1);
+        let table = Table::new(&path.join(KEYDIR_DIR_NAME), INITIAL_BUCKETS, cache_pages)?;
+
+        return Ok(DiskKeyDir {
+            table: Mutex::new(table),
+        });
+    }
+}
+
+impl KeyDir for DiskKeyDir {
+    fn get(&self, key: &[u8]) -> KVResult<Option<LogPointer>> {
+        return self.table.lock().unwrap().get(key);
+    }
+
+    fn insert(&mut self, key: Vec<u8>, log_pointer: LogPointer) -> KVResult<Option<LogPointer>> {
+        return self.table.lock().unwrap().insert(key, log_pointer);
+    }
+
+    fn remove(&mut self, key: &[u8]) -> KVResult<Option<LogPointer>> {
+        return self.table.lock().unwrap().remove(key);
+    }
+
+    fn len(&self) -> usize {
+        return self.table.lock().unwrap().len;
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&[u8], LogPointer)) -> KVResult<()> {
+        return self.table.lock().unwrap().for_each(f);
+    }
+
+    /// Cached pages, the bloom filter and the keys kept in memory.
+    fn memory_bytes(&self) -> u64 {
+        let table = self.table.lock().unwrap();
+        let cache_bytes = table.cache.pages.len() * PAGE_SIZE;
+        let bloom_bytes = table.bloom.bits.capacity() * size_of::<u64>();
+
+        return (cache_bytes + bloom_bytes) as u64 + table.long_keys.memory_bytes();
+    }
+}
+
+struct Table {
+    dir: PathBuf,
+    file: File,
+    /// A power of two, bucket `b` starts at page `b`.
+    buckets: u64,
+    pages: u64,
+    len: usize,
+    cache: PageCache,
+    bloom: BloomFilter,
+    /// Keys too long for a page.
+    long_keys: HashMap<Vec<u8>, LogPointer>,
+}
+
+impl Table {
+    fn new(dir: &PathBuf, buckets: u64, cache_pages: usize) -> KVResult<Table> {
+        let file = create_table_file(dir)?;
+        file.set_len(buckets * PAGE_SIZE as u64)?;
+
+        return Ok(Table {
+            dir: dir.to_owned(),
+            file,
+            buckets,
+            pages: buckets,
+            len: 0,
+            cache: PageCache::new(cache_pages),
+            bloom: BloomFilter::new(buckets * KEYS_PER_BUCKET),
+            long_keys: HashMap::new(),
+        });
+    }
+
+    fn get(&mut self, key: &[u8]) -> KVResult<Option<LogPointer>> {
+        if key.len() > MAX_PAGE_KEY_LEN {
+            return Ok(self.long_keys.get(key).copied());
+        }
+        if !self.bloom.may_contain(key) {
+            return Ok(None);
+        }
+
+        match self.find(key)? {
+            Some((page_id, pos)) => {
+                let page = self.cache.page(&mut self.file, page_id, false)?;
+                return Ok(Some(read_entry_pointer(page, pos, key.len())));
+            }
+            None => return Ok(None),
+        }
+    }
+
+    fn insert(&mut self, key: Vec<u8>, log_pointer: LogPointer) -> KVResult<Option<LogPointer>> {
+        if key.len() > MAX_PAGE_KEY_LEN {
+            let old_log_pointer = self.long_keys.insert(key, log_pointer);
+            if old_log_pointer.is_none() {
+                self.len += 1;
+            }
+            return Ok(old_log_pointer);
+        }
+
+        if self.bloom.may_contain(&key) {
+            if let Some((page_id, pos)) = self.find(&key)? {
+                let page = self.cache.page(&mut self.file, page_id, true)?;
+                let old_log_pointer = read_entry_pointer(page, pos, key.len());
+                write_entry_pointer(page, pos, key.len(), &log_pointer);
+                return Ok(Some(old_log_pointer));
+            }
+        }
+
+        if self.len as u64 >= self.buckets * KEYS_PER_BUCKET {
+            self.grow()?;
+        }
+        self.append(&key, &log_pointer)?;
+        self.bloom.add(&key);
+        self.len += 1;
+
+        return Ok(None);
+    }
+
+    fn remove(&mut self, key: &[u8]) -> KVResult<Option<LogPointer>> {
+        if key.len() > MAX_PAGE_KEY_LEN {
+            let old_log_pointer = self.long_keys.remove(key);
+            if old_log_pointer.is_some() {
+                self.len -= 1;
+            }
+            return Ok(old_log_pointer);
+        }
+        if !self.bloom.may_contain(key) {
+            return Ok(None);
+        }
+
+        let (page_id, pos) = match self.find(key)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let page = self.cache.page(&mut self.file, page_id, true)?;
+        let old_log_pointer = read_entry_pointer(page, pos, key.len());
+        let used = page_used(page);
+        let entry_len = ENTRY_FIXED_LEN + key.len();
+        page.copy_within(pos + entry_len..used, pos);
+        write_u16(page, 0, used - entry_len);
+        self.len -= 1;
+
+        return Ok(Some(old_log_pointer));
+    }
+
+    fn for_each(&mut self, f: &mut dyn FnMut(&[u8], LogPointer)) -> KVResult<()> {
+        self.cache.flush(&mut self.file)?;
+        self.file.seek(SeekFrom::Start(0))?;
+
+        let mut page = vec![0; PAGE_SIZE];
+        for _ in 0..self.pages {
+            self.file.read_exact(&mut page)?;
+            for (key, log_pointer) in page_entries(&page) {
+                f(key, log_pointer);
+            }
+        }
+        for (key, log_pointer) in &self.long_keys {
+            f(key, *log_pointer);
+        }
+
+        return Ok(());
+    }
+
+    /// Page and offset of the entry of `key`.
+    fn find(&mut self, key: &[u8]) -> KVResult<Option<(u64, usize)>> {
+        let mut page_id = hash(key) & (self.buckets - 1);
+        loop {
+            let page = self.cache.page(&mut self.file, page_id, false)?;
+            let mut pos = PAGE_HEADER_LEN;
+            while pos < page_used(page) {
+                let key_len = read_u16(page, pos);
+                if &page[pos + size_of::<u16>()..pos + size_of::<u16>() + key_len] == key {
+                    return Ok(Some((page_id, pos)));
+                }
+                pos += ENTRY_FIXED_LEN + key_len;
+            }
+
+            page_id = read_u64(page, size_of::<u16>());
+            if page_id == 0 {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Adds an entry for `key` to the first page of its bucket with room, chaining a new page
+    /// to the bucket when every one is full.
+    fn append(&mut self, key: &[u8], log_pointer: &LogPointer) -> KVResult<()> {
+        let entry_len = ENTRY_FIXED_LEN + key.len();
+        let mut page_id = hash(key) & (self.buckets - 1);
+        loop {
+            let page = self.cache.page(&mut self.file, page_id, false)?;
+            let used = page_used(page);
+            if used + entry_len <= PAGE_SIZE {
+                let page = self.cache.page(&mut self.file, page_id, true)?;
+                write_u16(page, used, key.len());
+                page[used + size_of::<u16>()..used + size_of::<u16>() + key.len()]
+                    .copy_from_slice(key);
+                write_entry_pointer(page, used, key.len(), log_pointer);
+                write_u16(page, 0, used + entry_len);
+                return Ok(());
+            }
+
+            let next_page_id = read_u64(page, size_of::<u16>());
+            if next_page_id != 0 {
+                page_id = next_page_id;
+                continue;
+            }
+
+            let new_page_id = self.pages;
+            self.pages += 1;
+            self.file.set_len(self.pages * PAGE_SIZE as u64)?;
+            let page = self.cache.page(&mut self.file, page_id, true)?;
+            write_u64(page, size_of::<u16>(), new_page_id);
+            page_id = new_page_id;
+        }
+    }
+
+    /// Rehashes every entry into a new file with twice the buckets, one page at a time. The
+    /// table is left as it was when that fails.
+    fn grow(&mut self) -> KVResult<()> {
+        let mut table = Table::new(&self.dir, self.buckets * 2, self.cache.capacity)?;
+        self.cache.flush(&mut self.file)?;
+        self.file.seek(SeekFrom::Start(0))?;
+
+        let mut page = vec![0; PAGE_SIZE];
+        for _ in 0..self.pages {
+            self.file.read_exact(&mut page)?;
+            // Keys are unique, so they are appended without looking them up.
+            for (key, log_pointer) in page_entries(&page) {
+                table.append(key, &log_pointer)?;
+                table.bloom.add(key);
+            }
+        }
+
+        table.len = self.len;
+        table.long_keys = std::mem::take(&mut self.long_keys);
+        *self = table;
+        return Ok(());
+    }
+}
+
+/// Creates a table file and unlinks it right away, so nothing is left behind once it is
+/// closed, also after a crash.
+fn create_table_file(dir: &PathBuf) -> KVResult<File> {
+    create_dir_all(dir)?;
+    let file_path = dir.join(format!(
+        "{}-{}.table",
+        std::process::id(),
+        NEXT_TABLE_ID.fetch_add(1, Ordering::SeqCst)
+    ));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&file_path)?;
+    remove_file(&file_path)?;
+
+    return Ok(file);
+}
+
+/// LRU cache of table pages, dirty pages are written back when evicted.
+struct PageCache {
+    capacity: usize,
+    tick: u64,
+    pages: HashMap<u64, CachedPage>,
+    recency: BTreeMap<u64, u64>,
+}
+
+struct CachedPage {
+    data: Vec<u8>,
+    dirty: bool,
+    tick: u64,
+}
+
+impl PageCache {
+    fn new(capacity: usize) -> PageCache {
+        PageCache {
+            capacity,
+            tick: 0,
+            pages: HashMap::new(),
+            recency: BTreeMap::new(),
+        }
+    }
+
+    /// Page `page_id`, read from `file` when not cached. `dirty` marks it to be written back.
+    fn page(&mut self, file: &mut File, page_id: u64, dirty: bool) -> KVResult<&mut [u8]> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        match self.pages.get_mut(&page_id) {
+            Some(page) => {
+                self.recency.remove(&page.tick);
+            }
+            None => {
+                while self.pages.len() >= self.capacity {
+                    self.evict_least_recent(file)?;
+                }
+                let mut data = vec![0; PAGE_SIZE];
+                file.seek(SeekFrom::Start(page_id * PAGE_SIZE as u64))?;
+                file.read_exact(&mut data)?;
+                self.pages.insert(
+                    page_id,
+                    CachedPage {
+                        data,
+                        dirty: false,
+                        tick,
+                    },
+                );
+            }
+        }
+
+        self.recency.insert(tick, page_id);
+        let page = self.pages.get_mut(&page_id).unwrap();
+        page.tick = tick;
+        page.dirty |= dirty;
+
+        return Ok(&mut page.data);
+    }
+
+    fn evict_least_recent(&mut self, file: &mut File) -> KVResult<()> {
+        if let Some((_, page_id)) = self.recency.pop_first() {
+            let page = self.pages.remove(&page_id).unwrap();
+            if page.dirty {
+                write_page(file, page_id, &page.data)?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    fn flush(&mut self, file: &mut File) -> KVResult<()> {
+        for (page_id, page) in self.pages.iter_mut().filter(|(_, page)| page.dirty) {
+            write_page(file, *page_id, &page.data)?;
+            page.dirty = false;
+        }
+
+        return Ok(());
+    }
+}
+
+fn write_page(file: &mut File, page_id: u64, data: &[u8]) -> KVResult<()> {
+    file.seek(SeekFrom::Start(page_id * PAGE_SIZE as u64))?;
+    file.write_all(data)?;
+    return Ok(());
+}
+
+struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    fn new(keys: u64) -> BloomFilter {
+        let words = (keys * BLOOM_BITS_PER_KEY).div_ceil(64).max(1);
+        BloomFilter {
+            bits: vec![0; words as usize],
+        }
+    }
+
+    /// Bit positions of `key`, derived from the two halves of one hash.
+    fn positions(&self, key: &[u8]) -> impl Iterator<Item = u64> {
+        let hash = hash(key);
+        let (low, high) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        let bit_count = self.bits.len() as u64 * 64;
+
+        return (0..BLOOM_HASHES).map(move |i| low.wrapping_add(i.wrapping_mul(high)) % bit_count);
+    }
+
+    fn add(&mut self, key: &[u8]) {
+        for position in self.positions(key).collect::<Vec<u64>>() {
+            self.bits[(position / 64) as usize] |= 1 << (position % 64);
+        }
+    }
+
+    /// False when `key` was never added, true can be a false positive.
+    fn may_contain(&self, key: &[u8]) -> bool {
+        return self
+            .positions(key)
+            .all(|position| self.bits[(position / 64) as usize] & (1 << (position % 64)) != 0);
+    }
+}
+
+/// Every key and pointer in `page`.
+fn page_entries(page: &[u8]) -> Vec<(&[u8], LogPointer)> {
+    let mut entries = Vec::new();
+    let mut pos = PAGE_HEADER_LEN;
+    while pos < page_used(page) {
+        let key_len = read_u16(page, pos);
+        let key = &page[pos + size_of::<u16>()..pos + size_of::<u16>() + key_len];
+        entries.push((key, read_entry_pointer(page, pos, key_len)));
+        pos += ENTRY_FIXED_LEN + key_len;
+    }
+
+    return entries;
+}
+
+/// Bytes in use, a page never written to is all zeros.
+fn page_used(page: &[u8]) -> usize {
+    return read_u16(page, 0).max(PAGE_HEADER_LEN);
+}
+
+fn read_entry_pointer(page: &[u8], pos: usize, key_len: usize) -> LogPointer {
+    let pointer_pos = pos + size_of::<u16>() + key_len;
+    return LogPointer::new(
+        read_u64(page, pointer_pos),
+        read_u64(page, pointer_pos + size_of::<u64>()),
+        read_u64(page, pointer_pos + 2 * size_of::<u64>()),
+    );
+}
+
+fn write_entry_pointer(page: &mut [u8], pos: usize, key_len: usize, log_pointer: &LogPointer) {
+    let pointer_pos = pos + size_of::<u16>() + key_len;
+    write_u64(page, pointer_pos, log_pointer.gen);
+    write_u64(page, pointer_pos + size_of::<u64>(), log_pointer.pos);
+    write_u64(page, pointer_pos + 2 * size_of::<u64>(), log_pointer.len);
+}
+
+fn read_u16(page: &[u8], pos: usize) -> usize {
+    let mut bytes = [0; 2];
+    bytes.copy_from_slice(&page[pos..pos + size_of::<u16>()]);
+    return u16::from_le_bytes(bytes) as usize;
+}
+
+fn write_u16(page: &mut [u8], pos: usize, value: usize) {
+    page[pos..pos + size_of::<u16>()].copy_from_slice(&(value as u16).to_le_bytes());
+}
+
+fn read_u64(page: &[u8], pos: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&page[pos..pos + size_of::<u64>()]);
+    return u64::from_le_bytes(bytes);
+}
+
+fn write_u64(page: &mut [u8], pos: usize, value: u64) {
+    page[pos..pos + size_of::<u64>()].copy_from_slice(&value.to_le_bytes());
+}
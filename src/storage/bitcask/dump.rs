@@ -0,0 +1,332 @@
+#![allow(clippy::needless_return)]
+
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::error::KVResult;
+use crate::storage::bitcask::bitcask_engine::get_log_file_dir;
+use crate::storage::bitcask::command::{
+    check_record, claimed_record_len, record_len, record_namespace, record_seq, Command,
+    RecordDefect, RECORD_HEADER_LEN, RECORD_MAGIC,
+};
+
+/// Bytes of a value shown by `dump`.
+const VALUE_PREVIEW_LEN: usize = 32;
+
+/// Which records of a generation `dump` returns.
+#[derive(Debug, Default)]
+pub struct DumpFilter {
+    pub key: Option<Vec<u8>>,
+    /// Records starting in this range of offsets, every record when `None`.
+    pub offsets: Option<Range<u64>>,
+}
+
+/// One record, or corrupt range, of a generation file. Keys and values are shown with
+/// non-printable bytes escaped.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DumpedRecord {
+    pub offset: u64,
+    pub len: u64,
+    /// `set`, `blob` for a set whose value is in a blob file, `remove`, `increment` and `merge`
+    /// whose value is the operand, `batch` in front of the records of one batch, or `corrupt`
+    /// for bytes that do not hold a valid record.
+    #[serde(rename = "type")]
+    pub record_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Id of the namespace of the key, `None` for the default namespace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_len: Option<u64>,
+    /// The first `VALUE_PREVIEW_LEN` bytes of the value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value_preview: Option<String>,
+    /// Blob file and offset of the value of a `blob` record, as `file:offset`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+    /// Number of records in the batch started by a `batch` record.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch_len: Option<u64>,
+    /// Why a corrupt range failed the record checks.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub defect: Option<String>,
+}
+
+/// Bytes read at once while looking for the next record after a corrupt range.
+const SCAN_CHUNK_LEN: usize = 64 * 1024;
+
+/// Every record of generation `gen` in `path` passing `filter`, in file order. The file is read
+/// one record at a time.
+pub fn dump(path: &PathBuf, gen: u64, filter: &DumpFilter) -> KVResult<Vec<DumpedRecord>> {
+    let mut scanner = RecordScanner::new(&get_log_file_dir(gen, path))?;
+    let end = filter.offsets.as_ref().map_or(scanner.file_len, |offsets| {
+        offsets.end.min(scanner.file_len)
+    });
+
+    let mut dumped = Vec::new();
+    let mut pos = 0;
+    while pos < end {
+        let (record, len) = match scanner.read_at(pos)? {
+            Ok(record) => {
+                let len = record.len() as u64;
+                (dumped_record(pos, &record, filter), len)
+            }
+            Err(defect) => {
+                let next_start = scanner.next_record_start(pos + 1)?;
+                let len = next_start.unwrap_or(scanner.file_len) - pos;
+                // A corrupt range has no key to match.
+                let record = Some(corrupt_range(pos, len, defect)).filter(|_| filter.key.is_none());
+                (record, len)
+            }
+        };
+        let in_offsets = filter
+            .offsets
+            .as_ref()
+            .is_none_or(|offsets| offsets.contains(&pos));
+        if in_offsets {
+            dumped.extend(record);
+        }
+        pos += len;
+    }
+
+    return Ok(dumped);
+}
+
+/// The valid record `record` at offset `pos`, `None` when it does not pass `filter`.
+fn dumped_record(pos: u64, record: &[u8], filter: &DumpFilter) -> Option<DumpedRecord> {
+    let (record_type, key, value, blob, batch_len) = match Command::from(record) {
+        Command::Set { key, value } => ("set", Some(key), Some(value), None, None),
+        Command::SetBlob { key, blob } => ("blob", Some(key), None, Some(blob), None),
+        Command::Remove { key } => ("remove", Some(key), None, None, None),
+        Command::Increment { key, delta } => {
+            let delta = delta.to_string().into_bytes();
+            ("increment", Some(key), Some(delta), None, None)
+        }
+        Command::Merge { key, operand } => ("merge", Some(key), Some(operand), None, None),
+        Command::Batch { count } => ("batch", None, None, None, Some(count)),
+    };
+    if filter
+        .key
+        .as_ref()
+        .is_some_and(|filter_key| Some(filter_key) != key.as_ref())
+    {
+        return None;
+    }
+    let namespace = Some(record_namespace(record)).filter(|namespace| *namespace != 0);
+
+    return Some(DumpedRecord {
+        offset: pos,
+        len: record.len() as u64,
+        record_type,
+        seq: Some(record_seq(record)),
+        namespace,
+        key: key.map(|key| key.escape_ascii().to_string()),
+        value_len: value
+            .as_ref()
+            .map(|value| value.len() as u64)
+            .or(blob.map(|blob| blob.len)),
+        value_preview: value.map(|value| {
+            let preview_len = value.len().min(VALUE_PREVIEW_LEN);
+            return value[..preview_len].escape_ascii().to_string();
+        }),
+        blob: blob.map(|blob| format!("{}:{}", blob.file, blob.offset)),
+        batch_len,
+        defect: None,
+    });
+}
+
+fn corrupt_range(pos: u64, len: u64, defect: RecordDefect) -> DumpedRecord {
+    return DumpedRecord {
+        offset: pos,
+        len,
+        record_type: "corrupt",
+        seq: None,
+        namespace: None,
+        key: None,
+        value_len: None,
+        value_preview: None,
+        blob: None,
+        batch_len: None,
+        defect: Some(defect.to_string()),
+    };
+}
+
+/// Reads the records of one generation file through a buffer, holding at most one record in
+/// memory.
+struct RecordScanner {
+    reader: BufReader<File>,
+    /// Offset the reader is at.
+    pos: u64,
+    file_len: u64,
+}
+
+impl RecordScanner {
+    fn new(log_path: &PathBuf) -> KVResult<RecordScanner> {
+        let file = File::open(log_path)?;
+        let file_len = file.metadata()?.len();
+
+        return Ok(RecordScanner {
+            reader: BufReader::new(file),
+            pos: 0,
+            file_len,
+        });
+    }
+
+    /// The record at offset `pos` when it passes `check_record`, why not otherwise.
+    fn read_at(&mut self, pos: u64) -> KVResult<Result<Vec<u8>, RecordDefect>> {
+        let header_len = (RECORD_HEADER_LEN as u64).min(self.file_len - pos);
+        let mut record = self.read_exact_at(pos, header_len as usize)?;
+        if let Err(defect) = record_len(&record) {
+            // Anything but the rest of the record missing from the header.
+            if defect != RecordDefect::Truncated || record.len() < RECORD_HEADER_LEN {
+                return Ok(Err(defect));
+            }
+        }
+
+        let len = claimed_record_len(&record);
+        if len > self.file_len - pos {
+            return Ok(Err(RecordDefect::Truncated));
+        }
+        record.resize(len as usize, 0);
+        self.reader.read_exact(&mut record[RECORD_HEADER_LEN..])?;
+        self.pos += len - RECORD_HEADER_LEN as u64;
+
+        return Ok(check_record(&record).map(|_| record));
+    }
+
+    /// Offset of the first record at or after `from` passing `check_record`.
+    fn next_record_start(&mut self, from: u64) -> KVResult<Option<u64>> {
+        let mut chunk_start = from;
+        while chunk_start + RECORD_MAGIC.len() as u64 <= self.file_len {
+            let chunk_len = (SCAN_CHUNK_LEN as u64).min(self.file_len - chunk_start);
+            let chunk = self.read_exact_at(chunk_start, chunk_len as usize)?;
+            let candidates: Vec<u64> = chunk
+                .windows(RECORD_MAGIC.len())
+                .enumerate()
+                .filter(|(_, window)| *window == RECORD_MAGIC)
+                .map(|(pos, _)| chunk_start + pos as u64)
+                .collect();
+            for candidate in candidates {
+                if self.read_at(candidate)?.is_ok() {
+                    return Ok(Some(candidate));
+                }
+            }
+            // The next chunk overlaps this one by a magic cut short at its end.
+            chunk_start += (chunk.len() - RECORD_MAGIC.len() + 1) as u64;
+        }
+
+        return Ok(None);
+    }
+
+    fn read_exact_at(&mut self, pos: u64, len: usize) -> KVResult<Vec<u8>> {
+        if pos != self.pos {
+            self.reader.seek(SeekFrom::Start(pos))?;
+        }
+        let mut buffer = vec![0; len];
+        self.reader.read_exact(&mut buffer)?;
+        self.pos = pos + len as u64;
+
+        return Ok(buffer);
+    }
+}
+
+#[test]
+fn dump_filters_records() {
+    use crate::storage::bitcask::bitcask_engine::{get_sorted_gen_list, Bitcask};
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+    store_engine.set(b"a".to_vec(), vec![b'x'; 100]).unwrap();
+    store_engine.set(b"b\n".to_vec(), b"2".to_vec()).unwrap();
+    store_engine.remove(b"a".to_vec()).unwrap();
+    drop(store_engine);
+
+    let gen = *get_sorted_gen_list(&path).unwrap().last().unwrap();
+    OpenOptions::new()
+        .append(true)
+        .open(get_log_file_dir(gen, &path))
+        .unwrap()
+        .write_all(&[1, 2, 3])
+        .unwrap();
+
+    let records = dump(&path, gen, &DumpFilter::default()).unwrap();
+    assert_eq!(
+        vec!["set", "set", "remove", "corrupt"],
+        records
+            .iter()
+            .map(|record| record.record_type)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(Some(100), records[0].value_len);
+    assert_eq!(
+        Some("x".repeat(VALUE_PREVIEW_LEN)),
+        records[0].value_preview
+    );
+    assert_eq!(Some("b\\n".to_owned()), records[1].key);
+    assert_eq!(Some("magic".to_owned()), records[3].defect);
+    assert!(records[0].seq < records[1].seq);
+
+    let filter = DumpFilter {
+        key: Some(b"a".to_vec()),
+        offsets: None,
+    };
+    let records_of_a = dump(&path, gen, &filter).unwrap();
+    assert_eq!(
+        vec![&records[0], &records[2]],
+        records_of_a.iter().collect::<Vec<_>>()
+    );
+
+    let filter = DumpFilter {
+        key: None,
+        offsets: Some(records[1].offset..records[3].offset),
+    };
+    let middle = dump(&path, gen, &filter).unwrap();
+    assert_eq!(
+        vec![&records[1], &records[2]],
+        middle.iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn dump_resumes_after_corrupt_range() {
+    use crate::storage::bitcask::bitcask_engine::{get_sorted_gen_list, Bitcask};
+    use std::fs::{read, write};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+    for i in 0..3 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), b"value".to_vec())
+            .unwrap();
+    }
+    drop(store_engine);
+
+    let gen = *get_sorted_gen_list(&path).unwrap().last().unwrap();
+    let log_path = get_log_file_dir(gen, &path);
+    let mut buffer = read(&log_path).unwrap();
+    let record_len = buffer.len() / 3;
+    buffer[record_len] ^= 0xff;
+    write(&log_path, &buffer).unwrap();
+
+    let records = dump(&path, gen, &DumpFilter::default()).unwrap();
+    assert_eq!(
+        vec!["set", "corrupt", "set"],
+        records
+            .iter()
+            .map(|record| record.record_type)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(record_len as u64, records[1].len);
+    assert_eq!(Some("key2".to_owned()), records[2].key);
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::io::{stderr, Write};
+#[cfg(test)]
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EventLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+/// Something worth knowing that happened inside the engine.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BitcaskEvent {
+    /// The store was opened after loading the index from `generations` files.
+    Opened {
+        generations: usize,
+        keys: usize,
+        load_time: Duration,
+    },
+    /// Bytes at the end of a generation left by an interrupted write, with no good record
+    /// after them, were ignored.
+    RecordsSkipped {
+        gen: u64,
+        pos: u64,
+        bytes: u64,
+    },
+    /// The active generation reached its size limit and writes moved to a new one.
+    Rotated {
+        sealed_gen: u64,
+        active_gen: u64,
+    },
+    /// The active generation reached its size limit but no new one could be opened, writes
+    /// go on to `gen` and the next write tries again.
+    RotationFailed {
+        gen: u64,
+        error: String,
+    },
+    MergeStarted {
+        gens: Vec<u64>,
+        merge_gen: u64,
+    },
+    MergeFinished {
+        merge_gen: u64,
+        records: usize,
+        duration: Duration,
+    },
+    MergeFailed {
+        error: String,
+    },
+    /// The change numbered `seq` could not be read back to be sent, the subscribers it was
+    /// meant for are dropped.
+    ChangeDropped {
+        seq: u64,
+        error: String,
+    },
+    /// A `get`, `set`, `remove`, `increment` or `merge` of a value took longer than the slow
+    /// operation threshold, `op` names which one.
+    SlowOperation {
+        op: &'static str,
+        duration: Duration,
+    },
+    OperationFailed {
+        op: &'static str,
+        error: String,
+    },
+}
+
+impl BitcaskEvent {
+    pub fn level(&self) -> EventLevel {
+        match self {
+            BitcaskEvent::Opened { .. }
+            | BitcaskEvent::Rotated { .. }
+            | BitcaskEvent::MergeStarted { .. }
+            | BitcaskEvent::MergeFinished { .. } => EventLevel::Info,
+            BitcaskEvent::RecordsSkipped { .. } | BitcaskEvent::SlowOperation { .. } => {
+                EventLevel::Warn
+            }
+            BitcaskEvent::RotationFailed { .. }
+            | BitcaskEvent::MergeFailed { .. }
+            | BitcaskEvent::ChangeDropped { .. }
+            | BitcaskEvent::OperationFailed { .. } => EventLevel::Error,
+        }
+    }
+}
+
+/// Renders the event as `event=name key=value ...`.
+impl fmt::Display for BitcaskEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BitcaskEvent::Opened {
+                generations,
+                keys,
+                load_time,
+            } => write!(
+                f,
+                "event=open generations={} keys={} load_ms={}",
+                generations,
+                keys,
+                load_time.as_millis()
+            ),
+            BitcaskEvent::RecordsSkipped { gen, pos, bytes } => write!(
+                f,
+                "event=records_skipped gen={} pos={} bytes={}",
+                gen, pos, bytes
+            ),
+            BitcaskEvent::Rotated {
+                sealed_gen,
+                active_gen,
+            } => write!(
+                f,
+                "event=rotate sealed_gen={} active_gen={}",
+                sealed_gen, active_gen
+            ),
+            BitcaskEvent::RotationFailed { gen, error } => {
+                write!(f, "event=rotation_failed gen={} error={:?}", gen, error)
+            }
+            BitcaskEvent::MergeStarted { gens, merge_gen } => write!(
+                f,
+                "event=merge_start gens={:?} merge_gen={}",
+                gens, merge_gen
+            ),
+            BitcaskEvent::MergeFinished {
+                merge_gen,
+                records,
+                duration,
+            } => write!(
+                f,
+                "event=merge_finish merge_gen={} records={} duration_ms={}",
+                merge_gen,
+                records,
+                duration.as_millis()
+            ),
+            BitcaskEvent::MergeFailed { error } => {
+                write!(f, "event=merge_failed error={:?}", error)
+            }
+            BitcaskEvent::ChangeDropped { seq, error } => {
+                write!(f, "event=change_dropped seq={} error={:?}", seq, error)
+            }
+            BitcaskEvent::SlowOperation { op, duration } => write!(
+                f,
+                "event=slow_operation op={} duration_ms={}",
+                op,
+                duration.as_millis()
+            ),
+            BitcaskEvent::OperationFailed { op, error } => {
+                write!(f, "event=operation_failed op={} error={:?}", op, error)
+            }
+        }
+    }
+}
+
+/// Receives every event of a store, called on whichever thread caused the event.
+pub trait EventListener: Send + Sync {
+    fn on_event(&self, event: &BitcaskEvent);
+}
+
+/// Writes events at or above `level` to stderr, one line each.
+pub struct StderrEventListener {
+    pub level: EventLevel,
+}
+
+impl Default for StderrEventListener {
+    fn default() -> Self {
+        StderrEventListener {
+            level: EventLevel::Warn,
+        }
+    }
+}
+
+impl EventListener for StderrEventListener {
+    fn on_event(&self, event: &BitcaskEvent) {
+        let level = event.level();
+        if level > self.level {
+            return;
+        }
+
+        let level = format!("{:?}", level).to_lowercase();
+        let _ = writeln!(stderr(), "level={} {}", level, event);
+    }
+}
+
+/// Drops every event, the default listener of tests so they do not write to stderr.
+#[cfg(test)]
+pub(crate) struct QuietEventListener;
+
+#[cfg(test)]
+impl EventListener for QuietEventListener {
+    fn on_event(&self, _event: &BitcaskEvent) {}
+}
+
+/// Keeps every event for a test to look at.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct CollectEvents(pub(crate) Mutex<Vec<BitcaskEvent>>);
+
+#[cfg(test)]
+impl EventListener for CollectEvents {
+    fn on_event(&self, event: &BitcaskEvent) {
+        self.0.lock().unwrap().push(event.clone());
+    }
+}
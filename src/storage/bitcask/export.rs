@@ -0,0 +1,308 @@
+#![allow(clippy::needless_return)]
+
+use std::io::{BufRead, Error, ErrorKind, Write};
+use std::str::{from_utf8, FromStr};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::error::KVResult;
+use crate::storage::bitcask::bitcask_engine::{Bitcask, Snapshot};
+use crate::storage::bitcask::namespace::Namespace;
+
+/// Writes per `write_batch` call while importing.
+const IMPORT_BATCH_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    JsonLines,
+    Csv,
+}
+
+/// How keys and values that are not valid UTF-8 are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryEncoding {
+    Base64,
+    Hex,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<ExportFormat, String> {
+        match format {
+            "jsonl" => Ok(ExportFormat::JsonLines),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("unknown format {}", format)),
+        }
+    }
+}
+
+impl FromStr for BinaryEncoding {
+    type Err = String;
+
+    fn from_str(encoding: &str) -> Result<BinaryEncoding, String> {
+        match encoding {
+            "base64" => Ok(BinaryEncoding::Base64),
+            "hex" => Ok(BinaryEncoding::Hex),
+            _ => Err(format!("unknown encoding {}", encoding)),
+        }
+    }
+}
+
+/// One exported pair. Key and value are plain text unless `encoding` names the encoding
+/// both are written in, which is used as soon as either of them is not valid UTF-8.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportRecord {
+    key: String,
+    value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
+}
+
+impl ExportRecord {
+    fn new(key: &[u8], value: &[u8], binary_encoding: BinaryEncoding) -> ExportRecord {
+        if let (Ok(key), Ok(value)) = (from_utf8(key), from_utf8(value)) {
+            return ExportRecord {
+                key: key.to_owned(),
+                value: value.to_owned(),
+                encoding: None,
+            };
+        }
+
+        let (key, value, encoding) = match binary_encoding {
+            BinaryEncoding::Base64 => (STANDARD.encode(key), STANDARD.encode(value), "base64"),
+            BinaryEncoding::Hex => (hex::encode(key), hex::encode(value), "hex"),
+        };
+        return ExportRecord {
+            key,
+            value,
+            encoding: Some(encoding.to_owned()),
+        };
+    }
+
+    fn decode(self) -> KVResult<(Vec<u8>, Vec<u8>)> {
+        let encoding = self.encoding;
+        let decode = |text: String| -> KVResult<Vec<u8>> {
+            let bytes = match encoding.as_deref() {
+                None => text.into_bytes(),
+                Some("base64") => STANDARD
+                    .decode(text)
+                    .map_err(|err| Error::new(ErrorKind::InvalidData, err))?,
+                Some("hex") => {
+                    hex::decode(text).map_err(|err| Error::new(ErrorKind::InvalidData, err))?
+                }
+                Some(encoding) => {
+                    let message = format!("unknown encoding {}", encoding);
+                    return Err(Error::new(ErrorKind::InvalidData, message).into());
+                }
+            };
+            return Ok(bytes);
+        };
+
+        return Ok((decode(self.key)?, decode(self.value)?));
+    }
+}
+
+/// Writes every live pair of the default namespace of `store_engine` to `writer` in key order,
+/// as of the moment the export starts. Returns the number of pairs written. The keys of other
+/// namespaces are left out, see `export_namespace`.
+pub fn export(
+    store_engine: &Bitcask,
+    writer: impl Write,
+    format: ExportFormat,
+    binary_encoding: BinaryEncoding,
+) -> KVResult<u64> {
+    return export_snapshot(&store_engine.snapshot(), writer, format, binary_encoding);
+}
+
+/// Same as `export` for the keys of `namespace`.
+pub fn export_namespace(
+    namespace: &Namespace,
+    writer: impl Write,
+    format: ExportFormat,
+    binary_encoding: BinaryEncoding,
+) -> KVResult<u64> {
+    return export_snapshot(&namespace.snapshot()?, writer, format, binary_encoding);
+}
+
+fn export_snapshot(
+    snapshot: &Snapshot,
+    writer: impl Write,
+    format: ExportFormat,
+    binary_encoding: BinaryEncoding,
+) -> KVResult<u64> {
+    let mut count = 0;
+
+    match format {
+        ExportFormat::JsonLines => {
+            let mut writer = writer;
+            for entry in snapshot.iter() {
+                let (key, value) = entry?;
+                let record = ExportRecord::new(&key, &value, binary_encoding);
+                serde_json::to_writer(&mut writer, &record).map_err(Error::from)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            writer
+                .write_record(["key", "value", "encoding"])
+                .map_err(Error::from)?;
+            for entry in snapshot.iter() {
+                let (key, value) = entry?;
+                let record = ExportRecord::new(&key, &value, binary_encoding);
+                let encoding = record.encoding.unwrap_or_default();
+                writer
+                    .write_record([&record.key, &record.value, &encoding])
+                    .map_err(Error::from)?;
+                count += 1;
+            }
+            writer.flush()?;
+        }
+    }
+
+    return Ok(count);
+}
+
+/// Sets every pair read from `reader`, as written by `export`, in the default namespace of
+/// `store_engine` through batched writes. Returns the number of pairs imported.
+pub fn import(store_engine: &Bitcask, reader: impl BufRead, format: ExportFormat) -> KVResult<u64> {
+    return import_with(reader, format, |batch| store_engine.write_batch(batch));
+}
+
+/// Same as `import` into `namespace`.
+pub fn import_namespace(
+    namespace: &Namespace,
+    reader: impl BufRead,
+    format: ExportFormat,
+) -> KVResult<u64> {
+    return import_with(reader, format, |batch| namespace.write_batch(batch));
+}
+
+/// Hands the pairs read from `reader` to `write_batch`, `IMPORT_BATCH_SIZE` at a time.
+fn import_with(
+    reader: impl BufRead,
+    format: ExportFormat,
+    write_batch: impl Fn(Vec<(Vec<u8>, Option<Vec<u8>>)>) -> KVResult<()>,
+) -> KVResult<u64> {
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut count = 0;
+
+    let mut add = |record: ExportRecord| -> KVResult<()> {
+        let (key, value) = record.decode()?;
+        batch.push((key, Some(value)));
+        if batch.len() >= IMPORT_BATCH_SIZE {
+            write_batch(std::mem::take(&mut batch))?;
+        }
+        count += 1;
+        return Ok(());
+    };
+
+    match format {
+        ExportFormat::JsonLines => {
+            for line in reader.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                add(serde_json::from_str(&line).map_err(Error::from)?)?;
+            }
+        }
+        ExportFormat::Csv => {
+            let mut reader = csv::Reader::from_reader(reader);
+            for record in reader.deserialize() {
+                add(record.map_err(Error::from)?)?;
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        write_batch(batch)?;
+    }
+
+    return Ok(count);
+}
+
+#[test]
+fn export_import_round_trip() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let source = Bitcask::open(&temp_dir.path().join("source")).unwrap();
+    source
+        .set(b"text".to_vec(), b"a, \"quoted\"\nvalue".to_vec())
+        .unwrap();
+    source
+        .set(b"binary".to_vec(), vec![0, 159, 146, 150])
+        .unwrap();
+    source.set(vec![255, 0], b"binary key".to_vec()).unwrap();
+
+    let cases = [
+        (ExportFormat::JsonLines, BinaryEncoding::Base64),
+        (ExportFormat::JsonLines, BinaryEncoding::Hex),
+        (ExportFormat::Csv, BinaryEncoding::Base64),
+        (ExportFormat::Csv, BinaryEncoding::Hex),
+    ];
+    for (i, (format, encoding)) in cases.iter().enumerate() {
+        let mut output = Vec::new();
+        assert_eq!(3, export(&source, &mut output, *format, *encoding).unwrap());
+
+        let target = Bitcask::open(&temp_dir.path().join(i.to_string())).unwrap();
+        assert_eq!(3, import(&target, output.as_slice(), *format).unwrap());
+        assert_eq!(
+            source
+                .snapshot()
+                .iter()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>(),
+            target
+                .snapshot()
+                .iter()
+                .map(Result::unwrap)
+                .collect::<Vec<_>>()
+        );
+    }
+}
+
+#[test]
+fn import_rejects_unknown_encoding() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store_engine = Bitcask::open(&temp_dir.path().to_path_buf()).unwrap();
+    let input = "{\"key\":\"a\",\"value\":\"b\",\"encoding\":\"rot13\"}\n";
+
+    assert!(import(&store_engine, input.as_bytes(), ExportFormat::JsonLines).is_err());
+    assert!(store_engine.get(b"a".to_vec()).is_err());
+}
+
+#[test]
+fn export_import_namespace() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let source = Bitcask::open(&temp_dir.path().join("source")).unwrap();
+    source.set(b"default".to_vec(), b"value".to_vec()).unwrap();
+    let users = source.create_namespace("users").unwrap();
+    users.set(b"alice".to_vec(), b"1".to_vec()).unwrap();
+    users.set(b"bob".to_vec(), b"2".to_vec()).unwrap();
+
+    let mut output = Vec::new();
+    let count = export_namespace(
+        &users,
+        &mut output,
+        ExportFormat::JsonLines,
+        BinaryEncoding::Base64,
+    )
+    .unwrap();
+    assert_eq!(2, count);
+
+    let target = Bitcask::open(&temp_dir.path().join("target")).unwrap();
+    let target_users = target.create_namespace("users").unwrap();
+    let count = import_namespace(&target_users, output.as_slice(), ExportFormat::JsonLines);
+    assert_eq!(2, count.unwrap());
+    assert_eq!(
+        Some(b"2".to_vec()),
+        target_users.get(b"bob".to_vec()).unwrap()
+    );
+    assert!(target.get(b"alice".to_vec()).is_err());
+    assert!(target_users.get(b"default".to_vec()).is_err());
+}
@@ -0,0 +1,594 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::mem::{replace, take};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use crate::error::{KVError, KVResult};
+use crate::storage::bitcask::bitcask_engine::get_log_file_dir;
+use crate::storage::bitcask::changes::ChangeFeed;
+use crate::storage::bitcask::command::{record_hasher, CHECKSUM_OFFSET};
+use crate::storage::bitcask::events::{BitcaskEvent, EventListener};
+use crate::storage::bitcask::log_pointer::LogPointer;
+use crate::storage::bitcask::metrics::Metrics;
+use crate::storage::bitcask::stream::copy_value;
+
+/// Appends records to the active generation on behalf of concurrent callers.
+///
+/// The first caller to find no write in progress becomes the leader, it takes every pending
+/// record, appends them with one write and at most one fsync, then wakes the other callers
+/// with their `LogPointer`s. Callers arriving while the leader writes join the next batch.
+/// Callers then apply their results one after the other in log order.
+pub struct GroupCommitWriter {
+    path: PathBuf,
+    sync_writes: bool,
+    max_file_size: u64,
+    active_gen: AtomicU64,
+    batches: AtomicU64,
+    metrics: Arc<Metrics>,
+    events: Arc<dyn EventListener>,
+    changes: Arc<ChangeFeed>,
+    state: Mutex<CommitState>,
+    committed: Condvar,
+}
+
+/// Result of a write, with the error kept apart from `KVError` to hand it to every caller.
+type WriteResult = Result<LogPointer, (ErrorKind, String)>;
+
+struct CommitState {
+    writer: Option<BitcaskWriter>,
+    pending: Vec<(u64, Vec<u8>)>,
+    /// Per ticket, the position of its record in log order and the result of its write.
+    results: HashMap<u64, (u64, WriteResult)>,
+    next_ticket: u64,
+    /// Records taken for a write so far, failed writes included.
+    written: u64,
+    /// Records whose callers applied their result so far.
+    applied: u64,
+}
+
+struct BitcaskWriter {
+    writer: BufWriter<File>,
+    gen: u64,
+    pos: u64,
+}
+
+impl BitcaskWriter {
+    fn new(gen: u64, dir: &PathBuf) -> KVResult<BitcaskWriter> {
+        // Not opened for appending, a streamed record gets its checksum written in place.
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(get_log_file_dir(gen, dir))?;
+        let pos = file.seek(SeekFrom::End(0))?;
+        let writer = BitcaskWriter {
+            writer: BufWriter::new(file),
+            gen,
+            pos,
+        };
+        return Ok(writer);
+    }
+
+    fn write_batch(
+        &mut self,
+        batch: &[(u64, Vec<u8>)],
+        sync_writes: bool,
+    ) -> KVResult<Vec<LogPointer>> {
+        let mut log_pointers = Vec::with_capacity(batch.len());
+        let mut pos = self.pos;
+
+        let mut result = Ok(());
+        for (_, record) in batch {
+            log_pointers.push(LogPointer::new(self.gen, pos, record.len() as u64));
+            result = self.writer.write_all(record);
+            if result.is_err() {
+                break;
+            }
+            pos += record.len() as u64;
+        }
+
+        let result = result.and_then(|_| self.writer.flush()).and_then(|_| {
+            if sync_writes {
+                return self.writer.get_ref().sync_data();
+            }
+            return Ok(());
+        });
+        if let Err(err) = result {
+            self.roll_back()?;
+            return Err(err.into());
+        }
+        self.pos = pos;
+
+        return Ok(log_pointers);
+    }
+
+    /// Cuts the file back to the end of the last whole write, dropping any buffered bytes.
+    fn roll_back(&mut self) -> KVResult<()> {
+        let file = self.writer.get_ref().try_clone()?;
+        // Taken apart rather than dropped, which would write out the buffer.
+        let _ = replace(&mut self.writer, BufWriter::new(file)).into_parts();
+
+        let file = self.writer.get_mut();
+        file.set_len(self.pos)?;
+        file.seek(SeekFrom::Start(self.pos))?;
+
+        return Ok(());
+    }
+
+    /// Writes `head`, a set record up to its value, then `value_len` value bytes copied from
+    /// `reader`, and fills in the checksum. Nothing is left of the record when this fails.
+    fn write_streamed(
+        &mut self,
+        head: &[u8],
+        reader: &mut dyn Read,
+        value_len: u64,
+        sync_writes: bool,
+    ) -> KVResult<LogPointer> {
+        self.writer.flush()?;
+        let file = self.writer.get_mut();
+
+        let mut hasher = record_hasher(head);
+        let result = file
+            .write_all(head)
+            .map_err(|err| err.into())
+            .and_then(|_| copy_value(reader, file, value_len, &mut hasher));
+        if let Err(err) = result {
+            file.set_len(self.pos)?;
+            file.seek(SeekFrom::Start(self.pos))?;
+            return Err(err);
+        }
+
+        let len = head.len() as u64 + value_len;
+        file.seek(SeekFrom::Start(self.pos + CHECKSUM_OFFSET as u64))?;
+        file.write_all(&hasher.finalize().to_le_bytes())?;
+        file.seek(SeekFrom::Start(self.pos + len))?;
+        if sync_writes {
+            file.sync_data()?;
+        }
+
+        let log_pointer = LogPointer::new(self.gen, self.pos, len);
+        self.pos += len;
+
+        return Ok(log_pointer);
+    }
+}
+
+impl GroupCommitWriter {
+    pub fn new(
+        gen: u64,
+        path: &PathBuf,
+        sync_writes: bool,
+        max_file_size: u64,
+        metrics: Arc<Metrics>,
+        events: Arc<dyn EventListener>,
+        changes: Arc<ChangeFeed>,
+    ) -> KVResult<GroupCommitWriter> {
+        let writer = BitcaskWriter::new(gen, path)?;
+
+        let group_commit_writer = GroupCommitWriter {
+            path: path.to_owned(),
+            sync_writes,
+            max_file_size,
+            active_gen: AtomicU64::new(gen),
+            batches: AtomicU64::new(0),
+            metrics,
+            events,
+            changes,
+            state: Mutex::new(CommitState {
+                writer: Some(writer),
+                pending: Vec::new(),
+                results: HashMap::new(),
+                next_ticket: 0,
+                written: 0,
+                applied: 0,
+            }),
+            committed: Condvar::new(),
+        };
+
+        return Ok(group_commit_writer);
+    }
+
+    /// Generation currently receiving appends, every lower generation is sealed.
+    pub fn active_gen(&self) -> u64 {
+        self.active_gen.load(Ordering::SeqCst)
+    }
+
+    /// Sequence number of the last record handed to the log so far.
+    pub fn last_seq(&self) -> u64 {
+        return self.changes.last_seq();
+    }
+
+    /// Number of write (and fsync) batches issued so far.
+    pub fn batches(&self) -> u64 {
+        self.batches.load(Ordering::SeqCst)
+    }
+
+    /// Appends `record` to the log, once it has been written (and synced when `sync_writes`
+    /// is set) together with the records of every other waiting caller, runs `apply` with its
+    /// `LogPointer`. Callers run `apply` in log order, each after the callers of every record
+    /// written before its own.
+    pub fn append_then<T>(
+        &self,
+        record: Vec<u8>,
+        apply: impl FnOnce(KVResult<LogPointer>) -> T,
+    ) -> T {
+        return self.append_batch_then(vec![record], |result| {
+            apply(result.map(|mut log_pointers| log_pointers.remove(0)))
+        });
+    }
+
+    /// Same as `append_then` for several records, which end up next to each other in one
+    /// batch.
+    pub fn append_batch_then<T>(
+        &self,
+        records: Vec<Vec<u8>>,
+        apply: impl FnOnce(KVResult<Vec<LogPointer>>) -> T,
+    ) -> T {
+        // No ticket would ever get a result, and no order is taken for nothing to apply.
+        if records.is_empty() {
+            return apply(Ok(Vec::new()));
+        }
+
+        let mut state = self.state.lock().unwrap();
+
+        let first_ticket = state.next_ticket;
+        state.next_ticket += records.len() as u64;
+        let tickets = first_ticket..state.next_ticket;
+        state.pending.extend(tickets.clone().zip(records));
+
+        loop {
+            // All tickets are taken by the same leader, so their results arrive together.
+            if let Some((order, _)) = state.results.get(&first_ticket) {
+                let order = *order;
+                let result = tickets
+                    .clone()
+                    .map(|ticket| {
+                        let (_, result) = state.results.remove(&ticket).unwrap();
+                        result.map_err(|(kind, message)| Error::new(kind, message).into())
+                    })
+                    .collect();
+                return self.apply_in_order(state, order..order + tickets.count() as u64, || {
+                    apply(result)
+                });
+            }
+
+            let mut writer = match state.writer.take() {
+                Some(writer) => writer,
+                None => {
+                    state = self.committed.wait(state).unwrap();
+                    continue;
+                }
+            };
+
+            let mut batch = take(&mut state.pending);
+            let first_order = state.written;
+            state.written += batch.len() as u64;
+            drop(state);
+
+            self.changes.number(&mut batch);
+
+            let batch_result =
+                writer
+                    .write_batch(&batch, self.sync_writes)
+                    .inspect(|log_pointers| {
+                        self.record_batch(log_pointers);
+                        self.changes.publish(&batch);
+                    });
+            self.rotate_if_needed(&mut writer);
+            self.batches.fetch_add(1, Ordering::SeqCst);
+
+            state = self.state.lock().unwrap();
+            state.writer = Some(writer);
+
+            let orders = first_order..;
+            match batch_result {
+                Ok(log_pointers) => {
+                    for (((ticket, _), log_pointer), order) in
+                        batch.iter().zip(log_pointers).zip(orders)
+                    {
+                        state.results.insert(*ticket, (order, Ok(log_pointer)));
+                    }
+                }
+                Err(err) => {
+                    let (kind, message) = match err {
+                        KVError::IOError(err) => (err.kind(), err.to_string()),
+                        err => (ErrorKind::Other, format!("{:?}", err)),
+                    };
+                    for ((ticket, _), order) in batch.iter().zip(orders) {
+                        state
+                            .results
+                            .insert(*ticket, (order, Err((kind, message.clone()))));
+                    }
+                }
+            }
+
+            self.committed.notify_all();
+        }
+    }
+
+    /// Appends a set record made of `head`, the record up to its value, and `value_len` bytes
+    /// copied from `reader` in chunks. Records of other callers wait until the value is
+    /// written. Subscribers of the change feed get the record read back from the log. `apply`
+    /// runs with the result as in `append_then`.
+    pub fn append_streamed_then<T>(
+        &self,
+        head: Vec<u8>,
+        reader: &mut dyn Read,
+        value_len: u64,
+        apply: impl FnOnce(KVResult<LogPointer>) -> T,
+    ) -> T {
+        let mut state = self.state.lock().unwrap();
+        let mut writer = loop {
+            match state.writer.take() {
+                Some(writer) => break writer,
+                None => state = self.committed.wait(state).unwrap(),
+            }
+        };
+        let order = state.written;
+        state.written += 1;
+        drop(state);
+
+        let mut batch = [(0, head)];
+        self.changes.number(&mut batch);
+
+        let result = writer
+            .write_streamed(&batch[0].1, reader, value_len, self.sync_writes)
+            .inspect(|log_pointer| {
+                self.record_batch(&[*log_pointer]);
+                if self.changes.has_subscribers() {
+                    match self.read_back(log_pointer) {
+                        Ok(record) => self.changes.publish(&[(0, record)]),
+                        Err(err) => self.changes.drop_change(&batch[0].1, err),
+                    }
+                }
+            });
+        self.rotate_if_needed(&mut writer);
+        self.batches.fetch_add(1, Ordering::SeqCst);
+
+        let mut state = self.state.lock().unwrap();
+        state.writer = Some(writer);
+        self.committed.notify_all();
+
+        return self.apply_in_order(state, order..order + 1, || apply(result));
+    }
+
+    /// Seals the active generation and skips one generation number, which is returned for a
+    /// merge to write into. The merge output then sorts after every sealed generation it may
+    /// replace and before everything written from now on. Waits until every record written
+    /// so far is applied by its caller.
+    pub fn reserve_merge_gen(&self) -> KVResult<u64> {
+        let mut state = self.state.lock().unwrap();
+        while state.writer.is_none() || state.applied != state.written {
+            state = self.committed.wait(state).unwrap();
+        }
+
+        let writer = state.writer.as_mut().unwrap();
+        let merge_gen = writer.gen + 1;
+        *writer = BitcaskWriter::new(merge_gen + 1, &self.path)?;
+        self.active_gen.store(writer.gen, Ordering::SeqCst);
+
+        return Ok(merge_gen);
+    }
+
+    /// The active generation and the end of its last record written so far.
+    pub fn committed_end(&self) -> (u64, u64) {
+        let mut state = self.state.lock().unwrap();
+        while state.writer.is_none() {
+            state = self.committed.wait(state).unwrap();
+        }
+
+        let writer = state.writer.as_ref().unwrap();
+        return (writer.gen, writer.pos);
+    }
+
+    /// Runs `f` between two batches, while every record appended so far is flushed to the log
+    /// and published, and no other record can be appended.
+    pub fn while_idle<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut state = self.state.lock().unwrap();
+        while state.writer.is_none() {
+            state = self.committed.wait(state).unwrap();
+        }
+
+        let result = f();
+        drop(state);
+
+        return result;
+    }
+
+    /// Runs `apply` for the records at `orders` in log order once every record before them
+    /// is applied. The commit state is unlocked while `apply` runs.
+    fn apply_in_order<T>(
+        &self,
+        mut state: MutexGuard<CommitState>,
+        orders: Range<u64>,
+        apply: impl FnOnce() -> T,
+    ) -> T {
+        while state.applied != orders.start {
+            state = self.committed.wait(state).unwrap();
+        }
+        drop(state);
+
+        let result = apply();
+
+        self.state.lock().unwrap().applied = orders.end;
+        self.committed.notify_all();
+
+        return result;
+    }
+
+    /// Reads the record at `log_pointer` back from the log.
+    fn read_back(&self, log_pointer: &LogPointer) -> KVResult<Vec<u8>> {
+        let mut record = vec![0; log_pointer.len as usize];
+        let mut file = File::open(get_log_file_dir(log_pointer.gen, &self.path))?;
+        file.seek(SeekFrom::Start(log_pointer.pos))?;
+        file.read_exact(&mut record)?;
+
+        return Ok(record);
+    }
+
+    fn record_batch(&self, log_pointers: &[LogPointer]) {
+        let written: u64 = log_pointers.iter().map(|log_pointer| log_pointer.len).sum();
+        self.metrics
+            .bytes_written
+            .fetch_add(written, Ordering::Relaxed);
+        if self.sync_writes {
+            self.metrics.fsyncs.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Moves writes to a new generation once the active one is full. A failure is reported as
+    /// an event rather than to the callers, whose records are written already.
+    fn rotate_if_needed(&self, writer: &mut BitcaskWriter) {
+        if writer.pos < self.max_file_size {
+            return;
+        }
+
+        let sealed_gen = writer.gen;
+        match BitcaskWriter::new(sealed_gen + 1, &self.path) {
+            Ok(new_writer) => *writer = new_writer,
+            Err(err) => {
+                return self.events.on_event(&BitcaskEvent::RotationFailed {
+                    gen: sealed_gen,
+                    error: format!("{:?}", err),
+                });
+            }
+        }
+        self.active_gen.store(writer.gen, Ordering::SeqCst);
+        self.events.on_event(&BitcaskEvent::Rotated {
+            sealed_gen,
+            active_gen: writer.gen,
+        });
+    }
+}
+
+/// Writer of generation 0 in `path` for tests, events go to `events`.
+#[cfg(test)]
+fn test_writer(
+    path: &PathBuf,
+    sync_writes: bool,
+    max_file_size: u64,
+    events: Arc<dyn EventListener>,
+) -> GroupCommitWriter {
+    let changes = Arc::new(ChangeFeed::new(path, 1, events.clone()));
+    return GroupCommitWriter::new(
+        0,
+        path,
+        sync_writes,
+        max_file_size,
+        Arc::new(Metrics::new()),
+        events,
+        changes,
+    )
+    .unwrap();
+}
+
+#[test]
+fn group_commit_assign_contiguous_pointers() {
+    use crate::storage::bitcask::events::QuietEventListener;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let writer = Arc::new(test_writer(
+        &path,
+        true,
+        u64::MAX,
+        Arc::new(QuietEventListener),
+    ));
+
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let writer = writer.clone();
+            std::thread::spawn(move || {
+                (0..20)
+                    .map(|_| writer.append_then(vec![0; 30], |result| result).unwrap())
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let mut positions: Vec<u64> = handles
+        .into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .map(|log_pointer| log_pointer.pos)
+        .collect();
+    positions.sort();
+
+    let expected_positions: Vec<u64> = (0..160).map(|i| i * 30).collect();
+    assert_eq!(expected_positions, positions);
+    assert_eq!(
+        writer.batches(),
+        writer.metrics.fsyncs.load(Ordering::Relaxed)
+    );
+    assert_eq!(
+        4800,
+        std::fs::metadata(get_log_file_dir(0, &path)).unwrap().len()
+    );
+}
+
+#[test]
+fn group_commit_batch_waiting_callers() {
+    use crate::storage::bitcask::events::QuietEventListener;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let writer = Arc::new(test_writer(
+        &path,
+        true,
+        u64::MAX,
+        Arc::new(QuietEventListener),
+    ));
+
+    // Holding the writer as a leader would, every caller queues up for the next batch.
+    let bitcask_writer = writer.state.lock().unwrap().writer.take().unwrap();
+    let handles: Vec<_> = (0..8)
+        .map(|_| {
+            let writer = writer.clone();
+            std::thread::spawn(move || writer.append_then(vec![0; 30], |result| result).unwrap())
+        })
+        .collect();
+    while writer.state.lock().unwrap().pending.len() < 8 {
+        std::thread::yield_now();
+    }
+    writer.state.lock().unwrap().writer = Some(bitcask_writer);
+    writer.committed.notify_all();
+
+    let mut positions: Vec<u64> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap().pos)
+        .collect();
+    positions.sort();
+
+    assert_eq!((0..8).map(|i| i * 30).collect::<Vec<u64>>(), positions);
+    assert_eq!(1, writer.batches());
+}
+
+#[test]
+fn group_commit_rotation_failure_keep_write() {
+    use crate::storage::bitcask::events::CollectEvents;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let events = Arc::new(CollectEvents::default());
+    let writer = test_writer(&path, false, 30, events.clone());
+
+    // The next generation can not be opened.
+    std::fs::create_dir(get_log_file_dir(1, &path)).unwrap();
+    let log_pointer = writer.append_then(vec![0; 30], |result| result).unwrap();
+    assert_eq!(LogPointer::new(0, 0, 30), log_pointer);
+    assert_eq!(0, writer.active_gen());
+    assert!(events
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|event| matches!(event, BitcaskEvent::RotationFailed { gen: 0, .. })));
+
+    std::fs::remove_dir(get_log_file_dir(1, &path)).unwrap();
+    writer.append_then(vec![0; 30], |result| result).unwrap();
+    assert_eq!(1, writer.active_gen());
+}
@@ -0,0 +1,402 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::path::PathBuf;
+
+use crate::error::KVResult;
+use crate::storage::bitcask::disk_keydir::DiskKeyDir;
+use crate::storage::bitcask::log_pointer::LogPointer;
+
+/// Maps every live key to the record holding its value.
+pub(crate) trait KeyDir: Send + Sync {
+    fn get(&self, key: &[u8]) -> KVResult<Option<LogPointer>>;
+
+    /// Points `key` at `log_pointer`, returns where it pointed before.
+    fn insert(&mut self, key: Vec<u8>, log_pointer: LogPointer) -> KVResult<Option<LogPointer>>;
+
+    fn remove(&mut self, key: &[u8]) -> KVResult<Option<LogPointer>>;
+
+    fn len(&self) -> usize;
+
+    /// Calls `f` with every key and its pointer, in no particular order.
+    fn for_each(&self, f: &mut dyn FnMut(&[u8], LogPointer)) -> KVResult<()>;
+
+    /// Rough heap usage, keys included.
+    fn memory_bytes(&self) -> u64;
+}
+
+/// Layout of the in-memory index of a store.
+///
+/// As measured by `keydir_compact_memory_per_key` through `memory_bytes`, with 1M keys of 16
+/// bytes the `HashMap` took about 105 bytes per key before the allocator overhead of every key
+/// buffer, the compact keydir about 67. That is about a third less, not enough to hold hundreds
+/// of millions of keys in memory, 100M such keys still take close to 7GB.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum KeyDirKind {
+    /// A `HashMap` with a heap allocation per key, the fastest.
+    #[default]
+    HashMap,
+    /// Packed pointers in an open addressing table, with every key in one shared buffer.
+    Compact,
+    /// A hash table file in the store directory, for more keys than fit in memory. At most
+    /// `cache_bytes` of it are cached, and a bloom filter of about 10 bits per key spares
+    /// reading it for most absent keys. It is rebuilt from the log on every open.
+    ///
+    /// Only the pointers of the keys move to disk. Keys longer than a page, the blob of every
+    /// key whose value is in a blob file, the merge chain of every key holding operands and
+    /// the entries kept for open snapshots are still held in memory per key, so with
+    /// `blob_threshold` set or many counters the keyspace is still bound by memory.
+    OnDisk { cache_bytes: u64 },
+}
+
+impl KeyDirKind {
+    /// An empty keydir for the store in `path`.
+    pub(crate) fn new_keydir(&self, path: &PathBuf) -> KVResult<Box<dyn KeyDir>> {
+        match self {
+            KeyDirKind::HashMap => return Ok(Box::new(HashMap::<Vec<u8>, LogPointer>::new())),
+            KeyDirKind::Compact => return Ok(Box::new(CompactKeyDir::default())),
+            KeyDirKind::OnDisk { cache_bytes } => {
+                return Ok(Box::new(DiskKeyDir::new(path, *cache_bytes)?));
+            }
+        }
+    }
+}
+
+impl KeyDir for HashMap<Vec<u8>, LogPointer> {
+    fn get(&self, key: &[u8]) -> KVResult<Option<LogPointer>> {
+        return Ok(HashMap::get(self, key).copied());
+    }
+
+    fn insert(&mut self, key: Vec<u8>, log_pointer: LogPointer) -> KVResult<Option<LogPointer>> {
+        return Ok(HashMap::insert(self, key, log_pointer));
+    }
+
+    fn remove(&mut self, key: &[u8]) -> KVResult<Option<LogPointer>> {
+        return Ok(HashMap::remove(self, key));
+    }
+
+    fn len(&self) -> usize {
+        return HashMap::len(self);
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&[u8], LogPointer)) -> KVResult<()> {
+        for (key, log_pointer) in self.iter() {
+            f(key, *log_pointer);
+        }
+        return Ok(());
+    }
+
+    /// Every slot holds a key `Vec` and a `LogPointer` plus a control byte, and every key owns
+    /// its heap buffer.
+    fn memory_bytes(&self) -> u64 {
+        let slot_size = size_of::<(Vec<u8>, LogPointer)>() + 1;
+        let key_bytes: usize = self.keys().map(|key| key.capacity()).sum();
+
+        return (self.capacity() * slot_size + key_bytes) as u64;
+    }
+}
+
+/// Marks a slot whose pointer does not fit 32-bit fields and is kept in `wide_pointers`.
+const WIDE_GEN: u32 = u32::MAX;
+
+/// A key and its pointer packed into 24 bytes.
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+    /// Offset of the key in the arena plus one, 0 for an empty slot.
+    key_pos: u64,
+    key_len: u32,
+    gen: u32,
+    pos: u32,
+    len: u32,
+}
+
+impl Slot {
+    fn is_empty(&self) -> bool {
+        return self.key_pos == 0;
+    }
+}
+
+/// Open addressing table with linear probing. Keys are appended to one arena instead of
+/// owning a heap buffer each, and pointers are packed into 32-bit fields.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CompactKeyDir {
+    /// Empty, or a power of two slots long.
+    slots: Vec<Slot>,
+    arena: Vec<u8>,
+    len: usize,
+    /// Arena bytes of removed keys, reclaimed once they make up half the arena.
+    garbage_bytes: usize,
+    /// Pointers into generations or offsets past 32 bits, rare enough to keep apart.
+    wide_pointers: HashMap<Vec<u8>, LogPointer>,
+}
+
+impl CompactKeyDir {
+    fn key(&self, slot: &Slot) -> &[u8] {
+        let start = (slot.key_pos - 1) as usize;
+        return &self.arena[start..start + slot.key_len as usize];
+    }
+
+    fn pointer(&self, slot: &Slot) -> LogPointer {
+        if slot.gen == WIDE_GEN {
+            return self.wide_pointers[self.key(slot)];
+        }
+
+        return LogPointer::new(slot.gen as u64, slot.pos as u64, slot.len as u64);
+    }
+
+    /// Packs `log_pointer` into `slot`, or keeps it apart when it does not fit.
+    fn set_pointer(&mut self, index: usize, log_pointer: LogPointer) {
+        let fits = log_pointer.gen < WIDE_GEN as u64
+            && log_pointer.pos <= u32::MAX as u64
+            && log_pointer.len <= u32::MAX as u64;
+        let mut slot = self.slots[index];
+        if slot.gen == WIDE_GEN && fits {
+            let key = self.key(&slot).to_vec();
+            self.wide_pointers.remove(&key);
+        }
+
+        if fits {
+            slot.gen = log_pointer.gen as u32;
+            slot.pos = log_pointer.pos as u32;
+            slot.len = log_pointer.len as u32;
+        } else {
+            let key = self.key(&slot).to_vec();
+            self.wide_pointers.insert(key, log_pointer);
+            slot.gen = WIDE_GEN;
+        }
+        self.slots[index] = slot;
+    }
+
+    fn mask(&self) -> usize {
+        return self.slots.len() - 1;
+    }
+
+    /// Slot holding `key`, or the empty slot ending its probe sequence.
+    fn find(&self, key: &[u8]) -> (usize, bool) {
+        let mut index = hash(key) as usize & self.mask();
+        loop {
+            let slot = &self.slots[index];
+            if slot.is_empty() {
+                return (index, false);
+            }
+            if self.key(slot) == key {
+                return (index, true);
+            }
+            index = (index + 1) & self.mask();
+        }
+    }
+
+    /// Doubles the table once it is more than 80% full.
+    fn reserve_one(&mut self) {
+        if (self.len + 1) * 5 <= self.slots.len() * 4 {
+            return;
+        }
+
+        let slot_count = (self.slots.len() * 2).max(16);
+        let old_slots = std::mem::replace(&mut self.slots, vec![Slot::default(); slot_count]);
+        for slot in old_slots.into_iter().filter(|slot| !slot.is_empty()) {
+            let (index, _) = self.find(self.key(&slot));
+            self.slots[index] = slot;
+        }
+    }
+
+    /// Copies the keys still in use into a new arena.
+    fn compact_arena(&mut self) {
+        let mut arena = Vec::with_capacity(self.arena.len() - self.garbage_bytes);
+        for index in 0..self.slots.len() {
+            let slot = self.slots[index];
+            if slot.is_empty() {
+                continue;
+            }
+            let key_pos = arena.len() as u64 + 1;
+            arena.extend_from_slice(self.key(&slot));
+            self.slots[index].key_pos = key_pos;
+        }
+
+        self.arena = arena;
+        self.garbage_bytes = 0;
+    }
+}
+
+impl KeyDir for CompactKeyDir {
+    fn get(&self, key: &[u8]) -> KVResult<Option<LogPointer>> {
+        if self.slots.is_empty() {
+            return Ok(None);
+        }
+
+        match self.find(key) {
+            (index, true) => return Ok(Some(self.pointer(&self.slots[index]))),
+            (_, false) => return Ok(None),
+        }
+    }
+
+    fn insert(&mut self, key: Vec<u8>, log_pointer: LogPointer) -> KVResult<Option<LogPointer>> {
+        self.reserve_one();
+
+        let (index, found) = self.find(&key);
+        let old_log_pointer = if found {
+            Some(self.pointer(&self.slots[index]))
+        } else {
+            // Grows by an eighth instead of doubling, the arena holds most of the memory.
+            if self.arena.capacity() - self.arena.len() < key.len() {
+                self.arena.reserve_exact(self.arena.len() / 8 + key.len());
+            }
+            self.slots[index] = Slot {
+                key_pos: self.arena.len() as u64 + 1,
+                key_len: key.len() as u32,
+                ..Slot::default()
+            };
+            self.arena.extend_from_slice(&key);
+            self.len += 1;
+            None
+        };
+        self.set_pointer(index, log_pointer);
+
+        return Ok(old_log_pointer);
+    }
+
+    fn remove(&mut self, key: &[u8]) -> KVResult<Option<LogPointer>> {
+        if self.slots.is_empty() {
+            return Ok(None);
+        }
+        let mut hole = match self.find(key) {
+            (index, true) => index,
+            (_, false) => return Ok(None),
+        };
+
+        let slot = self.slots[hole];
+        let log_pointer = self.pointer(&slot);
+        if slot.gen == WIDE_GEN {
+            self.wide_pointers.remove(key);
+        }
+        self.len -= 1;
+        self.garbage_bytes += slot.key_len as usize;
+
+        // Shift back the slots after the hole that would no longer be found past it.
+        let mut index = (hole + 1) & self.mask();
+        while !self.slots[index].is_empty() {
+            let home = hash(self.key(&self.slots[index])) as usize & self.mask();
+            if index.wrapping_sub(home) & self.mask() >= index.wrapping_sub(hole) & self.mask() {
+                self.slots[hole] = self.slots[index];
+                hole = index;
+            }
+            index = (index + 1) & self.mask();
+        }
+        self.slots[hole] = Slot::default();
+
+        if self.garbage_bytes * 2 > self.arena.len() {
+            self.compact_arena();
+        }
+
+        return Ok(Some(log_pointer));
+    }
+
+    fn len(&self) -> usize {
+        return self.len;
+    }
+
+    fn for_each(&self, f: &mut dyn FnMut(&[u8], LogPointer)) -> KVResult<()> {
+        for slot in self.slots.iter().filter(|slot| !slot.is_empty()) {
+            f(self.key(slot), self.pointer(slot));
+        }
+        return Ok(());
+    }
+
+    fn memory_bytes(&self) -> u64 {
+        let wide_pointers = self.wide_pointers.memory_bytes() as usize;
+
+        return (self.slots.capacity() * size_of::<Slot>() + self.arena.capacity() + wide_pointers)
+            as u64;
+    }
+}
+
+/// FNV-1a, cheap for the short keys of most stores.
+pub(crate) fn hash(key: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    return hash;
+}
+
+#[test]
+fn keydir_every_kind_matches_hash_map() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let kinds = [
+        KeyDirKind::HashMap,
+        KeyDirKind::Compact,
+        // A few pages of cache, so most lookups go to the file.
+        KeyDirKind::OnDisk {
+            cache_bytes: 16 * 1024,
+        },
+    ];
+
+    for kind in kinds {
+        let mut expected: HashMap<Vec<u8>, LogPointer> = HashMap::new();
+        let mut keydir = kind.new_keydir(&path).unwrap();
+
+        for i in 0..20_000u64 {
+            // Every 1000th key does not fit a page of the on-disk table.
+            let key = if i % 1000 == 0 {
+                vec![b'k'; 4096]
+            } else {
+                format!("key{}", i % 6000).into_bytes()
+            };
+            // Every 7th pointer does not fit the packed fields of the compact keydir.
+            let log_pointer = if i % 7 == 0 {
+                LogPointer::new(1 << 40, i, 10)
+            } else {
+                LogPointer::new(i / 100, i, 10)
+            };
+            if i % 5 == 0 {
+                assert_eq!(
+                    HashMap::remove(&mut expected, &key),
+                    keydir.remove(&key).unwrap()
+                );
+            } else {
+                assert_eq!(
+                    HashMap::insert(&mut expected, key.clone(), log_pointer),
+                    keydir.insert(key, log_pointer).unwrap()
+                );
+            }
+        }
+
+        assert_eq!(expected.len(), keydir.len(), "{:?}", kind);
+        let mut entries = Vec::new();
+        keydir
+            .for_each(&mut |key, log_pointer| entries.push((key.to_vec(), log_pointer)))
+            .unwrap();
+        assert_eq!(expected.len(), entries.len(), "{:?}", kind);
+        for (key, log_pointer) in entries {
+            assert_eq!(Some(log_pointer), keydir.get(&key).unwrap());
+            assert_eq!(Some(&log_pointer), expected.get(&key));
+        }
+        assert_eq!(None, keydir.get(b"missing").unwrap());
+        if let KeyDirKind::OnDisk { .. } = kind {
+            assert!(keydir.memory_bytes() < 64 * 1024);
+        }
+    }
+}
+
+#[test]
+fn keydir_compact_memory_per_key() {
+    let key_count = 1_000_000;
+    let path = PathBuf::new();
+    let mut hash_map = KeyDirKind::HashMap.new_keydir(&path).unwrap();
+    let mut compact = KeyDirKind::Compact.new_keydir(&path).unwrap();
+    for i in 0..key_count {
+        let key = format!("key{:013}", i).into_bytes();
+        let log_pointer = LogPointer::new(i / 1000, i * 100, 100);
+        hash_map.insert(key.clone(), log_pointer).unwrap();
+        compact.insert(key, log_pointer).unwrap();
+    }
+
+    // The `HashMap` figure depends on how std grows its table, so only the saving is checked.
+    let hash_map_per_key = hash_map.memory_bytes() / key_count;
+    let compact_per_key = compact.memory_bytes() / key_count;
+    assert!(compact_per_key * 4 < hash_map_per_key * 3);
+    assert!(compact_per_key < 80);
+}
@@ -1,3 +1,8 @@
+use std::mem::size_of;
+
+use crate::utils::{u64_to_u8_array, u8_array_to_u64};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct LogPointer {
     pub gen: u64,
     pub pos: u64,
@@ -5,7 +10,32 @@ pub struct LogPointer {
 }
 
 impl LogPointer {
+    pub const ENCODED_LEN: usize = 3 * size_of::<u64>();
+
     pub fn new(gen: u64, pos: u64, len: u64) -> LogPointer {
         LogPointer { gen, pos, len }
     }
+
+    /// Whether the record behind `self` was appended to the log after the one behind `other`.
+    pub fn is_newer_than(&self, other: &LogPointer) -> bool {
+        (self.gen, self.pos) > (other.gen, other.pos)
+    }
+
+    pub fn encode(&self) -> [u8; LogPointer::ENCODED_LEN] {
+        let mut res = [0; LogPointer::ENCODED_LEN];
+        res[..8].copy_from_slice(&u64_to_u8_array(self.gen));
+        res[8..16].copy_from_slice(&u64_to_u8_array(self.pos));
+        res[16..].copy_from_slice(&u64_to_u8_array(self.len));
+        res
+    }
+
+    pub fn decode(data: &[u8]) -> LogPointer {
+        let read_u64 = |pos: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[pos..pos + size_of::<u64>()]);
+            u8_array_to_u64(&bytes)
+        };
+
+        LogPointer::new(read_u64(0), read_u64(8), read_u64(16))
+    }
 }
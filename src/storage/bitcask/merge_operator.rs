@@ -0,0 +1,144 @@
+#![allow(clippy::needless_return)]
+
+use std::io::{Error, ErrorKind};
+
+use crate::error::KVResult;
+
+/// Folds the operands appended by `Bitcask::merge` into the value of their key, when the key
+/// is read and when a merge rewrites it. Has to be registered with
+/// `BitcaskOptions::merge_operator` whenever the store holds merge operands.
+pub trait MergeOperator: Send + Sync {
+    /// The value of `key` after applying `operands`, oldest first, to `existing`, which is
+    /// `None` when the key was not set before them.
+    fn merge(&self, key: &[u8], existing: Option<&[u8]>, operands: &[Vec<u8>])
+        -> KVResult<Vec<u8>>;
+}
+
+/// Appends every operand to the value, for values used as lists.
+#[derive(Debug, Default)]
+pub struct AppendMergeOperator;
+
+impl MergeOperator for AppendMergeOperator {
+    fn merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[Vec<u8>],
+    ) -> KVResult<Vec<u8>> {
+        let mut value = existing.map_or_else(Vec::new, |existing| existing.to_vec());
+        for operand in operands {
+            value.extend_from_slice(operand);
+        }
+
+        return Ok(value);
+    }
+}
+
+/// One record of a key appended by `increment` or `merge`.
+pub(crate) enum Operand {
+    Increment(i64),
+    Merge(Vec<u8>),
+}
+
+/// Folds `operands`, oldest first, into `existing`. Increments are added by the store, runs of
+/// other operands are handed to `merge_operator`.
+pub(crate) fn fold_operands(
+    key: &[u8],
+    existing: Option<Vec<u8>>,
+    operands: Vec<Operand>,
+    merge_operator: Option<&dyn MergeOperator>,
+) -> KVResult<Vec<u8>> {
+    let mut value = existing;
+    let mut pending = Vec::new();
+
+    for operand in operands {
+        match operand {
+            Operand::Increment(delta) => {
+                value = apply_pending(key, value, &mut pending, merge_operator)?;
+                value = Some(add_to_counter(key, value.as_deref(), delta)?);
+            }
+            Operand::Merge(operand) => pending.push(operand),
+        }
+    }
+    value = apply_pending(key, value, &mut pending, merge_operator)?;
+
+    return Ok(value.unwrap_or_default());
+}
+
+fn apply_pending(
+    key: &[u8],
+    value: Option<Vec<u8>>,
+    pending: &mut Vec<Vec<u8>>,
+    merge_operator: Option<&dyn MergeOperator>,
+) -> KVResult<Option<Vec<u8>>> {
+    if pending.is_empty() {
+        return Ok(value);
+    }
+
+    let merge_operator = merge_operator.ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "the store holds merge operands but no merge operator is registered",
+        )
+    })?;
+    let value = merge_operator.merge(key, value.as_deref(), pending)?;
+    pending.clear();
+
+    return Ok(Some(value));
+}
+
+/// Counters are stored as decimal text, a missing value counts as 0. A sum past the range of a
+/// 64-bit integer fails.
+fn add_to_counter(key: &[u8], existing: Option<&[u8]>, delta: i64) -> KVResult<Vec<u8>> {
+    let counter = match existing {
+        None => 0,
+        Some(existing) => std::str::from_utf8(existing)
+            .ok()
+            .and_then(|existing| existing.parse::<i64>().ok())
+            .ok_or_else(|| {
+                let message = format!("value of {} is not a 64-bit integer", key.escape_ascii());
+                Error::new(ErrorKind::InvalidData, message)
+            })?,
+    };
+
+    let sum = counter.checked_add(delta).ok_or_else(|| {
+        let message = format!("value of {} overflows a 64-bit integer", key.escape_ascii());
+        Error::new(ErrorKind::InvalidData, message)
+    })?;
+
+    return Ok(sum.to_string().into_bytes());
+}
+
+#[test]
+fn merge_operator_fold_operands() {
+    let operands = vec![
+        Operand::Increment(5),
+        Operand::Increment(-2),
+        Operand::Merge(b"0".to_vec()),
+        Operand::Increment(1),
+    ];
+    let value = fold_operands(
+        b"key",
+        Some(b"10".to_vec()),
+        operands,
+        Some(&AppendMergeOperator),
+    )
+    .unwrap();
+    assert_eq!(b"131".to_vec(), value);
+
+    let value = fold_operands(b"key", None, vec![Operand::Increment(i64::MAX)], None).unwrap();
+    assert_eq!(i64::MAX.to_string().into_bytes(), value);
+    let operands = vec![Operand::Increment(i64::MAX), Operand::Increment(1)];
+    assert!(fold_operands(b"key", None, operands, None).is_err());
+    let existing = Some(i64::MIN.to_string().into_bytes());
+    assert!(fold_operands(b"key", existing, vec![Operand::Increment(-1)], None).is_err());
+
+    assert!(fold_operands(b"key", None, vec![Operand::Merge(b"a".to_vec())], None).is_err());
+    assert!(fold_operands(
+        b"key",
+        Some(b"abc".to_vec()),
+        vec![Operand::Increment(1)],
+        None
+    )
+    .is_err());
+}
@@ -0,0 +1,121 @@
+#![allow(clippy::needless_return)]
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::storage::bitcask::stats::GenerationStats;
+
+/// Decides which generations a background merge rewrites.
+pub trait MergePolicy: Send + Sync {
+    /// Picks generations out of `generations`, which holds every file not already being
+    /// merged including the active one. An empty selection skips this merge.
+    fn select(&self, generations: &[GenerationStats]) -> Vec<u64>;
+}
+
+/// Riak style merge triggers: a merge only starts once some file crosses one of the trigger
+/// values, and then takes every file crossing the lower threshold values along with it.
+pub struct ThresholdMergePolicy {
+    /// Fraction of dead bytes in a single file that starts a merge.
+    pub frag_merge_trigger: f64,
+    /// Dead bytes in a single file that start a merge.
+    pub dead_bytes_merge_trigger: u64,
+    /// Fraction of dead bytes for a file to be included once a merge starts.
+    pub frag_threshold: f64,
+    /// Dead bytes for a file to be included once a merge starts.
+    pub dead_bytes_threshold: u64,
+    /// UTC hours `(start, end)` during which merges may start, `None` allows any time.
+    /// A window with `start > end` wraps around midnight.
+    pub merge_window: Option<(u64, u64)>,
+}
+
+impl Default for ThresholdMergePolicy {
+    fn default() -> Self {
+        ThresholdMergePolicy {
+            frag_merge_trigger: 0.6,
+            dead_bytes_merge_trigger: 512 * 1024 * 1024,
+            frag_threshold: 0.4,
+            dead_bytes_threshold: 128 * 1024 * 1024,
+            merge_window: None,
+        }
+    }
+}
+
+impl ThresholdMergePolicy {
+    fn in_merge_window(&self, hour: u64) -> bool {
+        match self.merge_window {
+            None => true,
+            Some((start, end)) if start <= end => start <= hour && hour <= end,
+            Some((start, end)) => hour >= start || hour <= end,
+        }
+    }
+}
+
+impl MergePolicy for ThresholdMergePolicy {
+    fn select(&self, generations: &[GenerationStats]) -> Vec<u64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        if !self.in_merge_window(now / 3600 % 24) {
+            return Vec::new();
+        }
+
+        let triggered = generations.iter().any(|generation| {
+            generation.fragmentation() >= self.frag_merge_trigger
+                || generation.dead_bytes >= self.dead_bytes_merge_trigger
+        });
+        if !triggered {
+            return Vec::new();
+        }
+
+        return generations
+            .iter()
+            .filter(|generation| {
+                generation.fragmentation() >= self.frag_threshold
+                    || generation.dead_bytes >= self.dead_bytes_threshold
+            })
+            .map(|generation| generation.gen)
+            .collect();
+    }
+}
+
+#[test]
+fn threshold_merge_policy_select_fragmented() {
+    let policy = ThresholdMergePolicy::default();
+    let generations = vec![
+        GenerationStats {
+            gen: 0,
+            live_bytes: 90,
+            dead_bytes: 10,
+            live_keys: 9,
+            ..GenerationStats::default()
+        },
+        GenerationStats {
+            gen: 1,
+            live_bytes: 50,
+            dead_bytes: 50,
+            live_keys: 5,
+            ..GenerationStats::default()
+        },
+        GenerationStats {
+            gen: 2,
+            live_bytes: 20,
+            dead_bytes: 80,
+            live_keys: 2,
+            ..GenerationStats::default()
+        },
+    ];
+
+    assert_eq!(vec![1, 2], policy.select(&generations));
+    assert!(policy.select(&generations[..2]).is_empty());
+}
+
+#[test]
+fn threshold_merge_policy_merge_window() {
+    let policy = ThresholdMergePolicy {
+        merge_window: Some((22, 3)),
+        ..ThresholdMergePolicy::default()
+    };
+
+    assert!(policy.in_merge_window(23));
+    assert!(policy.in_merge_window(2));
+    assert!(!policy.in_merge_window(12));
+}
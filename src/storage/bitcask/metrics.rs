@@ -0,0 +1,238 @@
+#![allow(clippy::needless_return)]
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const OPERATION_BUCKETS: [f64; 8] = [0.00001, 0.0001, 0.001, 0.01, 0.1, 0.5, 1.0, 10.0];
+const COMPACTION_BUCKETS: [f64; 6] = [0.01, 0.1, 1.0, 10.0, 60.0, 600.0];
+
+/// Engine metrics, rendered in the Prometheus text exposition format.
+pub struct Metrics {
+    pub get_duration: Histogram,
+    pub set_duration: Histogram,
+    pub remove_duration: Histogram,
+    pub increment_duration: Histogram,
+    pub merge_duration: Histogram,
+    pub compaction_duration: Histogram,
+    pub bytes_written: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub fsyncs: AtomicU64,
+    pub get_errors: AtomicU64,
+    pub set_errors: AtomicU64,
+    pub remove_errors: AtomicU64,
+    pub increment_errors: AtomicU64,
+    pub merge_errors: AtomicU64,
+    pub compaction_errors: AtomicU64,
+}
+
+pub struct Histogram {
+    bounds: &'static [f64],
+    counts: Vec<AtomicU64>,
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            counts: bounds.iter().map(|_| AtomicU64::new(0)).collect(),
+            sum_nanos: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = self.bounds.iter().position(|bound| seconds <= *bound) {
+            self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            let le = format!("le=\"{}\"", bound);
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                name,
+                label_set(&[labels, &le]),
+                cumulative
+            );
+        }
+
+        let count = self.count();
+        let _ = writeln!(
+            out,
+            "{}_bucket{} {}",
+            name,
+            label_set(&[labels, "le=\"+Inf\""]),
+            count
+        );
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{}_sum{} {}", name, label_set(&[labels]), sum);
+        let _ = writeln!(out, "{}_count{} {}", name, label_set(&[labels]), count);
+    }
+}
+
+/// Joins the non-empty labels into `{a,b}`, or nothing at all when every label is empty.
+fn label_set(labels: &[&str]) -> String {
+    let labels: Vec<&str> = labels
+        .iter()
+        .filter(|label| !label.is_empty())
+        .cloned()
+        .collect();
+    if labels.is_empty() {
+        return String::new();
+    }
+
+    return format!("{{{}}}", labels.join(","));
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            get_duration: Histogram::new(&OPERATION_BUCKETS),
+            set_duration: Histogram::new(&OPERATION_BUCKETS),
+            remove_duration: Histogram::new(&OPERATION_BUCKETS),
+            increment_duration: Histogram::new(&OPERATION_BUCKETS),
+            merge_duration: Histogram::new(&OPERATION_BUCKETS),
+            compaction_duration: Histogram::new(&COMPACTION_BUCKETS),
+            bytes_written: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            fsyncs: AtomicU64::new(0),
+            get_errors: AtomicU64::new(0),
+            set_errors: AtomicU64::new(0),
+            remove_errors: AtomicU64::new(0),
+            increment_errors: AtomicU64::new(0),
+            merge_errors: AtomicU64::new(0),
+            compaction_errors: AtomicU64::new(0),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        write_header(
+            &mut out,
+            "kvs_operation_duration_seconds",
+            "histogram",
+            "Latency of engine operations.",
+        );
+        self.get_duration
+            .render(&mut out, "kvs_operation_duration_seconds", "op=\"get\"");
+        self.set_duration
+            .render(&mut out, "kvs_operation_duration_seconds", "op=\"set\"");
+        self.remove_duration
+            .render(&mut out, "kvs_operation_duration_seconds", "op=\"remove\"");
+        self.increment_duration.render(
+            &mut out,
+            "kvs_operation_duration_seconds",
+            "op=\"increment\"",
+        );
+        self.merge_duration
+            .render(&mut out, "kvs_operation_duration_seconds", "op=\"merge\"");
+
+        write_header(
+            &mut out,
+            "kvs_compaction_duration_seconds",
+            "histogram",
+            "Duration of completed merges.",
+        );
+        self.compaction_duration
+            .render(&mut out, "kvs_compaction_duration_seconds", "");
+
+        write_counter(
+            &mut out,
+            "kvs_compactions_total",
+            "Completed merges.",
+            self.compaction_duration.count(),
+        );
+        write_counter(
+            &mut out,
+            "kvs_written_bytes_total",
+            "Bytes appended to the log.",
+            self.bytes_written.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "kvs_read_bytes_total",
+            "Bytes read from the log.",
+            self.bytes_read.load(Ordering::Relaxed),
+        );
+        write_counter(
+            &mut out,
+            "kvs_fsyncs_total",
+            "Fsyncs of log files.",
+            self.fsyncs.load(Ordering::Relaxed),
+        );
+
+        write_header(
+            &mut out,
+            "kvs_errors_total",
+            "counter",
+            "Failed engine operations.",
+        );
+        let errors = [
+            ("get", &self.get_errors),
+            ("set", &self.set_errors),
+            ("remove", &self.remove_errors),
+            ("increment", &self.increment_errors),
+            ("merge", &self.merge_errors),
+            ("compaction", &self.compaction_errors),
+        ];
+        for (op, errors) in errors.iter() {
+            let _ = writeln!(
+                out,
+                "kvs_errors_total{{op=\"{}\"}} {}",
+                op,
+                errors.load(Ordering::Relaxed)
+            );
+        }
+
+        return out;
+    }
+}
+
+fn write_header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, value: u64) {
+    write_header(out, name, "counter", help);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+#[test]
+fn metrics_render_cumulative_buckets() {
+    let metrics = Metrics::new();
+
+    metrics.get_duration.observe(Duration::from_micros(5));
+    metrics.get_duration.observe(Duration::from_micros(50));
+    metrics.get_duration.observe(Duration::from_secs(20));
+
+    let text = metrics.render();
+    assert!(text.contains("kvs_operation_duration_seconds_bucket{op=\"get\",le=\"0.00001\"} 1\n"));
+    assert!(text.contains("kvs_operation_duration_seconds_bucket{op=\"get\",le=\"0.0001\"} 2\n"));
+    assert!(text.contains("kvs_operation_duration_seconds_bucket{op=\"get\",le=\"+Inf\"} 3\n"));
+    assert!(text.contains("kvs_operation_duration_seconds_count{op=\"get\"} 3\n"));
+    assert!(text.contains("kvs_compaction_duration_seconds_count 0\n"));
+}
@@ -0,0 +1,48 @@
+#![allow(clippy::needless_return)]
+
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+use memmap2::Mmap;
+
+use crate::error::KVResult;
+
+pub struct MmapReader {
+    mmap: Option<Mmap>,
+}
+
+impl MmapReader {
+    pub fn new(path: &PathBuf) -> KVResult<MmapReader> {
+        let file = File::open(path)?;
+
+        // Empty files can not be mapped on every platform, and there is nothing to read anyway.
+        let mmap = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            // Sealed generation files are never written again while the engine holds them.
+            Some(unsafe { Mmap::map(&file)? })
+        };
+
+        return Ok(MmapReader { mmap });
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        match &self.mmap {
+            Some(mmap) => &mmap[..],
+            None => &[],
+        }
+    }
+
+    pub fn read_at(&self, pos: u64, len: u64) -> KVResult<&[u8]> {
+        let data = self.as_slice();
+        let start = pos as usize;
+        let end = start.saturating_add(len as usize);
+
+        if end > data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "log pointer out of bounds").into());
+        }
+
+        return Ok(&data[start..end]);
+    }
+}
@@ -1,3 +1,24 @@
+pub mod backup;
 pub mod bitcask_engine;
+pub mod blob;
+pub mod changes;
 mod command;
+mod compaction;
+mod disk_keydir;
+pub mod dump;
+pub mod events;
+pub mod export;
+mod group_commit;
+pub mod keydir;
 mod log_pointer;
+pub mod merge_operator;
+pub mod merge_policy;
+pub mod metrics;
+mod mmap_reader;
+pub mod namespace;
+pub mod options;
+pub mod repair;
+pub mod stats;
+pub mod stream;
+mod value_cache;
+pub mod verify;
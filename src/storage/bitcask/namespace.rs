@@ -0,0 +1,207 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::BTreeMap;
+use std::fs::{read, rename, File};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::KVResult;
+use crate::storage::bitcask::bitcask_engine::{Bitcask, Snapshot, ValueMeta};
+use crate::storage::bitcask::stream::ValueReader;
+
+/// Lists the namespaces of a store next to its log files.
+pub(crate) const NAMESPACES_FILE_NAME: &str = "NAMESPACES.json";
+
+/// Id of the namespace every store has, which holds the keys written through `Bitcask` itself.
+pub const DEFAULT_NAMESPACE: u32 = 0;
+
+pub const DEFAULT_NAMESPACE_NAME: &str = "default";
+
+/// One write of `Bitcask::write_batch_across`, a `None` value removes the key.
+pub type NamespacedWrite<'a> = (&'a Namespace, Vec<u8>, Option<Vec<u8>>);
+
+/// Names and ids of the namespaces created in a store, the default one aside.
+///
+/// Ids are never reused, so the records a dropped namespace left in the logs are told apart
+/// from the ones of a namespace created later under the same name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct NamespaceRegistry {
+    next_id: u32,
+    namespaces: BTreeMap<String, u32>,
+}
+
+impl NamespaceRegistry {
+    /// The namespaces of the store in `path`, none but the default one for a new store.
+    #[allow(clippy::ptr_arg)]
+    pub(crate) fn load(path: &PathBuf) -> KVResult<NamespaceRegistry> {
+        let registry = match read(path.join(NAMESPACES_FILE_NAME)) {
+            Ok(registry) => registry,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(NamespaceRegistry {
+                    next_id: DEFAULT_NAMESPACE + 1,
+                    namespaces: BTreeMap::new(),
+                });
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let registry = serde_json::from_slice(&registry).map_err(Error::from)?;
+
+        return Ok(registry);
+    }
+
+    /// Id of namespace `name`.
+    pub(crate) fn id(&self, name: &str) -> Option<u32> {
+        if name == DEFAULT_NAMESPACE_NAME {
+            return Some(DEFAULT_NAMESPACE);
+        }
+
+        return self.namespaces.get(name).copied();
+    }
+
+    pub(crate) fn contains(&self, id: u32) -> bool {
+        return id == DEFAULT_NAMESPACE || self.namespaces.values().any(|other| *other == id);
+    }
+
+    /// Every namespace with its id, the default one first and the others in name order.
+    pub(crate) fn namespaces(&self) -> Vec<(String, u32)> {
+        let mut namespaces = vec![(DEFAULT_NAMESPACE_NAME.to_owned(), DEFAULT_NAMESPACE)];
+        namespaces.extend(
+            self.namespaces
+                .iter()
+                .map(|(name, id)| (name.to_owned(), *id)),
+        );
+
+        return namespaces;
+    }
+
+    /// Adds namespace `name` and persists it to the store in `path`, returns its id.
+    pub(crate) fn create(&mut self, path: &PathBuf, name: &str) -> KVResult<u32> {
+        if self.id(name).is_some() {
+            let message = format!("namespace {} already exists", name);
+            return Err(Error::new(ErrorKind::AlreadyExists, message).into());
+        }
+
+        let mut registry = self.clone();
+        let id = registry.next_id;
+        registry.next_id += 1;
+        registry.namespaces.insert(name.to_owned(), id);
+        registry.save(path)?;
+        *self = registry;
+
+        return Ok(id);
+    }
+
+    /// Forgets namespace `name` in the store in `path`, returns the id it had.
+    pub(crate) fn remove(&mut self, path: &PathBuf, name: &str) -> KVResult<u32> {
+        if name == DEFAULT_NAMESPACE_NAME {
+            let message = "the default namespace can not be dropped";
+            return Err(Error::new(ErrorKind::InvalidInput, message).into());
+        }
+
+        let mut registry = self.clone();
+        let id = registry
+            .namespaces
+            .remove(name)
+            .ok_or_else(|| not_found(name))?;
+        registry.save(path)?;
+        *self = registry;
+
+        return Ok(id);
+    }
+
+    /// Replaces the file in one rename, a crash leaves either the old or the new list.
+    fn save(&self, path: &PathBuf) -> KVResult<()> {
+        let registry = serde_json::to_vec_pretty(self).map_err(Error::from)?;
+        let temp_path = path.join(format!("{}.tmp", NAMESPACES_FILE_NAME));
+
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&registry)?;
+        file.sync_all()?;
+        rename(&temp_path, path.join(NAMESPACES_FILE_NAME))?;
+        File::open(path)?.sync_all()?;
+
+        return Ok(());
+    }
+}
+
+pub(crate) fn not_found(name: &str) -> Error {
+    let message = format!("namespace {} does not exist", name);
+    return Error::new(ErrorKind::NotFound, message);
+}
+
+/// A named set of keys sharing the log files of its store, see `Bitcask::create_namespace`.
+///
+/// Keys of different namespaces never clash. Every operation fails once the namespace is
+/// dropped, even after a namespace of the same name is created again.
+#[derive(Clone)]
+pub struct Namespace {
+    store: Bitcask,
+    id: u32,
+    name: String,
+}
+
+impl Namespace {
+    pub(crate) fn new(store: Bitcask, id: u32, name: &str) -> Namespace {
+        Namespace {
+            store,
+            id,
+            name: name.to_owned(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        return &self.name;
+    }
+
+    pub(crate) fn id(&self) -> u32 {
+        return self.id;
+    }
+
+    pub fn set(&self, key: Vec<u8>, value: Vec<u8>) -> KVResult<()> {
+        return self.store.set_in(self.id, key, value);
+    }
+
+    /// See `Bitcask::set_from_reader`.
+    pub fn set_from_reader(&self, key: Vec<u8>, reader: &mut impl Read, len: u64) -> KVResult<()> {
+        return self.store.set_from_reader_in(self.id, key, reader, len);
+    }
+
+    pub fn get(&self, key: Vec<u8>) -> KVResult<Option<Vec<u8>>> {
+        return self.store.get_in(self.id, key);
+    }
+
+    /// See `Bitcask::get_reader`.
+    pub fn get_reader(&self, key: Vec<u8>) -> KVResult<Option<ValueReader>> {
+        return self.store.get_reader_in(self.id, key);
+    }
+
+    /// See `Bitcask::get_with_meta`.
+    pub fn get_with_meta(&self, key: Vec<u8>) -> KVResult<Option<ValueMeta>> {
+        return self.store.get_with_meta_in(self.id, key);
+    }
+
+    pub fn remove(&self, key: Vec<u8>) -> KVResult<()> {
+        return self.store.remove_in(self.id, key);
+    }
+
+    pub fn increment(&self, key: Vec<u8>, delta: i64) -> KVResult<()> {
+        return self.store.increment_in(self.id, key, delta);
+    }
+
+    pub fn merge(&self, key: Vec<u8>, operand: Vec<u8>) -> KVResult<()> {
+        return self.store.merge_in(self.id, key, operand);
+    }
+
+    /// See `Bitcask::write_batch`.
+    pub fn write_batch(&self, writes: Vec<(Vec<u8>, Option<Vec<u8>>)>) -> KVResult<()> {
+        return self.store.write_batch_in(self.id, writes);
+    }
+
+    /// A view of the keys of this namespace, see `Bitcask::snapshot`. Reading it fails once
+    /// the namespace is dropped.
+    pub fn snapshot(&self) -> KVResult<Snapshot> {
+        return self.store.snapshot_in(self.id);
+    }
+}
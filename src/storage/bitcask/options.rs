@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::constants::{COMPACTION_THRESHOLD, MAX_BLOB_FILE_SIZE, SLOW_OPERATION_THRESHOLD_MILLIS};
+use crate::storage::bitcask::events::EventListener;
+#[cfg(test)]
+use crate::storage::bitcask::events::QuietEventListener;
+#[cfg(not(test))]
+use crate::storage::bitcask::events::StderrEventListener;
+use crate::storage::bitcask::keydir::KeyDirKind;
+use crate::storage::bitcask::merge_operator::MergeOperator;
+use crate::storage::bitcask::merge_policy::{MergePolicy, ThresholdMergePolicy};
+
+pub struct BitcaskOptions {
+    /// Upper bound in bytes for the LRU cache of decoded values, `None` disables the cache.
+    pub value_cache_capacity: Option<u64>,
+    /// Fsync the active file before a write returns, concurrent writes share one fsync.
+    pub sync_writes: bool,
+    /// Stale bytes after which a background merge starts, `None` only merges on `compact`.
+    pub compaction_threshold: Option<u64>,
+    /// Upper bound on the bytes per second copied by all running merges together.
+    pub compaction_bytes_per_sec: Option<u64>,
+    /// Number of merges allowed to run at the same time.
+    pub max_concurrent_merges: usize,
+    /// Picks the generations a background merge rewrites, `compact` always merges them all.
+    pub merge_policy: Arc<dyn MergePolicy>,
+    /// Receives the events of the store, by default warnings and errors go to stderr.
+    pub event_listener: Arc<dyn EventListener>,
+    /// Operations slower than this are reported as events, `None` never reports them.
+    pub slow_operation_threshold: Option<Duration>,
+    /// Values of at least this many bytes go to blob files and only a reference to them to
+    /// the log, so merges do not copy them. `None` keeps every value in the log.
+    pub blob_threshold: Option<u64>,
+    /// Size in bytes after which writes move on to a new blob file.
+    pub max_blob_file_size: u64,
+    /// Layout of the index, the compact one trades some speed for memory. The on-disk one only
+    /// moves the pointers of the keys out of memory, see `KeyDirKind::OnDisk`.
+    pub keydir: KeyDirKind,
+    /// Folds the operands of `Bitcask::merge`, which fails while there is none. Increments
+    /// need no operator.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+}
+
+impl Default for BitcaskOptions {
+    fn default() -> Self {
+        BitcaskOptions {
+            value_cache_capacity: None,
+            sync_writes: false,
+            compaction_threshold: Some(COMPACTION_THRESHOLD),
+            compaction_bytes_per_sec: None,
+            max_concurrent_merges: 1,
+            merge_policy: Arc::new(ThresholdMergePolicy::default()),
+            #[cfg(not(test))]
+            event_listener: Arc::new(StderrEventListener::default()),
+            #[cfg(test)]
+            event_listener: Arc::new(QuietEventListener),
+            slow_operation_threshold: Some(Duration::from_millis(SLOW_OPERATION_THRESHOLD_MILLIS)),
+            blob_threshold: None,
+            max_blob_file_size: MAX_BLOB_FILE_SIZE,
+            keydir: KeyDirKind::default(),
+            merge_operator: None,
+        }
+    }
+}
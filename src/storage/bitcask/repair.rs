@@ -0,0 +1,183 @@
+#![allow(clippy::needless_return)]
+
+use std::fs::{copy, create_dir_all, hard_link, read, rename, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::error::KVResult;
+use crate::storage::bitcask::bitcask_engine::{get_log_file_dir, get_sorted_gen_list};
+use crate::storage::bitcask::command::{salvage_record_key, RECORD_HEADER_LEN};
+use crate::storage::bitcask::verify::{scan_records, CorruptRange};
+
+/// Directory inside the store the original files of repaired generations are moved to.
+const QUARANTINE_DIR_NAME: &str = "quarantine";
+
+#[derive(Debug, Serialize)]
+pub struct GenerationRepair {
+    pub gen: u64,
+    pub salvaged_records: u64,
+    pub lost_ranges: Vec<CorruptRange>,
+    /// Keys of the records in `lost_ranges` whose header could still be read, escaped. The
+    /// ranges can hold records of other keys whose header is lost too.
+    pub lost_keys: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct RepairReport {
+    /// Generations that held corrupt ranges, the others are left untouched.
+    pub generations: Vec<GenerationRepair>,
+    /// Where the original files of `generations` were kept, `None` when nothing was repaired.
+    pub quarantine_dir: Option<PathBuf>,
+}
+
+impl RepairReport {
+    pub fn lost_bytes(&self) -> u64 {
+        return self
+            .generations
+            .iter()
+            .flat_map(|generation| &generation.lost_ranges)
+            .map(|range| range.len)
+            .sum();
+    }
+}
+
+/// Rewrites every generation file in `path` holding corrupt ranges with only the records that
+/// are still valid, after keeping the original in a quarantine directory. Records past a
+/// corrupt range are found again by their magic. The store must not be open.
+pub fn repair(path: &PathBuf) -> KVResult<RepairReport> {
+    let mut report = RepairReport::default();
+
+    for gen in get_sorted_gen_list(path)? {
+        let log_path = get_log_file_dir(gen, path);
+        let buffer = read(&log_path)?;
+        let (records, corrupt_ranges) = scan_records(gen, &buffer);
+        if corrupt_ranges.is_empty() {
+            continue;
+        }
+
+        let quarantine_dir = match &report.quarantine_dir {
+            Some(quarantine_dir) => quarantine_dir.to_owned(),
+            None => {
+                let quarantine_dir = path.join(QUARANTINE_DIR_NAME).join(
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map_or(0, |duration| duration.as_nanos())
+                        .to_string(),
+                );
+                create_dir_all(&quarantine_dir)?;
+                report.quarantine_dir = Some(quarantine_dir.to_owned());
+                quarantine_dir
+            }
+        };
+        let quarantine_path = get_log_file_dir(gen, &quarantine_dir);
+        if hard_link(&log_path, &quarantine_path).is_err() {
+            copy(&log_path, &quarantine_path)?;
+        }
+
+        // Written aside and renamed over the original, so a crash leaves either file whole.
+        let repair_path = log_path.with_extension("repair");
+        let mut repair_file = File::create(&repair_path)?;
+        for (pos, len) in &records {
+            repair_file.write_all(&buffer[*pos..*pos + *len])?;
+        }
+        repair_file.sync_all()?;
+        rename(&repair_path, &log_path)?;
+        File::open(&quarantine_dir)?.sync_all()?;
+        File::open(path)?.sync_all()?;
+
+        let lost_keys = lost_keys(&buffer, &corrupt_ranges);
+        report.generations.push(GenerationRepair {
+            gen,
+            salvaged_records: records.len() as u64,
+            lost_ranges: corrupt_ranges,
+            lost_keys,
+        });
+    }
+
+    return Ok(report);
+}
+
+/// Keys found in the corrupt ranges of `buffer`, following the claimed record lengths as long
+/// as they stay within a range.
+fn lost_keys(buffer: &[u8], corrupt_ranges: &[CorruptRange]) -> Vec<String> {
+    let mut keys = Vec::new();
+
+    for range in corrupt_ranges {
+        let range_end = (range.offset + range.len) as usize;
+        let mut pos = range.offset as usize;
+        while let Some((key, len)) = salvage_record_key(&buffer[pos..range_end]) {
+            let key = key.escape_ascii().to_string();
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+            if len < RECORD_HEADER_LEN || len > range_end - pos {
+                break;
+            }
+            pos += len;
+        }
+    }
+
+    return keys;
+}
+
+#[test]
+fn repair_salvages_records_after_corruption() {
+    use crate::storage::bitcask::bitcask_engine::Bitcask;
+    use crate::storage::bitcask::verify::verify;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+    for i in 0..10 {
+        store_engine
+            .set(format!("key{}", i).into_bytes(), b"value".to_vec())
+            .unwrap();
+    }
+    drop(store_engine);
+
+    let gen = *get_sorted_gen_list(&path).unwrap().last().unwrap();
+    let log_path = get_log_file_dir(gen, &path);
+    let original = read(&log_path).unwrap();
+    let record_len = original.len() / 10;
+    let mut file = OpenOptions::new().write(true).open(&log_path).unwrap();
+    file.seek(SeekFrom::Start(record_len as u64 * 3 + 2))
+        .unwrap();
+    file.write_all(&[0xff; 4]).unwrap();
+    drop(file);
+    let corrupt = read(&log_path).unwrap();
+
+    // `open` refuses a generation with records after the corrupt one.
+    assert!(Bitcask::open(&path).is_err());
+
+    let report = repair(&path).unwrap();
+    assert_eq!(1, report.generations.len());
+    assert_eq!(9, report.generations[0].salvaged_records);
+    assert_eq!(record_len as u64, report.lost_bytes());
+    assert_eq!(vec!["key3".to_owned()], report.generations[0].lost_keys);
+    assert_eq!(
+        corrupt,
+        read(get_log_file_dir(
+            gen,
+            report.quarantine_dir.as_ref().unwrap()
+        ))
+        .unwrap()
+    );
+    assert!(verify(&path).unwrap().is_healthy());
+
+    let store_engine = Bitcask::open(&path).unwrap();
+    assert!(store_engine.get(b"key3".to_vec()).is_err());
+    for i in (0..10).filter(|i| *i != 3) {
+        assert_eq!(
+            Some(b"value".to_vec()),
+            store_engine.get(format!("key{}", i).into_bytes()).unwrap()
+        );
+    }
+    drop(store_engine);
+
+    assert!(repair(&path).unwrap().generations.is_empty());
+}
@@ -0,0 +1,68 @@
+#![allow(clippy::needless_return)]
+
+use serde::Serialize;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct BitcaskStats {
+    pub key_count: u64,
+    pub generations: Vec<GenerationStats>,
+    /// Dead bytes and tombstones over all generations, what a full merge would reclaim.
+    pub uncompacted: u64,
+    /// Rough heap usage of the in-memory index, keys included.
+    pub index_memory_bytes: u64,
+    pub gets: u64,
+    pub sets: u64,
+    pub removes: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    pub cache_size: u64,
+    pub write_batches: u64,
+    pub compactions: u64,
+    /// Every namespace, the default one first, `key_count` and `index_memory_bytes` are
+    /// summed over them.
+    pub namespaces: Vec<NamespaceStats>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct NamespaceStats {
+    pub name: String,
+    pub key_count: u64,
+    /// Bytes of the records the keys of the namespace point at.
+    pub live_bytes: u64,
+    pub index_memory_bytes: u64,
+}
+
+/// Byte accounting of one generation file, dead bytes are overwritten records.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct GenerationStats {
+    pub gen: u64,
+    pub file_size: u64,
+    pub live_bytes: u64,
+    pub dead_bytes: u64,
+    /// Tombstones still shadow records in older generations, a merge only drops them once
+    /// no older generation is left.
+    pub tombstone_bytes: u64,
+    pub live_keys: u64,
+}
+
+impl GenerationStats {
+    pub fn new(gen: u64) -> GenerationStats {
+        GenerationStats {
+            gen,
+            ..GenerationStats::default()
+        }
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.live_bytes + self.dead_bytes + self.tombstone_bytes
+    }
+
+    /// Fraction of the file taken by dead bytes, 0 for an empty file.
+    pub fn fragmentation(&self) -> f64 {
+        if self.total_bytes() == 0 {
+            return 0.0;
+        }
+
+        return self.dead_bytes as f64 / self.total_bytes() as f64;
+    }
+}
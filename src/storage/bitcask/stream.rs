@@ -0,0 +1,152 @@
+#![allow(clippy::needless_return)]
+
+use std::fs::File;
+use std::io::{BufReader, Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Take, Write};
+use std::path::PathBuf;
+
+use crate::constants::STREAM_CHUNK_LEN;
+use crate::error::KVResult;
+use crate::storage::bitcask::bitcask_engine::get_log_file_dir;
+use crate::storage::bitcask::blob::get_blob_file_dir;
+use crate::storage::bitcask::command::{
+    head_value_len, record_checksum, record_hasher, record_head_len, Command, RECORD_HEADER_LEN,
+};
+use crate::storage::bitcask::log_pointer::LogPointer;
+
+/// Reads a value straight from its log or blob file, without holding it in memory.
+///
+/// The checksum is checked once the last byte is read, a mismatch fails that read. The file
+/// is opened up front, so the value stays readable when a merge or a blob collection removes
+/// the file in the meantime.
+pub struct ValueReader {
+    reader: Take<Box<dyn Read + Send>>,
+    len: u64,
+    hasher: crc32fast::Hasher,
+    checksum: u32,
+}
+
+impl ValueReader {
+    /// Reader over the value of the record at `log_pointer`, `None` for a removal. Fails for
+    /// a merge operand, as the value has to be folded first.
+    pub(crate) fn open(path: &PathBuf, log_pointer: &LogPointer) -> KVResult<Option<ValueReader>> {
+        let mut file = File::open(get_log_file_dir(log_pointer.gen, path))?;
+        file.seek(SeekFrom::Start(log_pointer.pos))?;
+        let mut reader = BufReader::new(file);
+
+        let mut head = vec![0; RECORD_HEADER_LEN];
+        reader.read_exact(&mut head)?;
+        let head_len = record_head_len(&head, log_pointer.len).map_err(|defect| {
+            let (gen, pos) = (log_pointer.gen, log_pointer.pos);
+            let message = format!("record at gen {} pos {} is corrupt ({})", gen, pos, defect);
+            Error::new(ErrorKind::InvalidData, message)
+        })?;
+        head.resize(head_len, 0);
+        reader.read_exact(&mut head[RECORD_HEADER_LEN..])?;
+
+        if let Some(len) = head_value_len(&head) {
+            let reader: Box<dyn Read + Send> = Box::new(reader);
+            return Ok(Some(ValueReader {
+                reader: reader.take(len),
+                len,
+                hasher: record_hasher(&head),
+                checksum: record_checksum(&head),
+            }));
+        }
+
+        match Command::from(head.as_slice()) {
+            Command::SetBlob { key: _, blob } => {
+                let mut file = File::open(get_blob_file_dir(blob.file, path))?;
+                file.seek(SeekFrom::Start(blob.offset))?;
+                let reader: Box<dyn Read + Send> = Box::new(BufReader::new(file));
+                return Ok(Some(ValueReader {
+                    reader: reader.take(blob.len),
+                    len: blob.len,
+                    hasher: crc32fast::Hasher::new(),
+                    checksum: blob.checksum,
+                }));
+            }
+            Command::Increment { key: _, delta: _ } | Command::Merge { key: _, operand: _ } => {
+                let message = "value is folded from merge operands, read it with get";
+                return Err(Error::new(ErrorKind::InvalidInput, message).into());
+            }
+            Command::Set { key: _, value: _ }
+            | Command::Remove { key: _ }
+            | Command::Batch { count: _ } => return Ok(None),
+        }
+    }
+
+    /// Reader over `value`, folded from merge operands in memory.
+    pub(crate) fn folded(value: Vec<u8>) -> ValueReader {
+        let len = value.len() as u64;
+        let checksum = crc32fast::hash(&value);
+        let reader: Box<dyn Read + Send> = Box::new(Cursor::new(value));
+
+        return ValueReader {
+            reader: reader.take(len),
+            len,
+            hasher: crc32fast::Hasher::new(),
+            checksum,
+        };
+    }
+
+    /// Length of the whole value.
+    pub fn len(&self) -> u64 {
+        return self.len;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len == 0;
+    }
+}
+
+impl Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.hasher.update(&buf[..read]);
+
+        if read == 0 && !buf.is_empty() {
+            if self.reader.limit() > 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "value cut short"));
+            }
+            if self.hasher.clone().finalize() != self.checksum {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "value fails its checksum",
+                ));
+            }
+        }
+
+        return Ok(read);
+    }
+}
+
+/// Copies exactly `len` bytes from `reader` to `writer` in chunks, feeding them to `hasher`.
+/// Fails when `reader` ends first.
+pub(crate) fn copy_value(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    len: u64,
+    hasher: &mut crc32fast::Hasher,
+) -> KVResult<()> {
+    let mut buffer = vec![0; (STREAM_CHUNK_LEN as u64).min(len) as usize];
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let chunk_len = (buffer.len() as u64).min(remaining) as usize;
+        let read = match reader.read(&mut buffer[..chunk_len]) {
+            Ok(0) => {
+                let message = format!("value ended {} bytes early", remaining);
+                return Err(Error::new(ErrorKind::UnexpectedEof, message).into());
+            }
+            Ok(read) => read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        hasher.update(&buffer[..read]);
+        writer.write_all(&buffer[..read])?;
+        remaining -= read as u64;
+    }
+
+    return Ok(());
+}
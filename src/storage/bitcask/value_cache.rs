@@ -0,0 +1,129 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::{BTreeMap, HashMap};
+
+/// LRU cache of decoded values bounded by the total bytes of keys and values it holds.
+pub struct ValueCache {
+    capacity: u64,
+    size: u64,
+    tick: u64,
+    entries: HashMap<Vec<u8>, CacheEntry>,
+    recency: BTreeMap<u64, Vec<u8>>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct CacheEntry {
+    value: Vec<u8>,
+    tick: u64,
+}
+
+impl ValueCache {
+    pub fn new(capacity: u64) -> ValueCache {
+        ValueCache {
+            capacity,
+            size: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                self.hits += 1;
+                let key = self.recency.remove(&entry.tick).unwrap();
+                self.recency.insert(tick, key);
+                entry.tick = tick;
+                return Some(entry.value.clone());
+            }
+            None => {
+                self.misses += 1;
+                return None;
+            }
+        }
+    }
+
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.invalidate(&key);
+
+        let entry_size = entry_size(&key, &value);
+        if entry_size > self.capacity {
+            return;
+        }
+
+        while self.size + entry_size > self.capacity {
+            self.evict_least_recent();
+        }
+
+        self.tick += 1;
+        self.size += entry_size;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value,
+                tick: self.tick,
+            },
+        );
+    }
+
+    pub fn invalidate(&mut self, key: &[u8]) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.tick);
+            self.size -= entry_size(key, &entry.value);
+        }
+    }
+
+    fn evict_least_recent(&mut self) {
+        let oldest_tick = match self.recency.keys().next() {
+            Some(tick) => *tick,
+            None => return,
+        };
+
+        let key = self.recency.remove(&oldest_tick).unwrap();
+        let entry = self.entries.remove(&key).unwrap();
+        self.size -= entry_size(&key, &entry.value);
+    }
+}
+
+fn entry_size(key: &[u8], value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
+
+#[test]
+fn value_cache_evict_least_recently_used() {
+    let mut cache = ValueCache::new(12);
+
+    cache.insert(b"k1".to_vec(), b"v1".to_vec());
+    cache.insert(b"k2".to_vec(), b"v2".to_vec());
+    cache.insert(b"k3".to_vec(), b"v3".to_vec());
+    cache.get(b"k1");
+    cache.insert(b"k4".to_vec(), b"v4".to_vec());
+
+    assert_eq!(Some(b"v1".to_vec()), cache.get(b"k1"));
+    assert_eq!(None, cache.get(b"k2"));
+    assert_eq!(Some(b"v4".to_vec()), cache.get(b"k4"));
+    assert_eq!(12, cache.size());
+}
+
+#[test]
+fn value_cache_skip_oversized_value() {
+    let mut cache = ValueCache::new(4);
+
+    cache.insert(b"k1".to_vec(), b"too big".to_vec());
+
+    assert_eq!(None, cache.get(b"k1"));
+    assert_eq!(0, cache.size());
+    assert_eq!(1, cache.misses);
+}
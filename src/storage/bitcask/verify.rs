@@ -0,0 +1,191 @@
+#![allow(clippy::needless_return)]
+
+use std::collections::HashMap;
+use std::fs::read;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::error::KVResult;
+use crate::storage::bitcask::bitcask_engine::{get_log_file_dir, get_sorted_gen_list};
+use crate::storage::bitcask::command::{
+    check_record, next_record_start, record_namespace, Command,
+};
+
+use crate::storage::bitcask::namespace::NamespaceRegistry;
+
+pub use crate::storage::bitcask::command::RecordDefect;
+
+/// Bytes of a generation file that do not hold a valid record.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CorruptRange {
+    pub gen: u64,
+    pub offset: u64,
+    pub len: u64,
+    #[serde(serialize_with = "serialize_defect")]
+    pub defect: RecordDefect,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    pub generations: usize,
+    pub records: u64,
+    /// Records the value of a key is read from, its latest set and the merge operands after it,
+    /// in a namespace not dropped.
+    pub live_records: u64,
+    /// Overwritten sets and removals, reclaimed by the next merge.
+    pub dead_records: u64,
+    pub corrupt_ranges: Vec<CorruptRange>,
+}
+
+impl VerifyReport {
+    pub fn is_healthy(&self) -> bool {
+        self.corrupt_ranges.is_empty()
+    }
+}
+
+/// Checks every record of every generation file in `path` without opening the store or
+/// changing any file.
+pub fn verify(path: &PathBuf) -> KVResult<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let registry = NamespaceRegistry::load(path)?;
+    // Number of live records of each key of each namespace, replayed in generation and file
+    // order.
+    let mut live: HashMap<(u32, Vec<u8>), u64> = HashMap::new();
+
+    for gen in get_sorted_gen_list(path)? {
+        let buffer = read(get_log_file_dir(gen, path))?;
+        report.generations += 1;
+
+        let (records, mut corrupt_ranges) = scan_records(gen, &buffer);
+        for (pos, len) in records {
+            report.records += 1;
+            let record = &buffer[pos..pos + len];
+            let namespace = record_namespace(record);
+            match Command::from(record) {
+                Command::Set { key, value: _ } | Command::SetBlob { key, blob: _ } => {
+                    live.insert((namespace, key), 1);
+                }
+                Command::Increment { key, delta: _ } | Command::Merge { key, operand: _ } => {
+                    *live.entry((namespace, key)).or_insert(0) += 1;
+                }
+                Command::Remove { key } => {
+                    live.insert((namespace, key), 0);
+                }
+                Command::Batch { count: _ } => {}
+            }
+        }
+        report.corrupt_ranges.append(&mut corrupt_ranges);
+    }
+
+    report.live_records = live
+        .iter()
+        .filter(|((namespace, _), _)| registry.contains(*namespace))
+        .map(|(_, records)| records)
+        .sum();
+    report.dead_records = report.records - report.live_records;
+
+    return Ok(report);
+}
+
+/// Offset and length of every valid record in `buffer`, holding the content of generation
+/// `gen`, and every corrupt range between them. A corrupt range ends where the next valid
+/// record starts.
+pub(crate) fn scan_records(gen: u64, buffer: &[u8]) -> (Vec<(usize, usize)>, Vec<CorruptRange>) {
+    let mut records = Vec::new();
+    let mut corrupt_ranges = Vec::new();
+    let mut pos = 0;
+
+    while pos < buffer.len() {
+        match check_record(&buffer[pos..]) {
+            Ok(len) => {
+                records.push((pos, len));
+                pos += len;
+            }
+            Err(defect) => {
+                let len = match next_record_start(&buffer[pos + 1..]) {
+                    Some(next_start) => next_start + 1,
+                    None => buffer.len() - pos,
+                };
+                corrupt_ranges.push(CorruptRange {
+                    gen,
+                    offset: pos as u64,
+                    len: len as u64,
+                    defect,
+                });
+                pos += len;
+            }
+        }
+    }
+
+    return (records, corrupt_ranges);
+}
+
+fn serialize_defect<S: serde::Serializer>(
+    defect: &RecordDefect,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    return serializer.collect_str(defect);
+}
+
+#[test]
+fn verify_reports_corrupt_ranges() {
+    use crate::storage::bitcask::bitcask_engine::Bitcask;
+    use crate::storage::bitcask::command::record_len;
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let path = temp_dir.path().to_path_buf();
+    let store_engine = Bitcask::open(&path).unwrap();
+    store_engine.set(b"a".to_vec(), b"1".to_vec()).unwrap();
+    store_engine.set(b"a".to_vec(), b"2".to_vec()).unwrap();
+    store_engine.set(b"b".to_vec(), b"3".to_vec()).unwrap();
+    store_engine.set(b"c".to_vec(), b"4".to_vec()).unwrap();
+    store_engine.remove(b"c".to_vec()).unwrap();
+    drop(store_engine);
+
+    let report = verify(&path).unwrap();
+    assert!(report.is_healthy());
+    assert_eq!(
+        (5, 2, 3),
+        (report.records, report.live_records, report.dead_records)
+    );
+
+    let gen = *get_sorted_gen_list(&path).unwrap().last().unwrap();
+    let log_path = get_log_file_dir(gen, &path);
+    let buffer = read(&log_path).unwrap();
+    let file_len = buffer.len() as u64;
+    let first_len = record_len(&buffer).unwrap() as u64;
+    // Overwrite the last byte of the first record and cut the last record short.
+    let mut file = OpenOptions::new().write(true).open(&log_path).unwrap();
+    file.seek(SeekFrom::Start(first_len - 1)).unwrap();
+    file.write_all(b"x").unwrap();
+    file.set_len(file_len - 1).unwrap();
+    drop(file);
+
+    let report = verify(&path).unwrap();
+    assert!(!report.is_healthy());
+    let remove_len = Command::Remove { key: b"c".to_vec() }.parse().len() as u64;
+    assert_eq!(
+        vec![
+            CorruptRange {
+                gen,
+                offset: 0,
+                len: first_len,
+                defect: RecordDefect::Checksum,
+            },
+            CorruptRange {
+                gen,
+                offset: file_len - remove_len,
+                len: remove_len - 1,
+                defect: RecordDefect::Truncated,
+            },
+        ],
+        report.corrupt_ranges
+    );
+    assert_eq!(
+        (3, 3, 0),
+        (report.records, report.live_records, report.dead_records)
+    );
+}
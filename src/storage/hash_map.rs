@@ -7,6 +7,7 @@ pub struct HashMapStore {
 }
 
 impl HashMapStore {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         HashMapStore {
             map: HashMap::new(),
@@ -29,6 +30,7 @@ impl KeyValueStore for HashMapStore {
 }
 
 #[test]
+#[allow(clippy::needless_borrow)]
 fn store_engine_write_read() {
     let mut store_engine = HashMapStore::new();
     let key = "key1".as_bytes();
@@ -41,6 +43,7 @@ fn store_engine_write_read() {
 }
 
 #[test]
+#[allow(clippy::needless_borrow)]
 fn store_engine_get_non_existed_value() {
     let store_engine = HashMapStore::new();
     let non_existed_key = "key1".as_bytes();
@@ -51,6 +54,7 @@ fn store_engine_get_non_existed_value() {
 }
 
 #[test]
+#[allow(clippy::needless_borrow)]
 fn store_engine_replace_write() {
     let mut store_engine = HashMapStore::new();
     let key = "key1".as_bytes();
@@ -67,6 +71,7 @@ fn store_engine_replace_write() {
 }
 
 #[test]
+#[allow(clippy::needless_borrow)]
 fn store_engine_remove() {
     let mut store_engine = HashMapStore::new();
     let key = "key1".as_bytes();
@@ -1,3 +1,4 @@
+#[allow(clippy::identity_op)]
 pub fn u64_to_u8_array(x: u64) -> [u8; 8] {
     let b7 = ((x >> 56) & 0xff) as u8;
     let b6 = ((x >> 48) & 0xff) as u8;
@@ -11,6 +12,7 @@ pub fn u64_to_u8_array(x: u64) -> [u8; 8] {
     [b0, b1, b2, b3, b4, b5, b6, b7]
 }
 
+#[allow(clippy::identity_op, clippy::useless_conversion)]
 pub fn u8_array_to_u64(data: &[u8; 8]) -> u64 {
     (((data[0] as u64) << 0)
         + ((data[1] as u64) << 8)
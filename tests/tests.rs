@@ -34,13 +34,13 @@ fn cli_get_version() {
 fn cli_invalid_get_command() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
-        .args(&[Constants::SUBCOMMAND_GET])
+        .args([Constants::SUBCOMMAND_GET])
         .assert()
         .failure();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
-        .args(&[Constants::SUBCOMMAND_GET, "extra", "field"])
+        .args([Constants::SUBCOMMAND_GET, "extra", "field"])
         .assert()
         .failure();
 }
@@ -49,19 +49,19 @@ fn cli_invalid_get_command() {
 fn cli_invalid_set_command() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
-        .args(&[Constants::SUBCOMMAND_SET])
+        .args([Constants::SUBCOMMAND_SET])
         .assert()
         .failure();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
-        .args(&[Constants::SUBCOMMAND_SET, "missing_field"])
+        .args([Constants::SUBCOMMAND_SET, "missing_field"])
         .assert()
         .failure();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
-        .args(&[Constants::SUBCOMMAND_SET, "extra", "extra", "field"])
+        .args([Constants::SUBCOMMAND_SET, "extra", "extra", "field"])
         .assert()
         .failure();
 }
@@ -70,13 +70,13 @@ fn cli_invalid_set_command() {
 fn cli_invalid_rm_command() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
-        .args(&[Constants::SUBCOMMAND_REMOVE])
+        .args([Constants::SUBCOMMAND_REMOVE])
         .assert()
         .failure();
 
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
-        .args(&[Constants::SUBCOMMAND_REMOVE, "extra", "field"])
+        .args([Constants::SUBCOMMAND_REMOVE, "extra", "field"])
         .assert()
         .failure();
 }
@@ -85,7 +85,7 @@ fn cli_invalid_rm_command() {
 fn cli_invalid_subcommand() {
     Command::cargo_bin(env!("CARGO_PKG_NAME"))
         .unwrap()
-        .args(&["unknown", "subcommand"])
+        .args(["unknown", "subcommand"])
         .assert()
         .failure();
 }
@@ -117,3 +117,279 @@ fn get_non_existent_value() {}
 #[test]
 #[ignore]
 fn remove_key() {}
+
+#[test]
+fn cli_stats_command() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, temp_dir.path())
+        .args([Constants::SUBCOMMAND_SET, "key", "value"])
+        .assert()
+        .success();
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, temp_dir.path())
+        .args([Constants::SUBCOMMAND_STATS])
+        .assert()
+        .success();
+
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, temp_dir.path())
+        .args([Constants::SUBCOMMAND_STATS, "--json"])
+        .ok()
+        .unwrap()
+        .stdout;
+    let stats: serde_json::Value = serde_json::from_slice(&output).unwrap();
+
+    assert_eq!(1, stats["key_count"]);
+    assert!(stats["generations"].is_array());
+}
+
+#[test]
+fn cli_watch_command() {
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let key = "watched_key".to_string();
+    let mut watcher = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, temp_dir.path())
+        .args([Constants::SUBCOMMAND_WATCH, &key])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let (sender, receiver) = channel();
+    let stdout = watcher.stdout.take().unwrap();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if sender.send(line.unwrap()).is_err() {
+                return;
+            }
+        }
+    });
+    std::thread::sleep(Duration::from_millis(500));
+
+    for (subcommand, args) in [
+        (Constants::SUBCOMMAND_SET, vec![key.as_str(), "first"]),
+        (Constants::SUBCOMMAND_SET, vec!["other_key", "ignored"]),
+        (Constants::SUBCOMMAND_SET, vec![key.as_str(), "second"]),
+        (Constants::SUBCOMMAND_REMOVE, vec![key.as_str()]),
+    ] {
+        Command::cargo_bin(env!("CARGO_PKG_NAME"))
+            .unwrap()
+            .env(Constants::STORE_DIR_ENV_VAR, temp_dir.path())
+            .arg(subcommand)
+            .args(args)
+            .assert()
+            .success();
+    }
+
+    let lines: Vec<String> = (0..3)
+        .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap())
+        .collect();
+    watcher.kill().unwrap();
+    let _ = watcher.wait();
+
+    assert_eq!(
+        vec!["first", "second", Constants::MISSING_KEY_MESSAGE],
+        lines
+    );
+}
+
+#[test]
+fn cli_backup_command() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let backup_dir = temp_dir.path().join("backup");
+    let archive = temp_dir.path().join("backup.kvs");
+    let store_dir = temp_dir.path().join("store");
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, &store_dir)
+        .args([Constants::SUBCOMMAND_SET, "backup_key", "value"])
+        .assert()
+        .success();
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, &store_dir)
+        .args([Constants::SUBCOMMAND_BACKUP, backup_dir.to_str().unwrap()])
+        .assert()
+        .success();
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, &store_dir)
+        .args([
+            Constants::SUBCOMMAND_BACKUP,
+            archive.to_str().unwrap(),
+            "--archive",
+        ])
+        .assert()
+        .success();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, &store_dir)
+        .args([
+            Constants::SUBCOMMAND_BACKUP,
+            temp_dir.path().join("incremental").to_str().unwrap(),
+            "--incremental-from",
+            backup_dir.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    assert!(std::fs::read_dir(&backup_dir).unwrap().count() > 0);
+    assert!(archive.is_file());
+    assert!(temp_dir.path().join("incremental/MANIFEST.json").is_file());
+
+    // The store in use already holds log files.
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, &store_dir)
+        .args([Constants::SUBCOMMAND_RESTORE, archive.to_str().unwrap()])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn cli_export_import_command() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let input = temp_dir.path().join("import.csv");
+    std::fs::write(&input, "key,value,encoding\nimported_key,imported_value,\n").unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, temp_dir.path())
+        .args([
+            Constants::SUBCOMMAND_IMPORT,
+            "--format",
+            "csv",
+            input.to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .env(Constants::STORE_DIR_ENV_VAR, temp_dir.path())
+        .args([Constants::SUBCOMMAND_EXPORT])
+        .ok()
+        .unwrap()
+        .stdout;
+    let output = String::from_utf8(output).unwrap();
+
+    assert!(output
+        .lines()
+        .any(|line| line == "{\"key\":\"imported_key\",\"value\":\"imported_value\"}"));
+}
+
+#[test]
+fn cli_verify_command() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store = kvs::storage::bitcask::bitcask_engine::Bitcask::open(&temp_dir.path().into());
+    store
+        .unwrap()
+        .set(b"verify_key".to_vec(), b"value".to_vec())
+        .unwrap();
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args([
+            Constants::SUBCOMMAND_VERIFY,
+            temp_dir.path().to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+
+    std::fs::write(temp_dir.path().join("1000.log"), [0xff; 40]).unwrap();
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args([
+            Constants::SUBCOMMAND_VERIFY,
+            temp_dir.path().to_str().unwrap(),
+        ])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn cli_repair_command() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store = kvs::storage::bitcask::bitcask_engine::Bitcask::open(&temp_dir.path().into());
+    store
+        .unwrap()
+        .set(b"repair_key".to_vec(), b"value".to_vec())
+        .unwrap();
+    std::fs::write(temp_dir.path().join("1000.log"), [0xff; 40]).unwrap();
+
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args([
+            Constants::SUBCOMMAND_REPAIR,
+            temp_dir.path().to_str().unwrap(),
+        ])
+        .ok()
+        .unwrap()
+        .stdout;
+    let output = String::from_utf8(output).unwrap();
+    assert!(output.contains("lost gen=1000 offset=0 len=40 defect=magic"));
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args([
+            Constants::SUBCOMMAND_VERIFY,
+            temp_dir.path().to_str().unwrap(),
+        ])
+        .assert()
+        .success();
+}
+
+#[test]
+fn cli_dump_command() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let store =
+        kvs::storage::bitcask::bitcask_engine::Bitcask::open(&temp_dir.path().into()).unwrap();
+    store.set(b"dump_key".to_vec(), b"value".to_vec()).unwrap();
+    store.set(b"other_key".to_vec(), b"value".to_vec()).unwrap();
+    drop(store);
+    let gen = std::fs::read_dir(temp_dir.path())
+        .unwrap()
+        .filter_map(|entry| {
+            let file_name = entry.unwrap().file_name().into_string().unwrap();
+            file_name.strip_suffix(".log")?.parse::<u64>().ok()
+        })
+        .max()
+        .unwrap()
+        .to_string();
+
+    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args([
+            Constants::SUBCOMMAND_DUMP,
+            gen.as_str(),
+            temp_dir.path().to_str().unwrap(),
+            "--key",
+            "dump_key",
+        ])
+        .ok()
+        .unwrap()
+        .stdout;
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(1, output.lines().count());
+    assert!(output.starts_with("offset=0 "));
+    assert!(output.contains(" type=set "));
+    assert!(output.contains(" key=\"dump_key\" value_len=5 value=\"value\""));
+
+    Command::cargo_bin(env!("CARGO_PKG_NAME"))
+        .unwrap()
+        .args([Constants::SUBCOMMAND_DUMP, "not_a_gen"])
+        .assert()
+        .failure();
+}